@@ -1,16 +1,31 @@
-use super::{Command, NameSpace};
-use crate::job::{SharedJobs, Status};
-use crate::parse::{Block as ParseBlk, SpecialStr};
+use super::command::{exited, pipe, read, subshell, trace_header, wait_code, with_stdin};
+use super::command::{Args, Stage, SubshellExit};
+use super::{coproc, fork, glob, Command, Jump, NameSpace};
+use crate::job::{format_time, CpuTimes, SharedJobs};
+use crate::parse::{Block as ParseBlk, CaseEnd, Parsed, SpecialStr};
+use std::fmt;
+use std::time::Instant;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Block {
     Single(Command),
-    Multi(Vec<Self>),
-    If(SpecialStr, Box<Self>, Option<Box<Self>>),
-    Case(SpecialStr, Vec<(Vec<SpecialStr>, Self)>),
-    For(String, SpecialStr, Box<Self>),
-    While(SpecialStr, Box<Self>),
-    Proc(String, Box<Self>),
+    Multi(Vec<(usize, Self)>),
+    If(Box<Self>, Box<Self>, Option<Box<Self>>),
+    Case(SpecialStr, Vec<(Vec<SpecialStr>, Self, CaseEnd)>),
+    For(String, Args, Box<Self>),
+    Select(String, Args, Box<Self>),
+    While(Box<Self>, Box<Self>),
+    // The source of the body is kept for `declare -f`.
+    Proc(String, Box<Self>, String),
+    Time(bool, Box<Self>),
+    // The source is kept to run it in a child process.
+    Coproc(String, Box<Self>, String),
+    // The source is kept for `jobs`.
+    Background(Box<Self>, String),
+    And(Box<Self>, Box<Self>),
+    Or(Box<Self>, Box<Self>),
+    // The source of the left side is kept to run it in a child process.
+    Pipe(Box<Self>, Box<Self>, String),
     Break,
     Continue,
 }
@@ -22,13 +37,154 @@ enum State {
     Continued,
 }
 
+#[derive(Debug)]
+pub struct LineError {
+    line: usize,
+    start: usize,
+    source: anyhow::Error,
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == self.start {
+            write!(f, "line {}: {}", self.line, self.source)
+        } else {
+            write!(
+                f,
+                "line {} (in the block from line {}): {}",
+                self.line, self.start, self.source
+            )
+        }
+    }
+}
+
+impl std::error::Error for LineError {}
+
+// Errors keep the innermost failing line, while the start is moved out to
+// each enclosing statement. `exit` from a subshell isn't an error there.
+pub fn located(e: anyhow::Error, line: usize) -> anyhow::Error {
+    if e.is::<SubshellExit>() {
+        return e;
+    }
+    match e.downcast::<LineError>() {
+        Ok(mut e) => {
+            e.start = line;
+            e.into()
+        }
+        Err(e) => LineError {
+            line,
+            start: line,
+            source: e,
+        }
+        .into(),
+    }
+}
+
+/// Parses statements to run, with the first line counted as line 1.
+///
+/// ```
+/// use tish::eval::Block;
+///
+/// let block = "if true { echo yes } else { echo no }".parse::<Block>().unwrap();
+/// match block {
+///     Block::If(cond, _, second) => {
+///         assert!(matches!(*cond, Block::Single(_)));
+///         assert!(second.is_some());
+///     }
+///     block => panic!("not an if: {:?}", block),
+/// }
+/// ```
+impl std::str::FromStr for Block {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Self::parse_at(s, 1)
+    }
+}
+
+impl Block {
+    // The lines of the statements in braces, in the order `set_lines` takes
+    // them. A block parsed again from its source has the same shape, but its
+    // lines counted in that source, so they are put back with these.
+    pub fn lines(&self) -> Vec<usize> {
+        let mut lines = Vec::new();
+        self.clone().each_line(&mut |line| lines.push(*line));
+        lines
+    }
+
+    pub fn set_lines(&mut self, lines: &[usize]) {
+        let mut lines = lines.iter();
+        self.each_line(&mut |line| {
+            if let Some(next) = lines.next() {
+                *line = *next;
+            }
+        });
+    }
+
+    fn each_line(&mut self, f: &mut dyn FnMut(&mut usize)) {
+        match self {
+            Self::Multi(lines) => {
+                for (line, block) in lines.iter_mut() {
+                    f(line);
+                    block.each_line(f);
+                }
+            }
+            Self::If(cond, first, second) => {
+                cond.each_line(f);
+                first.each_line(f);
+                if let Some(second) = second {
+                    second.each_line(f);
+                }
+            }
+            Self::Case(_, arms) => {
+                for (_, block, _) in arms.iter_mut() {
+                    block.each_line(f);
+                }
+            }
+            Self::While(lhs, rhs)
+            | Self::And(lhs, rhs)
+            | Self::Or(lhs, rhs)
+            | Self::Pipe(lhs, rhs, _) => {
+                lhs.each_line(f);
+                rhs.each_line(f);
+            }
+            Self::For(_, _, block)
+            | Self::Select(_, _, block)
+            | Self::Proc(_, block, _)
+            | Self::Time(_, block)
+            | Self::Coproc(_, block, _)
+            | Self::Background(block, _) => block.each_line(f),
+            Self::Single(_) | Self::Break | Self::Continue => (),
+        }
+    }
+
+    // Parses statements with the first line counted as `line`, for a source
+    // taken out of a larger one.
+    pub fn parse_at(s: &str, line: usize) -> anyhow::Result<Self> {
+        let mut stmts = match crate::parse::parse_line(s, line)? {
+            Parsed::Complete(stmts) => stmts,
+            Parsed::Yet => anyhow::bail!("Unexpected end of input."),
+        };
+        Ok(if stmts.len() == 1 {
+            Self::from(stmts.remove(0).1)
+        } else {
+            Self::from(ParseBlk::Multi(stmts))
+        })
+    }
+}
+
 impl From<ParseBlk> for Block {
     fn from(block: ParseBlk) -> Self {
         match block {
-            ParseBlk::Multi(blocks) => Self::Multi(blocks.into_iter().map(Self::from).collect()),
+            ParseBlk::Multi(blocks) => Self::Multi(
+                blocks
+                    .into_iter()
+                    .map(|(line, block)| (line, Self::from(block)))
+                    .collect(),
+            ),
             ParseBlk::Single(cmd) => Self::Single(Command::from(cmd)),
             ParseBlk::If(cond, first, second) => Self::If(
-                cond,
+                Box::new(Self::from(*cond)),
                 Box::new(Self::from(*first)),
                 second.map(|sec| Box::new(Self::from(*sec))),
             ),
@@ -36,12 +192,45 @@ impl From<ParseBlk> for Block {
                 cond,
                 blocks
                     .into_iter()
-                    .map(|(pats, block)| (pats, Self::from(block)))
+                    .map(|(pats, block, end)| (pats, Self::from(block), end))
                     .collect(),
             ),
-            ParseBlk::For(c, iter, block) => Self::For(c, iter, Box::new(Self::from(*block))),
-            ParseBlk::While(cond, block) => Self::While(cond, Box::new(Self::from(*block))),
-            ParseBlk::Proc(name, block) => Self::Proc(name, Box::new(Self::from(*block))),
+            ParseBlk::For(c, iter, block) => {
+                Self::For(c, Args::from(iter), Box::new(Self::from(*block)))
+            }
+            ParseBlk::Select(c, items, block) => {
+                Self::Select(c, Args::from(items), Box::new(Self::from(*block)))
+            }
+            ParseBlk::While(cond, block) => {
+                Self::While(Box::new(Self::from(*cond)), Box::new(Self::from(*block)))
+            }
+            ParseBlk::Proc(name, block) => {
+                let source = block.source();
+                Self::Proc(name, Box::new(Self::from(*block)), source)
+            }
+            ParseBlk::Time(posix, block) => Self::Time(posix, Box::new(Self::from(*block))),
+            ParseBlk::Coproc(name, block) => {
+                let source = block.source();
+                Self::Coproc(name, Box::new(Self::from(*block)), source)
+            }
+            ParseBlk::Background(block) => {
+                let source = block.source();
+                Self::Background(Box::new(Self::from(*block)), source)
+            }
+            ParseBlk::And(lhs, rhs) => {
+                Self::And(Box::new(Self::from(*lhs)), Box::new(Self::from(*rhs)))
+            }
+            ParseBlk::Or(lhs, rhs) => {
+                Self::Or(Box::new(Self::from(*lhs)), Box::new(Self::from(*rhs)))
+            }
+            ParseBlk::Pipe(lhs, rhs) => {
+                let source = lhs.source();
+                Self::Pipe(
+                    Box::new(Self::from(*lhs)),
+                    Box::new(Self::from(*rhs)),
+                    source,
+                )
+            }
             ParseBlk::Break => Self::Break,
             ParseBlk::Continue => Self::Continue,
         }
@@ -49,57 +238,67 @@ impl From<ParseBlk> for Block {
 }
 
 impl Block {
+    // Returns the status the body has left, which is taken before the scope of
+    // the function goes away, as `$status` may first be set in it.
     pub fn eval_with_args(
         &self,
         name: &str,
         args: Vec<String>,
         jobs: &SharedJobs,
         ns: &mut NameSpace,
-    ) -> anyhow::Result<()> {
-        ns.mark();
-        ns.set_args(name, args);
-        self.eval(jobs, ns)?;
-        ns.drop();
-        Ok(())
+    ) -> anyhow::Result<i32> {
+        ns.with_func(name, args, |ns| {
+            self.eval(jobs, ns)?;
+            Ok(ns
+                .get_var("status")
+                .and_then(|code| code.parse().ok())
+                .unwrap_or(0))
+        })
     }
 
+    // `break` and `continue` escaping from a function or a sourced file are
+    // passed to the loop around the command through the namespace.
     pub fn eval(&self, jobs: &SharedJobs, ns: &mut NameSpace) -> anyhow::Result<()> {
-        self.eval_inner(jobs, ns)?;
+        match self.eval_inner(jobs, ns)? {
+            State::Normal => (),
+            State::Breaked => ns.set_jump(Jump::Break),
+            State::Continued => ns.set_jump(Jump::Continue),
+        }
         Ok(())
     }
 
+    // A braced condition runs in the enclosing scope like a body, so
+    // `while { read line } { ... }` sees `$line`, and succeeds with the status
+    // 0 it leaves.
+    fn test(&self, jobs: &SharedJobs, ns: &mut NameSpace) -> anyhow::Result<bool> {
+        match self {
+            Self::Single(cmd) => cmd.test(jobs, ns),
+            cond => {
+                ns.push_var("status", "0");
+                cond.eval_inner(jobs, ns)?;
+                Ok(ns.get_var("status").is_none_or(|code| code == "0"))
+            }
+        }
+    }
+
     fn eval_inner(&self, jobs: &SharedJobs, ns: &mut NameSpace) -> anyhow::Result<State> {
         match self {
             Self::Single(cmd) => {
-                cmd.eval(jobs, ns)?;
-                let stat = jobs.wait_fg()?;
-                if let Some(Status::Exited(code)) = stat {
+                if let Some(code) = cmd.eval(jobs, ns)? {
                     ns.push_var("status", code.to_string());
                 }
-                Ok(State::Normal)
-            }
-            Self::Multi(lines) => {
-                ns.mark();
-                for line in lines.iter() {
-                    let state = line.eval_inner(jobs, ns)?;
-                    match state {
-                        State::Normal => continue,
-                        State::Breaked | State::Continued => {
-                            ns.drop();
-                            return Ok(state);
-                        }
-                    }
-                }
-                ns.drop();
-                Ok(State::Normal)
+                Ok(match ns.take_jump() {
+                    Some(Jump::Break) => State::Breaked,
+                    Some(Jump::Continue) => State::Continued,
+                    None => State::Normal,
+                })
             }
+            // Braces group commands in the current shell without a scope of
+            // their own, so variables set in them, or in the bodies of `if`,
+            // `case` and loops, persist after them. Only functions have one.
+            Self::Multi(lines) => eval_lines(lines, jobs, ns),
             Self::If(cond, first, second) => {
-                let cond = matches!(
-                    cond.eval(jobs)?.to_lowercase().as_str(),
-                    "1" | "y" | "yes" | "true"
-                );
-
-                let state = if cond {
+                let state = if cond.test(jobs, ns)? {
                     first.eval_inner(jobs, ns)?
                 } else if let Some(sec) = second {
                     sec.eval_inner(jobs, ns)?
@@ -110,37 +309,92 @@ impl Block {
                 Ok(state)
             }
             Self::Case(cond, blocks) => {
-                let cond = cond.eval(jobs)?;
-                for (pats, block) in blocks.iter() {
-                    let pats = pats
-                        .iter()
-                        .map(|pat| pat.eval(jobs))
-                        .collect::<Result<Vec<_>, _>>()?;
-                    if pats.into_iter().any(|pat| pat == cond) {
-                        return Ok(block.eval_inner(jobs, ns)?);
+                let cond = cond.eval(jobs, ns)?;
+                trace_header("case", std::slice::from_ref(&cond), "in", jobs, ns);
+                let mut fall = false;
+                for (pats, block, end) in blocks.iter() {
+                    if !fall && !case_matches(pats, &cond, jobs, ns)? {
+                        continue;
+                    }
+
+                    let state = block.eval_inner(jobs, ns)?;
+                    match (state, end) {
+                        (State::Normal, CaseEnd::FallThrough) => fall = true,
+                        (State::Normal, CaseEnd::Continue) => fall = false,
+                        _ => return Ok(state),
                     }
                 }
                 Ok(State::Normal)
             }
             Self::For(c, iter, block) => {
-                ns.mark();
-                for val in iter.eval(jobs)?.split('\n') {
-                    ns.push_var(c, val);
-                    let state = block.eval_inner(jobs, ns)?;
+                let items = iter.eval_lines(jobs, ns)?;
+                let head = format!("for {} in", c);
+                // The header is back at its own line after the body.
+                let line = ns.line();
+                for (count, val) in items.iter().enumerate() {
+                    ns.set_line(line);
+                    trace_header(&head, &items, "", jobs, ns);
+                    check_loop(ns, count)?;
+                    ns.check_writable(c)?;
+                    ns.push_var(c, val.clone());
+                    let state = ns.with_loop(|ns| block.eval_inner(jobs, ns))?;
+                    match state {
+                        State::Normal | State::Continued => continue,
+                        State::Breaked => break,
+                    }
+                }
+                Ok(State::Normal)
+            }
+            // The menu is shown again on an empty reply, and the loop ends with
+            // `break` or the end of the input.
+            Self::Select(c, items, block) => {
+                let items = items.eval_lines(jobs, ns)?;
+                trace_header(&format!("select {} in", c), &items, "", jobs, ns);
+                let ps3 = ns.get_var("PS3").unwrap_or_else(|| String::from("#? "));
+                let mut menu = true;
+                let mut count = 0;
+                loop {
+                    if menu {
+                        for (i, item) in items.iter().enumerate() {
+                            eprintln!("{}) {}", i + 1, item);
+                        }
+                    }
+                    if read(["-p", &ps3], ns)? != 0 {
+                        eprintln!();
+                        break;
+                    }
+                    let reply = ns.get_var("REPLY").unwrap_or_default();
+                    menu = reply.trim().is_empty();
+                    if menu {
+                        continue;
+                    }
+
+                    check_loop(ns, count)?;
+                    count += 1;
+                    let choice = reply
+                        .trim()
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|n| items.get(n.checked_sub(1)?).cloned());
+                    ns.push_var(c, choice.unwrap_or_default());
+                    let state = ns.with_loop(|ns| block.eval_inner(jobs, ns))?;
                     match state {
                         State::Normal | State::Continued => continue,
                         State::Breaked => break,
                     }
                 }
-                ns.drop();
                 Ok(State::Normal)
             }
             Self::While(cond, block) => {
-                while matches!(
-                    cond.eval(jobs)?.to_lowercase().as_str(),
-                    "1" | "y" | "yes" | "true"
-                ) {
-                    let state = block.eval_inner(jobs, ns)?;
+                let mut count = 0;
+                let line = ns.line();
+                while {
+                    ns.set_line(line);
+                    cond.test(jobs, ns)?
+                } {
+                    check_loop(ns, count)?;
+                    count += 1;
+                    let state = ns.with_loop(|ns| block.eval_inner(jobs, ns))?;
                     match state {
                         State::Normal | State::Continued => continue,
                         State::Breaked => break,
@@ -148,12 +402,200 @@ impl Block {
                 }
                 Ok(State::Normal)
             }
-            Self::Proc(name, block) => {
-                ns.push_proc(name, (**block).clone());
+            Self::Proc(name, block, source) => {
+                ns.push_proc(name, (**block).clone(), source);
+                Ok(State::Normal)
+            }
+            Self::Time(posix, block) => {
+                let start = Instant::now();
+                let before = CpuTimes::shell() + CpuTimes::children();
+                let state = block.eval_inner(jobs, ns)?;
+                let times = CpuTimes::shell() + CpuTimes::children() - before;
+                let real = start.elapsed();
+
+                if *posix {
+                    eprintln!(
+                        "real {:.2}\nuser {:.2}\nsys {:.2}",
+                        real.as_secs_f64(),
+                        times.user.as_secs_f64(),
+                        times.sys.as_secs_f64()
+                    );
+                } else {
+                    eprintln!(
+                        "\nreal\t{}\nuser\t{}\nsys\t{}",
+                        format_time(real),
+                        format_time(times.user),
+                        format_time(times.sys)
+                    );
+                }
+                Ok(state)
+            }
+            Self::Coproc(name, block, source) => {
+                coproc::start(name, block, source, jobs, ns)?;
+                Ok(State::Normal)
+            }
+            Self::Background(block, source) => {
+                let child = fork::spawn(block, source, ns, |_| ())?;
+                jobs.with(|jobs| {
+                    let (id, pid) = jobs.new_bg(child.id() as i32)?;
+                    jobs.set_label(pid, one_line(source));
+                    println!("Job %{} ({}) has started.", id, pid);
+                    Ok(())
+                })?;
+                Ok(State::Normal)
+            }
+            Self::And(lhs, rhs) | Self::Or(lhs, rhs) => {
+                let state = lhs.eval_inner(jobs, ns)?;
+                if state != State::Normal {
+                    return Ok(state);
+                }
+
+                // The status is already adjusted for `pipefail` by the pipeline.
+                let success = ns.get_var("status").is_none_or(|code| code == "0");
+                if success == matches!(self, Self::And(_, _)) {
+                    rhs.eval_inner(jobs, ns)
+                } else {
+                    Ok(State::Normal)
+                }
+            }
+            // Each side runs in a subshell, as a simple command in a pipeline
+            // does, so `gen | { read x }` and `gen | while read l { ... }`
+            // leave the variables of the shell as they were. A compound
+            // command on the left runs in a child process writing to the
+            // right side as it goes.
+            Self::Pipe(lhs, rhs, source) => {
+                let (stages, stdout) = match lhs.as_ref() {
+                    Self::Single(cmd) => cmd.spawn_output(jobs, ns)?,
+                    lhs => {
+                        let (r, w) = pipe()?;
+                        let child = fork::spawn_stage(lhs, source, ns, None, w)?;
+                        (vec![Stage::Child(child)], Some(r))
+                    }
+                };
+                let run = |ns: &mut NameSpace| {
+                    subshell(ns, |ns| {
+                        rhs.eval_inner(jobs, ns).or_else(|e| {
+                            let code = exited(e)?;
+                            ns.push_var("status", code.to_string());
+                            Ok::<_, anyhow::Error>(State::Normal)
+                        })
+                    })
+                };
+                let res = match stdout {
+                    Some(stdout) => with_stdin(stdout, || run(ns))?,
+                    None => run(ns),
+                };
+                for stage in stages {
+                    wait_code(stage)?;
+                }
+                res?;
                 Ok(State::Normal)
             }
+            Self::Break | Self::Continue if !ns.in_loop() => {
+                let name = if *self == Self::Break {
+                    "break"
+                } else {
+                    "continue"
+                };
+                ns.push_var("status", "1");
+                anyhow::bail!("{}: only meaningful in a loop", name)
+            }
             Self::Break => Ok(State::Breaked),
             Self::Continue => Ok(State::Continued),
         }
     }
 }
+
+fn eval_lines(
+    lines: &[(usize, Block)],
+    jobs: &SharedJobs,
+    ns: &mut NameSpace,
+) -> anyhow::Result<State> {
+    for (line, block) in lines.iter() {
+        ns.set_line(*line);
+        let state = block.eval_inner(jobs, ns).map_err(|e| located(e, *line))?;
+        if state != State::Normal {
+            return Ok(state);
+        }
+    }
+    Ok(State::Normal)
+}
+
+// Patterns are evaluated from the left until one matches, and quoted ones
+// are compared literally.
+fn case_matches(
+    pats: &[SpecialStr],
+    cond: &str,
+    jobs: &SharedJobs,
+    ns: &mut NameSpace,
+) -> anyhow::Result<bool> {
+    for pat in pats {
+        let value = pat.eval(jobs, ns)?;
+        let matched = if pat.is_quoted() {
+            value == cond
+        } else {
+            glob::matches(&value, cond)
+        };
+        if matched {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// Joins the lines of a source with `;` to show it in a line.
+fn one_line(source: &str) -> String {
+    source
+        .lines()
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join("; ")
+        .replace("{; ", "{ ")
+        .replace("; }", " }")
+}
+
+// Called before each iteration of a loop.
+fn check_loop(ns: &NameSpace, count: usize) -> anyhow::Result<()> {
+    fork::check_stdout();
+    match ns.opts().loop_limit {
+        Some(limit) if count >= limit => anyhow::bail!("Loop limit ({}) exceeded.", limit),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locations() {
+        let e = located(anyhow::anyhow!("failed"), 4);
+        assert_eq!(e.to_string(), "line 4: failed");
+        let e = located(located(e, 3), 2);
+        assert_eq!(e.to_string(), "line 4 (in the block from line 2): failed");
+    }
+
+    #[test]
+    fn lines() {
+        let mut block = Block::parse_at("while true {\n    a\n\n    b\n}", 3).unwrap();
+        assert_eq!(block.lines(), [4, 6]);
+        block.set_lines(&[10, 20]);
+        assert_eq!(block.lines(), [10, 20]);
+    }
+
+    #[test]
+    fn one_line_joins_lines() {
+        assert_eq!(one_line("{\n    a\n    b\n}"), "{ a; b }");
+        assert_eq!(one_line("sleep 1"), "sleep 1");
+    }
+
+    #[test]
+    fn from_str() {
+        assert!(matches!("a && b".parse::<Block>(), Ok(Block::And(..))));
+        assert!(matches!(
+            "while true { a }".parse::<Block>(),
+            Ok(Block::While(..))
+        ));
+        assert!("if true {".parse::<Block>().is_err());
+    }
+}
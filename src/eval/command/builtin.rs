@@ -1,5 +1,8 @@
+use super::external::search_path;
+use super::json::{Json, ToJson};
 use super::NameSpace;
-use crate::job::SharedJobs;
+use crate::eval::{make_temp, temp_dir, CompSpec, TempFile};
+use crate::job::{format_time, CpuTimes, SharedJobs};
 use anyhow::Context;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -20,25 +23,86 @@ impl Builtin {
         }
     }
 
-    pub fn eval(&self, jobs: &SharedJobs, ns: &mut NameSpace) -> anyhow::Result<()> {
+    // Misuse is reported with the usage, and the status 2.
+    pub fn eval(&self, jobs: &SharedJobs, ns: &mut NameSpace) -> anyhow::Result<i32> {
+        match self.eval_inner(jobs, ns) {
+            Err(e) if e.is::<UsageError>() => {
+                let usage = format!("tish: {}: usage: {}", self.kind.name(), self.kind.usage());
+                eprintln!("{}", crate::session::color::error(usage));
+                Ok(2)
+            }
+            res => res,
+        }
+    }
+
+    fn eval_inner(&self, jobs: &SharedJobs, ns: &mut NameSpace) -> anyhow::Result<i32> {
         match self.kind {
-            BuiltinKind::Empty => (),
-            BuiltinKind::Exit => exit(&self.args)?,
-            BuiltinKind::Cd => cd(&self.args)?,
-            BuiltinKind::Fg => fg(&self.args, jobs)?,
-            BuiltinKind::Jobs => println!("{:#?}", jobs.get()?),
-            BuiltinKind::Let => let_(&self.args, ns)?,
-            BuiltinKind::Export => export(&self.args, ns)?,
-            BuiltinKind::Source => source(&self.args, ns)?,
+            BuiltinKind::Empty | BuiltinKind::Colon | BuiltinKind::True => Ok(0),
+            BuiltinKind::False => Ok(1),
+            BuiltinKind::Exit => exit(&self.args, jobs, ns),
+            BuiltinKind::Cd => cd(&self.args, ns),
+            BuiltinKind::Fg => fg(&self.args, jobs),
+            BuiltinKind::Jobs => jobs_(&self.args, jobs),
+            BuiltinKind::Let => let_(&self.args, ns),
+            BuiltinKind::Export => export(&self.args, ns),
+            BuiltinKind::Source => source(&self.args, ns),
+            BuiltinKind::Read => read(&self.args, ns),
+            BuiltinKind::Mapfile => mapfile(&self.args, ns),
+            BuiltinKind::Printf => printf(&self.args, ns),
+            BuiltinKind::Set => set(&self.args, ns),
+            BuiltinKind::Type => type_(&self.args, ns),
+            BuiltinKind::Help => help(&self.args),
+            BuiltinKind::Alias => alias(&self.args, ns),
+            BuiltinKind::Unalias => unalias(&self.args, ns),
+            BuiltinKind::Fc => fc(&self.args, jobs, ns),
+            BuiltinKind::Wait => wait(&self.args, jobs),
+            BuiltinKind::Complete => complete(&self.args, ns),
+            BuiltinKind::Hash => hash(&self.args, ns),
+            BuiltinKind::Declare => declare(&self.args, ns),
+            BuiltinKind::Disown => disown(&self.args, jobs),
+            BuiltinKind::Enable => enable(&self.args, ns),
+            BuiltinKind::Builtin => builtin(&self.args, jobs, ns),
+            BuiltinKind::Umask => umask(&self.args),
+            BuiltinKind::Mktemp => mktemp(&self.args, ns),
+            BuiltinKind::Readonly => readonly(&self.args, ns),
+            BuiltinKind::Unset => unset(&self.args, ns),
+            BuiltinKind::Times => times(&self.args),
+            BuiltinKind::Caller => caller(&self.args, ns),
         }
+    }
+}
 
-        Ok(())
+// Wrong arguments to a builtin, which are reported with its usage.
+#[derive(Debug)]
+pub struct UsageError;
+
+impl std::fmt::Display for UsageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Invalid arguments.")
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+// `exit` in a subshell run in the shell process, which ends only the
+// subshell with the code.
+#[derive(Debug)]
+pub struct SubshellExit(pub i32);
+
+impl std::fmt::Display for SubshellExit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "exit {}", self.0)
     }
 }
 
+impl std::error::Error for SubshellExit {}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BuiltinKind {
     Empty,
+    Colon,
+    True,
+    False,
     Exit,
     Cd,
     Fg,
@@ -46,12 +110,37 @@ pub enum BuiltinKind {
     Let,
     Export,
     Source,
+    Read,
+    Mapfile,
+    Printf,
+    Set,
+    Type,
+    Help,
+    Alias,
+    Unalias,
+    Fc,
+    Wait,
+    Complete,
+    Hash,
+    Declare,
+    Disown,
+    Enable,
+    Builtin,
+    Umask,
+    Mktemp,
+    Readonly,
+    Unset,
+    Times,
+    Caller,
 }
 
 impl BuiltinKind {
     pub fn new<T: AsRef<str>>(name: T) -> Option<Self> {
         Some(match name.as_ref() {
             "" => Self::Empty,
+            ":" => Self::Colon,
+            "true" => Self::True,
+            "false" => Self::False,
             "exit" => Self::Exit,
             "cd" => Self::Cd,
             "fg" => Self::Fg,
@@ -59,91 +148,1489 @@ impl BuiltinKind {
             "let" => Self::Let,
             "export" => Self::Export,
             "source" => Self::Source,
+            "read" => Self::Read,
+            "mapfile" | "readarray" => Self::Mapfile,
+            "printf" => Self::Printf,
+            "set" => Self::Set,
+            "type" => Self::Type,
+            "help" => Self::Help,
+            "alias" => Self::Alias,
+            "unalias" => Self::Unalias,
+            "fc" => Self::Fc,
+            "wait" => Self::Wait,
+            "complete" => Self::Complete,
+            "hash" => Self::Hash,
+            "declare" => Self::Declare,
+            "disown" => Self::Disown,
+            "enable" => Self::Enable,
+            "builtin" => Self::Builtin,
+            "umask" => Self::Umask,
+            "mktemp" => Self::Mktemp,
+            "readonly" => Self::Readonly,
+            "unset" => Self::Unset,
+            "times" => Self::Times,
+            "caller" => Self::Caller,
             _ => return None,
         })
     }
+
+    // Every builtin with a name, in the order `help` lists them.
+    pub const ALL: [Self; 32] = [
+        Self::Colon,
+        Self::Alias,
+        Self::Builtin,
+        Self::Caller,
+        Self::Cd,
+        Self::Complete,
+        Self::Declare,
+        Self::Disown,
+        Self::Enable,
+        Self::Exit,
+        Self::Export,
+        Self::False,
+        Self::Fc,
+        Self::Fg,
+        Self::Hash,
+        Self::Help,
+        Self::Jobs,
+        Self::Let,
+        Self::Mapfile,
+        Self::Mktemp,
+        Self::Printf,
+        Self::Read,
+        Self::Readonly,
+        Self::Set,
+        Self::Source,
+        Self::Times,
+        Self::True,
+        Self::Type,
+        Self::Umask,
+        Self::Unalias,
+        Self::Unset,
+        Self::Wait,
+    ];
+
+    // A builtin which isn't disabled by `enable -n`.
+    pub fn lookup<T: AsRef<str>>(name: T, ns: &NameSpace) -> Option<Self> {
+        Self::new(name.as_ref()).filter(|_| ns.builtin_enabled(name.as_ref()))
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.doc().0
+    }
+
+    pub fn usage(&self) -> &'static str {
+        self.doc().1
+    }
+
+    pub fn summary(&self) -> &'static str {
+        self.doc().2
+    }
+
+    fn doc(&self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            Self::Empty => ("", "", ""),
+            Self::Colon => (":", ": [args...]", "Do nothing but expand the arguments."),
+            Self::True => ("true", "true", "Succeed with the status 0."),
+            Self::False => ("false", "false", "Fail with the status 1."),
+            Self::Exit => ("exit", "exit [-f] [code]", "Exit the shell with the code."),
+            Self::Cd => ("cd", "cd [dir]", "Change the current directory."),
+            Self::Fg => ("fg", "fg [%job | pid]", "Move a job to the foreground."),
+            Self::Jobs => ("jobs", "jobs [-p | --json] [%job...]", "List the jobs."),
+            Self::Let => ("let", "let <name> = <value>", "Set a shell variable."),
+            Self::Export => (
+                "export",
+                "export <name> [= <value>]",
+                "Set an environment variable.",
+            ),
+            Self::Source => (
+                "source",
+                "source <file> [args...]",
+                "Run a file in the current shell.",
+            ),
+            Self::Read => (
+                "read",
+                "read [-p prompt] [-t secs] [-n chars] [names...]",
+                "Read a line from the standard input.",
+            ),
+            Self::Mapfile => (
+                "mapfile",
+                "mapfile [-t] [-n count] [array]",
+                "Read lines from the standard input into an array.",
+            ),
+            Self::Printf => (
+                "printf",
+                "printf [-v name] <format> [args...]",
+                "Print the arguments with the format.",
+            ),
+            Self::Set => (
+                "set",
+                "set [-l limit | +l] [-L bytes | +L] [-C | +C] [-u | +u] [-x | +x] [-o name | +o name]",
+                "Set or unset shell options.",
+            ),
+            Self::Type => (
+                "type",
+                "type [--json] <names...>",
+                "Show how each name would be run.",
+            ),
+            Self::Help => ("help", "help [name]", "Show the usage of builtins."),
+            Self::Alias => (
+                "alias",
+                "alias [--json | name [= value]]",
+                "Define or show aliases.",
+            ),
+            Self::Unalias => ("unalias", "unalias [-a] [names...]", "Remove aliases."),
+            Self::Fc => (
+                "fc",
+                "fc [-e editor] [first [last]] | fc -l [-nr] [first [last]]",
+                "Edit and run, or list commands in the history.",
+            ),
+            Self::Complete => (
+                "complete",
+                "complete [-f] [-W words] <names...> | complete -r <names...>",
+                "Set how the arguments of commands are completed.",
+            ),
+            Self::Declare => (
+                "declare",
+                "declare -f [names...] | declare -F",
+                "Show the definitions or the names of functions.",
+            ),
+            Self::Disown => (
+                "disown",
+                "disown [-h] [%job | pid...]",
+                "Stop tracking jobs, or keep them from SIGHUP with -h.",
+            ),
+            Self::Builtin => (
+                "builtin",
+                "builtin <name> [args...]",
+                "Run a builtin even if a function has the name.",
+            ),
+            Self::Enable => (
+                "enable",
+                "enable [-n] [names...]",
+                "Enable builtins, or disable them with -n to run the commands.",
+            ),
+            Self::Hash => (
+                "hash",
+                "hash [-r] [names...]",
+                "Show, add or forget the paths of commands.",
+            ),
+            Self::Mktemp => (
+                "mktemp",
+                "mktemp [-d] [template]",
+                "Create a temporary file or directory and print the path.",
+            ),
+            Self::Readonly => (
+                "readonly",
+                "readonly [-p] | readonly <name> [= <value>]",
+                "Make a variable unable to be assigned or unset.",
+            ),
+            Self::Unset => ("unset", "unset <names...>", "Remove shell variables."),
+            Self::Caller => (
+                "caller",
+                "caller [level]",
+                "Show the line and the source the function was called from.",
+            ),
+            Self::Times => (
+                "times",
+                "times",
+                "Show the CPU time used by the shell and its children.",
+            ),
+            Self::Umask => (
+                "umask",
+                "umask [-S] [mode]",
+                "Show or set the file creation mask.",
+            ),
+            Self::Wait => (
+                "wait",
+                "wait [-n] [%job | pid...]",
+                "Wait for background jobs to finish.",
+            ),
+        }
+    }
 }
 
-pub fn exit<T: AsRef<str>, TS: AsRef<[T]>>(args: TS) -> anyhow::Result<()> {
-    let args = args.as_ref();
+// An option, as opposed to `-` alone which is taken as an operand.
+fn is_flag(arg: &str) -> bool {
+    arg.len() > 1 && arg.starts_with('-')
+}
+
+pub fn exit<T: AsRef<str>, TS: AsRef<[T]>>(
+    args: TS,
+    jobs: &SharedJobs,
+    ns: &mut NameSpace,
+) -> anyhow::Result<i32> {
+    let mut args = args.as_ref();
+    let force = args.first().is_some_and(|arg| arg.as_ref() == "-f");
+    if force {
+        args = &args[1..];
+    }
     let code = match args.len() {
         0 => 0,
         1 => args[0]
             .as_ref()
             .parse::<i32>()
             .context("Failed to parse a number.")?,
-        _ => anyhow::bail!("Unnexpected args number."),
+        _ => anyhow::bail!(UsageError),
     };
+
+    // With `checkjobs`, the first `exit` only warns while there are jobs.
+    let check = ns.opts().checkjobs && !force && !ns.exit_warned();
+    if check && !jobs.get()?.list().is_empty() {
+        eprintln!("There are running jobs.");
+        ns.set_exit_warned(true);
+        return Ok(1);
+    }
+    if ns.in_subshell() {
+        anyhow::bail!(SubshellExit(code));
+    }
     std::process::exit(code);
 }
 
-pub fn cd<T: AsRef<str>, TS: AsRef<[T]>>(args: TS) -> anyhow::Result<()> {
+pub fn cd<T: AsRef<str>, TS: AsRef<[T]>>(args: TS, ns: &mut NameSpace) -> anyhow::Result<i32> {
     let args = args.as_ref();
-    let path = match args.len() {
-        0 => std::env::var("HOME").context("Failed to get the home directory.")?,
+    let mut path = match args.len() {
+        0 => ns
+            .get_var("HOME")
+            .context("Failed to get the home directory.")?,
         1 => String::from(args[0].as_ref()),
-        _ => anyhow::bail!("Unexpected args number."),
+        _ => anyhow::bail!(UsageError),
     };
 
-    std::env::set_current_dir(path).context("Failed to set current dir.")?;
+    // A relative path is searched in `$CDPATH` first, and the directory is
+    // printed when it is found through a non-empty entry other than `.`.
+    let relative =
+        !["/", "./", "../"].iter().any(|pre| path.starts_with(pre)) && path != "." && path != "..";
+    let mut print = false;
+    if let Some(cdpath) = ns
+        .get_var("CDPATH")
+        .filter(|_| relative && !args.is_empty())
+    {
+        for dir in cdpath.split(':') {
+            let found = std::path::Path::new(if dir.is_empty() { "." } else { dir }).join(&path);
+            if found.is_dir() {
+                print = !dir.is_empty() && dir != ".";
+                path = found.display().to_string();
+                break;
+            }
+        }
+    }
 
-    Ok(())
-}
+    // Failures are reported with the status instead of an error, so scripts
+    // can check it.
+    let res = std::fs::metadata(&path).and_then(|meta| {
+        if meta.is_dir() {
+            Ok(())
+        } else {
+            Err(std::io::Error::from_raw_os_error(nix::libc::ENOTDIR))
+        }
+    });
+    if let Err(e) = res {
+        let reason = match e.kind() {
+            std::io::ErrorKind::NotFound => String::from("No such file or directory"),
+            std::io::ErrorKind::PermissionDenied => String::from("Permission denied"),
+            _ if e.raw_os_error() == Some(nix::libc::ENOTDIR) => String::from("Not a directory"),
+            _ => e.to_string(),
+        };
+        eprintln!("cd: {}: {}", path, reason);
+        return Ok(1);
+    }
 
-pub fn fg<T: AsRef<str>, TS: AsRef<[T]>>(args: TS, jobs: &SharedJobs) -> anyhow::Result<()> {
-    let args = args.as_ref();
-    if args.len() != 1 {
-        anyhow::bail!("Unexpected args number.");
+    let old = std::env::current_dir().ok();
+    if let Err(e) = std::env::set_current_dir(&path) {
+        match e.kind() {
+            std::io::ErrorKind::PermissionDenied => eprintln!("cd: {}: Permission denied", path),
+            _ => eprintln!("cd: {}: {}", path, e),
+        }
+        return Ok(1);
+    }
+    if let Some(old) = old {
+        ns.push_gvar("OLDPWD", old.display().to_string());
     }
+    let pwd = std::env::current_dir()?.display().to_string();
+    if print {
+        println!("{}", pwd);
+    }
+    ns.push_gvar("PWD", pwd);
+
+    Ok(0)
+}
 
-    let id = args[0].as_ref();
+pub fn fg<T: AsRef<str>, TS: AsRef<[T]>>(args: TS, jobs: &SharedJobs) -> anyhow::Result<i32> {
+    // Without a job, the current one.
+    let spec = match args.as_ref() {
+        [] => "%+",
+        [spec] => spec.as_ref(),
+        _ => anyhow::bail!(UsageError),
+    };
 
     jobs.with(|jobs| {
         let id = jobs
-            .from_pid(id.parse().context("Failed to parse a number.")?)
-            .context("Can't find such a process.")?;
+            .resolve_job_spec(spec)
+            .with_context(|| format!("{}: no such job", spec))?;
 
         jobs.move_to_fg(id)?;
         Ok(())
     })?;
-    Ok(())
+    Ok(jobs.wait_fg()?.map_or(0, |status| status.code()))
 }
 
-pub fn let_<T: AsRef<str>, TS: AsRef<[T]>>(args: TS, ns: &mut NameSpace) -> anyhow::Result<()> {
+pub fn jobs_<T: AsRef<str>, TS: AsRef<[T]>>(args: TS, jobs: &SharedJobs) -> anyhow::Result<i32> {
+    let mut args = args.as_ref().iter().map(|arg| arg.as_ref()).peekable();
+    let json = args.next_if_eq(&"--json").is_some();
+    let pids = !json && args.next_if_eq(&"-p").is_some();
+    let specs = args.collect::<Vec<_>>();
+    if specs
+        .iter()
+        .any(|spec| is_flag(spec) && !spec.starts_with('%'))
+    {
+        anyhow::bail!(UsageError);
+    }
+
+    // Only the given jobs are listed, if any.
+    let jobs = jobs.get()?;
+    let mut list = jobs.list();
+    let mut code = 0;
+    if !specs.is_empty() {
+        let mut ids = Vec::new();
+        for spec in specs {
+            match jobs.resolve_job_spec(spec) {
+                Some(id) => ids.push(id),
+                None => {
+                    eprintln!("jobs: {}: no such job", spec);
+                    code = 1;
+                }
+            }
+        }
+        list.retain(|(id, _)| ids.contains(id));
+    }
+    if pids {
+        for (_, proc) in list {
+            println!("{}", proc.pid());
+        }
+    } else if json {
+        // [{"id": <job id>, "pid": <pid>, "suspended": <bool>}, ...], sorted by id.
+        let items = list
+            .iter()
+            .map(|(id, proc)| {
+                Json::object(vec![
+                    ("id", id.to_json()),
+                    ("pid", proc.pid().to_json()),
+                    ("suspended", proc.suspended().to_json()),
+                ])
+            })
+            .collect();
+        println!("{}", Json::Array(items));
+    } else {
+        for (id, proc) in list {
+            match jobs.label(proc.pid()) {
+                Some(label) => println!("%{} {} {}", id, proc, label),
+                None => println!("%{} {}", id, proc),
+            }
+        }
+    }
+
+    Ok(code)
+}
+
+pub fn wait<T: AsRef<str>, TS: AsRef<[T]>>(args: TS, jobs: &SharedJobs) -> anyhow::Result<i32> {
+    let args = args
+        .as_ref()
+        .iter()
+        .map(|arg| arg.as_ref())
+        .collect::<Vec<_>>();
+    match args.as_slice() {
+        // The status of the first job to finish, or 127 without any job.
+        ["-n"] => Ok(jobs
+            .wait_bg(None)?
+            .map_or(127, |(_, _, status)| status.code())),
+        [] => {
+            while jobs.wait_bg(None)?.is_some() {}
+            Ok(0)
+        }
+        specs => {
+            let mut code = 0;
+            for spec in specs {
+                // A pid is waited for even after its job has finished.
+                let pid = if spec.starts_with('%') {
+                    jobs.with(|jobs| {
+                        Ok(jobs
+                            .resolve_job_spec(spec)
+                            .and_then(|id| jobs.get_pid(&id).ok()))
+                    })?
+                } else {
+                    Some(spec.parse().context("Failed to parse a number.")?)
+                };
+                code = match pid
+                    .map(|pid| jobs.wait_bg(Some(pid)))
+                    .transpose()?
+                    .flatten()
+                {
+                    Some((_, _, status)) => status.code(),
+                    None => {
+                        eprintln!("wait: {}: no such job", spec);
+                        127
+                    }
+                };
+            }
+            Ok(code)
+        }
+    }
+}
+
+pub fn disown<T: AsRef<str>, TS: AsRef<[T]>>(args: TS, jobs: &SharedJobs) -> anyhow::Result<i32> {
+    let mut args = args
+        .as_ref()
+        .iter()
+        .map(|arg| arg.as_ref())
+        .collect::<Vec<_>>();
+    let nohup = args.first() == Some(&"-h");
+    if nohup {
+        args.remove(0);
+    }
+
+    jobs.with(|jobs| {
+        // Without any job, the most recent one.
+        let ids = match args.as_slice() {
+            [] => vec![jobs.last_id().context("There are no jobs.")?],
+            specs => specs
+                .iter()
+                .map(|spec| {
+                    jobs.resolve_job_spec(spec)
+                        .with_context(|| format!("{}: no such job", spec))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?,
+        };
+        for id in ids {
+            if nohup {
+                jobs.nohup(id);
+            } else {
+                jobs.disown(id);
+            }
+        }
+        Ok(0)
+    })
+}
+
+pub fn let_<T: AsRef<str>, TS: AsRef<[T]>>(args: TS, ns: &mut NameSpace) -> anyhow::Result<i32> {
     let args = args.as_ref();
     if args.len() != 3 {
-        anyhow::bail!("Unnexpected args number.");
+        anyhow::bail!(UsageError);
     }
 
     if args[1].as_ref() != "=" {
-        anyhow::bail!("Missing \"=\".");
+        anyhow::bail!(UsageError);
     }
 
+    ns.check_writable(args[0].as_ref())?;
     ns.push_var(args[0].as_ref(), args[2].as_ref());
-    Ok(())
+    Ok(0)
 }
 
-pub fn export<T: AsRef<str>, TS: AsRef<[T]>>(args: TS, ns: &mut NameSpace) -> anyhow::Result<()> {
+pub fn export<T: AsRef<str>, TS: AsRef<[T]>>(args: TS, ns: &mut NameSpace) -> anyhow::Result<i32> {
     let args = args.as_ref();
-    if args.len() != 3 {
-        anyhow::bail!("Unnexpected args number.");
+    // The environment can't take other names, as `export x=1` would give.
+    if let Some(name) = args.first().filter(|name| name.as_ref() != "-p") {
+        if !is_name(name.as_ref()) {
+            anyhow::bail!("{}: invalid variable name", name.as_ref());
+        }
+    }
+    match args {
+        [] => dump_vars(ns.env_snapshot(), "export ", " = "),
+        [p] if p.as_ref() == "-p" => dump_vars(ns.env_snapshot(), "export ", " = "),
+        // Exports a shell variable with its current value.
+        [name] => {
+            let value = ns.get_var(name).unwrap_or_default();
+            ns.push_gvar(name.as_ref(), value);
+        }
+        [name, eq, value] if eq.as_ref() == "=" => {
+            ns.check_writable(name.as_ref())?;
+            ns.push_gvar(name.as_ref(), value.as_ref());
+        }
+        _ => anyhow::bail!(UsageError),
     }
+    Ok(0)
+}
 
-    if args[1].as_ref() != "=" {
-        anyhow::bail!("Missing \"=\".");
+pub fn readonly<T: AsRef<str>, TS: AsRef<[T]>>(
+    args: TS,
+    ns: &mut NameSpace,
+) -> anyhow::Result<i32> {
+    let args = args.as_ref();
+    // `readonly x=1` would otherwise protect a variable nothing can assign.
+    if let Some(name) = args.first().filter(|name| name.as_ref() != "-p") {
+        if !is_name(name.as_ref()) {
+            anyhow::bail!("{}: invalid variable name", name.as_ref());
+        }
+    }
+    match args {
+        [] => dump_vars(ns.readonly_snapshot(), "readonly ", " = "),
+        [p] if p.as_ref() == "-p" => dump_vars(ns.readonly_snapshot(), "readonly ", " = "),
+        [name] => ns.set_readonly(name.as_ref()),
+        [name, eq, value] if eq.as_ref() == "=" => {
+            ns.check_writable(name.as_ref())?;
+            ns.push_var(name.as_ref(), value.as_ref());
+            ns.set_readonly(name.as_ref());
+        }
+        _ => anyhow::bail!(UsageError),
+    }
+    Ok(0)
+}
+
+pub fn unset<T: AsRef<str>, TS: AsRef<[T]>>(args: TS, ns: &mut NameSpace) -> anyhow::Result<i32> {
+    let args = args.as_ref();
+    if args.is_empty() {
+        anyhow::bail!(UsageError);
+    }
+    for name in args {
+        ns.remove_var(name.as_ref())?;
     }
+    Ok(0)
+}
+
+// Prints variables in the form which can be sourced again. Names which
+// can't be assigned, like `$1` and `$@`, are left out.
+fn dump_vars(vars: std::collections::BTreeMap<String, String>, prefix: &str, eq: &str) {
+    use crate::parse::SpecialStr;
 
-    ns.push_gvar(args[0].as_ref(), args[2].as_ref());
-    Ok(())
+    for (name, value) in vars.into_iter().filter(|(name, _)| is_name(name)) {
+        println!("{}{}{}{}", prefix, name, eq, SpecialStr::from(value));
+    }
+}
+
+fn is_name(name: &str) -> bool {
+    !name.starts_with(|c: char| c.is_ascii_digit())
+        && !name.is_empty()
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
 }
 
-pub fn source<T: AsRef<str>, TS: AsRef<[T]>>(args: TS, ns: &mut NameSpace) -> anyhow::Result<()> {
+pub fn source<T: AsRef<str>, TS: AsRef<[T]>>(args: TS, ns: &mut NameSpace) -> anyhow::Result<i32> {
     use crate::session::{IOReader, Session};
 
     let mut args = args.as_ref().iter();
-    let name = args.next().context("Specify the file to run.")?;
+    let name = args.next().ok_or(UsageError)?;
+
+    let reader = IOReader::new_file(name.as_ref())?;
+    ns.with_source(|ns| {
+        let mut session = Session::with_namespace(reader, std::mem::take(ns))?;
+        let res = session.all_with_args(name, args);
+        *ns = session.into_namespace();
+        res
+    })
+}
+
+// In a pipeline `read` takes the pipe as its stdin, but runs in a subshell
+// like any stage, so the variables are used inside it as in
+// `cmd | { read x; echo $x }`.
+pub fn read<T: AsRef<str>, TS: AsRef<[T]>>(args: TS, ns: &mut NameSpace) -> anyhow::Result<i32> {
+    let mut args = args.as_ref().iter().map(|arg| arg.as_ref());
+    let mut prompt = None;
+    let mut timeout = None;
+    let mut nchars = None;
+    let mut names = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg {
+            "-p" => prompt = Some(args.next().ok_or(UsageError)?),
+            "-t" => timeout = Some(parse_timeout(args.next().ok_or(UsageError)?)?),
+            "-n" => {
+                let n = args
+                    .next()
+                    .ok_or(UsageError)?
+                    .parse::<usize>()
+                    .context("Failed to parse a number.")?;
+                nchars = Some(n);
+            }
+            flag if is_flag(flag) => anyhow::bail!(UsageError),
+            name => names.push(name),
+        }
+    }
+
+    if let Some(prompt) = prompt {
+        use std::io::Write;
+        eprint!("{}", prompt);
+        std::io::stderr().flush()?;
+    }
+
+    let (line, complete) = match read_stdin(timeout, nchars)? {
+        Some(res) => res,
+        None => return Ok(1),
+    };
+
+    if names.is_empty() {
+        ns.check_writable("REPLY")?;
+        ns.push_var("REPLY", &line);
+    } else {
+        let ifs = ns.get_var("IFS").unwrap_or_else(|| String::from(" \t\n"));
+        let fields = split_fields(&line, &ifs, names.len());
+        for (i, name) in names.iter().enumerate() {
+            ns.check_writable(name)?;
+            ns.push_var(*name, fields.get(i).copied().unwrap_or_default());
+        }
+    }
+
+    Ok(if complete { 0 } else { 1 })
+}
+
+// Splits a line into at most `count` fields by `$IFS` like other shells.
+// Whitespaces in it are trimmed at both ends and a run of them separates
+// fields, while any other character separates fields one by one. The last
+// field takes the rest of the line.
+fn split_fields<'a>(line: &'a str, ifs: &str, count: usize) -> Vec<&'a str> {
+    let is_space = |c: char| c.is_whitespace() && ifs.contains(c);
+    let is_delim = |c: char| !c.is_whitespace() && ifs.contains(c);
+
+    let mut rest = line.trim_matches(is_space);
+    let mut fields = Vec::new();
+    while fields.len() + 1 < count && !rest.is_empty() {
+        let end = rest.find(|c| ifs.contains(c)).unwrap_or(rest.len());
+        fields.push(&rest[..end]);
+        rest = rest[end..].trim_start_matches(is_space);
+        if let Some(c) = rest.chars().next().filter(|c| is_delim(*c)) {
+            rest = rest[c.len_utf8()..].trim_start_matches(is_space);
+        }
+    }
+    fields.push(rest);
+    fields
+}
 
-    let mut session = Session::new(IOReader::new_file(name.as_ref())?)?;
-    session.all_with_args(ns, name, args)?;
-    Ok(())
+pub fn mapfile<T: AsRef<str>, TS: AsRef<[T]>>(args: TS, ns: &mut NameSpace) -> anyhow::Result<i32> {
+    let mut args = args.as_ref().iter().map(|arg| arg.as_ref());
+    let mut limit = None;
+    let mut name = None;
+    while let Some(arg) = args.next() {
+        match arg {
+            // Lines are always stored without the newline.
+            "-t" => (),
+            "-n" => {
+                let n = args
+                    .next()
+                    .ok_or(UsageError)?
+                    .parse::<usize>()
+                    .context("Failed to parse a number.")?;
+                limit = Some(n).filter(|n| *n > 0);
+            }
+            flag if is_flag(flag) => anyhow::bail!(UsageError),
+            arg if name.is_none() => name = Some(arg),
+            _ => anyhow::bail!(UsageError),
+        }
+    }
+
+    let mut lines = Vec::new();
+    while limit.is_none_or(|n| lines.len() < n) {
+        match read_stdin(None, None)? {
+            Some((line, _)) => lines.push(line),
+            None => break,
+        }
+    }
+
+    let name = name.unwrap_or("MAPFILE");
+    ns.check_writable(name)?;
+    ns.push_array(name, lines);
+    Ok(0)
+}
+
+// Seconds of `read -t`, which are too many to count are taken as forever.
+fn parse_timeout(secs: &str) -> anyhow::Result<std::time::Duration> {
+    let secs = secs.parse::<f64>().context("Failed to parse a number.")?;
+    if !secs.is_finite() || secs < 0.0 {
+        anyhow::bail!(UsageError);
+    }
+    Ok(std::time::Duration::try_from_secs_f64(secs).unwrap_or(std::time::Duration::MAX))
+}
+
+// Reads byte by byte so that nothing after the line is consumed.
+fn read_stdin(
+    timeout: Option<std::time::Duration>,
+    nchars: Option<usize>,
+) -> anyhow::Result<Option<(String, bool)>> {
+    use nix::poll::{poll, PollFd, PollFlags};
+    use nix::sys::termios::{tcgetattr, tcsetattr, LocalFlags, SetArg};
+    use nix::unistd;
+    use std::time::{Duration, Instant};
+
+    // Don't wait for a newline from the terminal when the length is limited.
+    let saved = match nchars {
+        Some(_) if unistd::isatty(0).unwrap_or(false) => {
+            let saved = tcgetattr(0).context("Failed to get the terminal attributes.")?;
+            let mut raw = saved.clone();
+            raw.local_flags.remove(LocalFlags::ICANON);
+            tcsetattr(0, SetArg::TCSANOW, &raw)
+                .context("Failed to set the terminal attributes.")?;
+            Some(saved)
+        }
+        _ => None,
+    };
+
+    // Waits in short slices to give up on SIGINT.
+    let slice = Duration::from_millis(100);
+    let deadline = timeout.and_then(|t| Instant::now().checked_add(t));
+    let mut buf = Vec::new();
+    crate::job::interrupted();
+    let res = loop {
+        if nchars.is_some_and(|n| complete_chars(&buf) >= n) {
+            break Ok(Some(true));
+        }
+
+        let left = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        let mut fds = [PollFd::new(0, PollFlags::POLLIN)];
+        match poll(
+            &mut fds,
+            left.unwrap_or(slice)
+                .min(slice)
+                .as_millis()
+                .min(i32::MAX as u128) as i32,
+        ) {
+            Ok(0) | Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => {
+                if crate::job::interrupted() {
+                    break Err(anyhow::anyhow!("Interrupted"));
+                }
+                if left.is_some_and(|left| left.is_zero()) {
+                    break Ok(None);
+                }
+                continue;
+            }
+            Ok(_) => (),
+            Err(e) => break Err(e).context("Failed to wait for the input."),
+        }
+
+        let mut byte = [0u8];
+        match unistd::read(0, &mut byte) {
+            Ok(0) if buf.is_empty() => break Ok(None),
+            Ok(0) => break Ok(Some(false)),
+            Ok(_) if byte[0] == b'\n' => break Ok(Some(true)),
+            Ok(_) => buf.push(byte[0]),
+            Err(e) => break Err(e).context("Failed to read the input."),
+        }
+    };
+
+    if let Some(saved) = saved {
+        tcsetattr(0, SetArg::TCSANOW, &saved).context("Failed to set the terminal attributes.")?;
+    }
+
+    Ok(res?.map(|complete| (String::from_utf8_lossy(&buf).into_owned(), complete)))
+}
+
+// The characters in `buf`, leaving out the last one until all of its bytes
+// have come. An invalid byte counts as one, as it is replaced by U+FFFD.
+fn complete_chars(buf: &[u8]) -> usize {
+    let mut rest = buf;
+    let mut count = 0;
+    loop {
+        match std::str::from_utf8(rest) {
+            Ok(s) => return count + s.chars().count(),
+            Err(e) => {
+                let (valid, after) = rest.split_at(e.valid_up_to());
+                count += String::from_utf8_lossy(valid).chars().count();
+                match e.error_len() {
+                    Some(len) => {
+                        count += 1;
+                        rest = &after[len..];
+                    }
+                    None => return count,
+                }
+            }
+        }
+    }
+}
+
+pub fn printf<T: AsRef<str>, TS: AsRef<[T]>>(args: TS, ns: &mut NameSpace) -> anyhow::Result<i32> {
+    let mut args = args.as_ref();
+    let mut var = None;
+    if let [opt, name, rest @ ..] = args {
+        if opt.as_ref() == "-v" {
+            var = Some(name.as_ref());
+            args = rest;
+        }
+    }
+
+    let (fmt, args) = args.split_first().ok_or(UsageError)?;
+    let res = super::format::printf(fmt.as_ref(), args)?;
+    match var {
+        Some(name) => {
+            ns.check_writable(name)?;
+            ns.push_var(name, res);
+        }
+        None => {
+            use std::io::Write;
+            let mut stdout = std::io::stdout();
+            stdout.write_all(res.as_bytes())?;
+            stdout.flush()?;
+        }
+    }
+    Ok(0)
+}
+
+pub fn help<T: AsRef<str>, TS: AsRef<[T]>>(args: TS) -> anyhow::Result<i32> {
+    match args.as_ref() {
+        [] => {
+            for kind in BuiltinKind::ALL.iter() {
+                println!("{:<10} {}", kind.name(), kind.summary());
+            }
+            Ok(0)
+        }
+        [name] => match BuiltinKind::new(name).filter(|kind| *kind != BuiltinKind::Empty) {
+            Some(kind) => {
+                println!("usage: {}\n    {}", kind.usage(), kind.summary());
+                Ok(0)
+            }
+            None => {
+                eprintln!("help: no builtin named `{}`", name.as_ref());
+                Ok(1)
+            }
+        },
+        _ => anyhow::bail!(UsageError),
+    }
+}
+
+pub fn alias<T: AsRef<str>, TS: AsRef<[T]>>(args: TS, ns: &mut NameSpace) -> anyhow::Result<i32> {
+    match args.as_ref() {
+        [] => {
+            for (name, value) in ns.aliases().list() {
+                println!("alias {} = {}", name, value);
+            }
+            Ok(0)
+        }
+        // [{"name": <name>, "value": <value>}, ...], sorted by the names.
+        [json] if json.as_ref() == "--json" => {
+            let items = ns
+                .aliases()
+                .list()
+                .into_iter()
+                .map(|(name, value)| {
+                    Json::object(vec![("name", name.to_json()), ("value", value.to_json())])
+                })
+                .collect();
+            println!("{}", Json::Array(items));
+            Ok(0)
+        }
+        [name] => match ns.aliases().get(name) {
+            Some(value) => {
+                println!("alias {} = {}", name.as_ref(), value);
+                Ok(0)
+            }
+            None => {
+                eprintln!("alias: {}: not found", name.as_ref());
+                Ok(1)
+            }
+        },
+        [name, eq, value @ ..] if eq.as_ref() == "=" => {
+            let value = value.iter().map(|v| v.as_ref()).collect::<Vec<_>>();
+            ns.aliases_mut().push(name.as_ref(), value.join(" "));
+            Ok(0)
+        }
+        _ => anyhow::bail!(UsageError),
+    }
+}
+
+pub fn unalias<T: AsRef<str>, TS: AsRef<[T]>>(args: TS, ns: &mut NameSpace) -> anyhow::Result<i32> {
+    let args = args.as_ref();
+    if args.is_empty() {
+        anyhow::bail!(UsageError);
+    }
+
+    let mut code = 0;
+    for name in args.iter().map(|arg| arg.as_ref()) {
+        if name == "-a" {
+            ns.aliases_mut().clear();
+        } else if !ns.aliases_mut().remove(name) {
+            eprintln!("unalias: {}: not found", name);
+            code = 1;
+        }
+    }
+    Ok(code)
+}
+
+pub fn fc<T: AsRef<str>, TS: AsRef<[T]>>(
+    args: TS,
+    jobs: &SharedJobs,
+    ns: &mut NameSpace,
+) -> anyhow::Result<i32> {
+    let mut args = args.as_ref().iter().map(|arg| arg.as_ref());
+    let mut list = false;
+    let mut numbers = true;
+    let mut reverse = false;
+    let mut editor = None;
+    let mut range = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg {
+            "-e" => editor = Some(args.next().ok_or(UsageError)?.to_string()),
+            "-l" => list = true,
+            "-n" => numbers = false,
+            "-r" => reverse = true,
+            "-ln" | "-nl" => {
+                list = true;
+                numbers = false;
+            }
+            "-lr" | "-rl" => {
+                list = true;
+                reverse = true;
+            }
+            // A negative number counts back in the history.
+            flag if is_flag(flag) && flag[1..].parse::<usize>().is_err() => {
+                anyhow::bail!(UsageError)
+            }
+            spec if range.len() < 2 => range.push(spec),
+            _ => anyhow::bail!(UsageError),
+        }
+    }
+
+    // The newest entry is the `fc` itself, so offsets count back from it.
+    let history = ns.history();
+    let resolve = |spec: &str| {
+        history
+            .resolve(spec)
+            .with_context(|| format!("fc: {}: no command found", spec))
+    };
+    let (first, last) = match (range.first(), range.get(1)) {
+        (Some(first), Some(last)) => (resolve(first)?, resolve(last)?),
+        (Some(first), None) if list => (resolve(first)?, resolve("-1")?),
+        (Some(first), None) => (resolve(first)?, resolve(first)?),
+        (None, _) if list => (resolve("-16").or_else(|_| resolve("1"))?, resolve("-1")?),
+        (None, _) => (resolve("-1")?, resolve("-1")?),
+    };
+    let mut entries = (first.min(last)..=first.max(last))
+        .map(|n| (n, history.get(n).unwrap_or_default().to_string()))
+        .collect::<Vec<_>>();
+    if reverse != (first > last) {
+        entries.reverse();
+    }
+
+    if list {
+        for (n, line) in entries {
+            if numbers {
+                println!("{}\t{}", n, line);
+            } else {
+                println!("\t{}", line);
+            }
+        }
+        return Ok(0);
+    }
+
+    let editor = editor
+        .or_else(|| ns.get_var("FCEDIT"))
+        .or_else(|| ns.get_var("EDITOR"))
+        .unwrap_or_else(|| String::from("vi"));
+    let (temp, mut file) = TempFile::new(ns, "fc")?;
+    for (_, line) in entries {
+        use std::io::Write;
+        writeln!(file, "{}", line).context("Failed to write a temporary file.")?;
+    }
+    drop(file);
+    edit_and_run(&editor, temp.path(), jobs, ns)
+}
+
+// The edited commands are run only when the editor exits successfully.
+fn edit_and_run(
+    editor: &str,
+    path: &std::path::Path,
+    jobs: &SharedJobs,
+    ns: &mut NameSpace,
+) -> anyhow::Result<i32> {
+    use crate::session::{IOReader, Session};
+
+    let mut words = editor.split_whitespace();
+    let name = words.next().context("fc: the editor is empty")?;
+    let mut cmd = std::process::Command::new(name);
+    ns.apply_env(&mut cmd);
+    let child = cmd
+        .args(words)
+        .arg(path)
+        .spawn()
+        .with_context(|| format!("fc: failed to run {}", name))?;
+    jobs.with(|jobs| jobs.new_fg(child.id() as i32))?;
+    let code = match jobs.wait_fg()? {
+        Some(status) => status.code(),
+        None => 0,
+    };
+    if code != 0 {
+        return Ok(code);
+    }
+
+    let content = std::fs::read_to_string(path).context("Failed to read a temporary file.")?;
+    eprint!("{}", content);
+    let mut session = Session::with_namespace(IOReader::new_file(path)?, std::mem::take(ns))?;
+    let res = session.all();
+    *ns = session.into_namespace();
+    res?;
+    Ok(ns
+        .get_var("status")
+        .and_then(|code| code.parse().ok())
+        .unwrap_or(0))
+}
+
+pub fn complete<T: AsRef<str>, TS: AsRef<[T]>>(
+    args: TS,
+    ns: &mut NameSpace,
+) -> anyhow::Result<i32> {
+    let mut args = args.as_ref().iter().map(|arg| arg.as_ref());
+    let mut spec = CompSpec::default();
+    let mut remove = false;
+    let mut names = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg {
+            "-f" => spec.files = true,
+            "-W" => {
+                let words = args.next().ok_or(UsageError)?;
+                spec.words
+                    .extend(words.split_whitespace().map(String::from));
+            }
+            "-r" => remove = true,
+            flag if is_flag(flag) => anyhow::bail!(UsageError),
+            name => names.push(name),
+        }
+    }
+
+    if names.is_empty() {
+        for (name, spec) in ns.completions().list() {
+            let mut opts = Vec::new();
+            if !spec.words.is_empty() {
+                opts.push(format!("-W \"{}\"", spec.words.join(" ")));
+            }
+            if spec.files {
+                opts.push(String::from("-f"));
+            }
+            println!("complete {} {}", opts.join(" "), name);
+        }
+        return Ok(0);
+    }
+
+    let mut code = 0;
+    for name in names {
+        if !remove {
+            ns.completions_mut().push(name, spec.clone());
+        } else if !ns.completions_mut().remove(name) {
+            eprintln!("complete: {}: no completion specification", name);
+            code = 1;
+        }
+    }
+    Ok(code)
+}
+
+pub fn hash<T: AsRef<str>, TS: AsRef<[T]>>(args: TS, ns: &mut NameSpace) -> anyhow::Result<i32> {
+    let mut code = 0;
+    let mut names = Vec::new();
+    for arg in args.as_ref().iter().map(|arg| arg.as_ref()) {
+        match arg {
+            "-r" => ns.paths_mut().clear(),
+            flag if is_flag(flag) => anyhow::bail!(UsageError),
+            name => names.push(name),
+        }
+    }
+
+    if names.is_empty() && args.as_ref().is_empty() {
+        println!("hits\tcommand");
+        for (_, path, hits) in ns.paths().list(ns.get_var("PATH").as_deref()) {
+            println!("{:>4}\t{}", hits, path.display());
+        }
+    }
+    for name in names {
+        let path = ns.get_var("PATH");
+        if ns
+            .paths_mut()
+            .resolve(name, path.as_deref(), search_path)
+            .is_none()
+        {
+            eprintln!("hash: {}: not found", name);
+            code = 1;
+        }
+    }
+    Ok(code)
+}
+
+pub fn declare<T: AsRef<str>, TS: AsRef<[T]>>(args: TS, ns: &mut NameSpace) -> anyhow::Result<i32> {
+    let args = args
+        .as_ref()
+        .iter()
+        .map(|arg| arg.as_ref())
+        .collect::<Vec<_>>();
+    match args.as_slice() {
+        ["-F"] => {
+            for name in ns.proc_names() {
+                println!("declare -f {}", name);
+            }
+            Ok(0)
+        }
+        ["-f"] => {
+            for name in ns.proc_names() {
+                println!("{} {}", name, ns.proc_source(name).unwrap_or_default());
+            }
+            Ok(0)
+        }
+        ["-f", names @ ..] => {
+            let mut code = 0;
+            for name in names {
+                match ns.proc_source(name) {
+                    Some(source) => println!("{} {}", name, source),
+                    None => code = 1,
+                }
+            }
+            Ok(code)
+        }
+        _ => anyhow::bail!(UsageError),
+    }
+}
+
+// Skips functions, aliases and `enable -n`, so a function can wrap the
+// builtin of the same name.
+pub fn builtin<T: AsRef<str>, TS: AsRef<[T]>>(
+    args: TS,
+    jobs: &SharedJobs,
+    ns: &mut NameSpace,
+) -> anyhow::Result<i32> {
+    let args = args.as_ref();
+    let name = match args.first() {
+        Some(name) => name.as_ref(),
+        None => return Ok(0),
+    };
+    match BuiltinKind::new(name).filter(|kind| *kind != BuiltinKind::Empty) {
+        Some(kind) => Builtin::new(kind, args[1..].iter().map(|arg| arg.as_ref())).eval(jobs, ns),
+        None => {
+            eprintln!("builtin: {}: not a shell builtin", name);
+            Ok(1)
+        }
+    }
+}
+
+pub fn enable<T: AsRef<str>, TS: AsRef<[T]>>(args: TS, ns: &mut NameSpace) -> anyhow::Result<i32> {
+    let mut args = args.as_ref().iter().map(|arg| arg.as_ref()).peekable();
+    let disable = args.next_if_eq(&"-n").is_some();
+    let names = args.collect::<Vec<_>>();
+
+    // Lists the builtins, only the disabled ones with `-n`.
+    if names.is_empty() {
+        for kind in BuiltinKind::ALL.iter() {
+            let enabled = ns.builtin_enabled(kind.name());
+            if enabled {
+                if !disable {
+                    println!("enable {}", kind.name());
+                }
+            } else {
+                println!("enable -n {}", kind.name());
+            }
+        }
+        return Ok(0);
+    }
+
+    let mut code = 0;
+    for name in names {
+        if BuiltinKind::new(name).is_some_and(|kind| kind != BuiltinKind::Empty) {
+            ns.set_builtin_enabled(name, !disable);
+        } else {
+            eprintln!("enable: {}: not a shell builtin", name);
+            code = 1;
+        }
+    }
+    Ok(code)
+}
+
+pub fn umask<T: AsRef<str>, TS: AsRef<[T]>>(args: TS) -> anyhow::Result<i32> {
+    use nix::sys::stat::{self, Mode};
+
+    let mut args = args.as_ref().iter().map(|arg| arg.as_ref()).peekable();
+    let symbolic = args.next_if_eq(&"-S").is_some();
+    // The mask can only be read by setting it, so it is put back.
+    let old = stat::umask(Mode::empty());
+    stat::umask(old);
+    let old = old.bits() as u32;
+
+    let mode = match args.next() {
+        Some(mode) => mode,
+        None if symbolic => {
+            let perm = !old & 0o777;
+            let part = |shift: u32| {
+                let bits = (perm >> shift) & 0o7;
+                ["r", "w", "x"]
+                    .iter()
+                    .zip([4, 2, 1].iter())
+                    .filter(|(_, bit)| bits & *bit != 0)
+                    .map(|(c, _)| *c)
+                    .collect::<String>()
+            };
+            println!("u={},g={},o={}", part(6), part(3), part(0));
+            return Ok(0);
+        }
+        None => {
+            println!("{:04o}", old);
+            return Ok(0);
+        }
+    };
+
+    let mask = match u32::from_str_radix(mode, 8) {
+        Ok(mask) if mask <= 0o777 => mask,
+        Ok(_) => anyhow::bail!("{}: octal number out of range", mode),
+        Err(_) => !parse_perm(mode, !old & 0o777)? & 0o777,
+    };
+    stat::umask(Mode::from_bits_truncate(mask as _));
+    Ok(0)
+}
+
+// `LINE SOURCE` of the current call, or `LINE NAME SOURCE` of the `level`th
+// one from it, so that a loop over the levels prints the whole stack. The
+// status is 1 outside of them.
+pub fn caller<T: AsRef<str>, TS: AsRef<[T]>>(args: TS, ns: &NameSpace) -> anyhow::Result<i32> {
+    let level = match args.as_ref() {
+        [] => None,
+        [level] => Some(level.as_ref().parse::<usize>().map_err(|_| UsageError)?),
+        _ => anyhow::bail!(UsageError),
+    };
+    match (level, ns.caller(level.unwrap_or(0))) {
+        (None, Some((line, _, source))) => println!("{} {}", line, source),
+        (Some(_), Some((line, name, source))) => println!("{} {} {}", line, name, source),
+        (_, None) => return Ok(1),
+    }
+    Ok(0)
+}
+
+// The user and system times of the shell, then of its finished children.
+pub fn times<T: AsRef<str>, TS: AsRef<[T]>>(args: TS) -> anyhow::Result<i32> {
+    if !args.as_ref().is_empty() {
+        anyhow::bail!(UsageError);
+    }
+    for times in [CpuTimes::shell(), CpuTimes::children()].iter() {
+        println!("{} {}", format_time(times.user), format_time(times.sys));
+    }
+    Ok(0)
+}
+
+// Without a template, the path is under `$TMPDIR`. A failure is reported
+// with the status 1.
+pub fn mktemp<T: AsRef<str>, TS: AsRef<[T]>>(args: TS, ns: &NameSpace) -> anyhow::Result<i32> {
+    let mut args = args.as_ref().iter().map(|arg| arg.as_ref()).peekable();
+    let dir = args.next_if_eq(&"-d").is_some();
+    let template = match args.next() {
+        Some(template) => String::from(template),
+        None => temp_dir(ns)
+            .join("tmp.XXXXXXXXXX")
+            .to_string_lossy()
+            .into_owned(),
+    };
+    if args.next().is_some() {
+        anyhow::bail!(UsageError);
+    }
+
+    match make_temp(&template, dir) {
+        Ok(path) => {
+            println!("{}", path.display());
+            Ok(0)
+        }
+        Err(e) => {
+            eprintln!("mktemp: {}", e);
+            Ok(1)
+        }
+    }
+}
+
+// Applies a symbolic mode like `u=rwx,g+r,o-w` to the permissions.
+fn parse_perm(mode: &str, mut perm: u32) -> anyhow::Result<u32> {
+    for clause in mode.split(',') {
+        let op = clause
+            .find(|c| "+-=".contains(c))
+            .with_context(|| format!("{}: invalid symbolic mode", mode))?;
+        let (who, rest) = clause.split_at(op);
+        let mut mask = 0;
+        for c in who.chars() {
+            mask |= match c {
+                'u' => 0o700,
+                'g' => 0o070,
+                'o' => 0o007,
+                'a' => 0o777,
+                _ => anyhow::bail!("{}: invalid symbolic mode", mode),
+            };
+        }
+        if who.is_empty() {
+            mask = 0o777;
+        }
+
+        let mut bits = 0;
+        for c in rest[1..].chars() {
+            bits |= match c {
+                'r' => 0o444,
+                'w' => 0o222,
+                'x' => 0o111,
+                _ => anyhow::bail!("{}: invalid symbolic mode", mode),
+            };
+        }
+        bits &= mask;
+        match &rest[..1] {
+            "+" => perm |= bits,
+            "-" => perm &= !bits,
+            _ => perm = (perm & !mask) | bits,
+        }
+    }
+    Ok(perm)
+}
+
+pub fn set<T: AsRef<str>, TS: AsRef<[T]>>(args: TS, ns: &mut NameSpace) -> anyhow::Result<i32> {
+    if args.as_ref().is_empty() {
+        dump_vars(ns.vars_snapshot(), "", "=");
+        return Ok(0);
+    }
+
+    let mut args = args.as_ref().iter().map(|arg| arg.as_ref());
+    while let Some(arg) = args.next() {
+        match arg {
+            "-l" => {
+                let limit = args
+                    .next()
+                    .context("Specify the loop limit.")?
+                    .parse::<usize>()
+                    .context("Failed to parse a number.")?;
+                ns.opts_mut().loop_limit = Some(limit);
+            }
+            "+l" => ns.opts_mut().loop_limit = None,
+            "-L" => {
+                let limit = args
+                    .next()
+                    .context("Specify the output limit.")?
+                    .parse::<usize>()
+                    .context("Failed to parse a number.")?;
+                ns.opts_mut().output_limit = Some(limit);
+            }
+            "+L" => ns.opts_mut().output_limit = None,
+            "-C" => ns.opts_mut().noclobber = true,
+            "+C" => ns.opts_mut().noclobber = false,
+            "-u" => ns.opts_mut().nounset = true,
+            "+u" => ns.opts_mut().nounset = false,
+            "-x" => ns.opts_mut().xtrace = true,
+            "+x" => ns.opts_mut().xtrace = false,
+            "-b" => ns.opts_mut().notify = true,
+            "+b" => ns.opts_mut().notify = false,
+            "-o" | "+o" => {
+                let on = arg == "-o";
+                match args.next().context("Specify the option name.")? {
+                    "nullglob" => ns.opts_mut().nullglob = on,
+                    "failglob" => ns.opts_mut().failglob = on,
+                    "globstar" => ns.opts_mut().globstar = on,
+                    "autocd" => ns.opts_mut().autocd = on,
+                    "parseskip" => ns.opts_mut().parse_skip = on,
+                    "pipefail" => ns.opts_mut().pipefail = on,
+                    "noclobber" => ns.opts_mut().noclobber = on,
+                    "nounset" => ns.opts_mut().nounset = on,
+                    "checkjobs" => ns.opts_mut().checkjobs = on,
+                    "vi" => ns.opts_mut().vi = on,
+                    // Emacs is the default, which `+o emacs` leaves as it is.
+                    "emacs" if on => ns.opts_mut().vi = false,
+                    "emacs" => (),
+                    "notify" => ns.opts_mut().notify = on,
+                    "asciicase" => ns.opts_mut().asciicase = on,
+                    "xtrace" => ns.opts_mut().xtrace = on,
+                    "ignoreeof" => ns.opts_mut().ignoreeof = on,
+                    name => anyhow::bail!("Unknown option name: {}", name),
+                }
+            }
+            opt => anyhow::bail!("Unknown option: {}", opt),
+        }
+    }
+
+    Ok(0)
+}
+
+pub fn type_<T: AsRef<str>, TS: AsRef<[T]>>(args: TS, ns: &mut NameSpace) -> anyhow::Result<i32> {
+    let mut args = args.as_ref().iter().map(|arg| arg.as_ref()).peekable();
+    let json = args.next_if_eq(&"--json").is_some();
+
+    let mut code = 0;
+    let mut items = Vec::new();
+    for name in args {
+        let (kind, path) = if ns.get_proc(name).is_some() {
+            ("function", None)
+        } else if BuiltinKind::lookup(name, ns).is_some() {
+            ("builtin", None)
+        } else if let Some(path) = search_path(name, ns.get_var("PATH").as_deref()) {
+            ("file", Some(path.display().to_string()))
+        } else {
+            code = 1;
+            ("none", None)
+        };
+
+        if json {
+            // [{"name": <name>, "kind": "function" | "builtin" | "file" | "none",
+            //   "path": <path> | null}, ...], in the order of the arguments.
+            items.push(Json::object(vec![
+                ("name", name.to_json()),
+                ("kind", kind.to_json()),
+                ("path", path.to_json()),
+            ]));
+            continue;
+        }
+
+        match (kind, path) {
+            ("function", _) => println!("{} is a function", name),
+            ("builtin", _) => println!("{} is a shell builtin", name),
+            (_, Some(path)) => println!("{} is {}", name, path),
+            _ => eprintln!("type: {}: not found", name),
+        }
+    }
+
+    if json {
+        println!("{}", Json::Array(items));
+    }
+
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn read_timeout() {
+        assert_eq!(parse_timeout("1.5").unwrap(), Duration::from_millis(1500));
+        assert_eq!(parse_timeout("0").unwrap(), Duration::from_secs(0));
+        assert_eq!(parse_timeout("1e300").unwrap(), Duration::MAX);
+        for secs in ["-1", "NaN", "inf", "-inf"].iter() {
+            assert!(
+                parse_timeout(secs).unwrap_err().is::<UsageError>(),
+                "{}",
+                secs
+            );
+        }
+        assert!(parse_timeout("x").is_err());
+    }
+
+    #[test]
+    fn ifs_fields() {
+        assert_eq!(
+            split_fields("  one   two  three ", " \t\n", 2),
+            ["one", "two  three"]
+        );
+        assert_eq!(split_fields("a::b:c d", ":", 4), ["a", "", "b", "c d"]);
+        assert_eq!(split_fields("a : b", " :", 3), ["a", "b", ""]);
+        assert_eq!(split_fields(":a", ":", 2), ["", "a"]);
+        assert_eq!(split_fields("a b", "", 2), ["a b", ""]);
+    }
+
+    #[test]
+    fn editing_modes() {
+        let mut ns = NameSpace::default();
+        set(["-o", "vi"], &mut ns).unwrap();
+        assert!(ns.opts().vi);
+        set(["+o", "emacs"], &mut ns).unwrap();
+        assert!(ns.opts().vi);
+        set(["-o", "emacs"], &mut ns).unwrap();
+        assert!(!ns.opts().vi);
+        set(["+o", "emacs"], &mut ns).unwrap();
+        assert!(!ns.opts().vi);
+    }
+
+    // Holding notices is `+o notify`, not an option of its own.
+    #[test]
+    fn notify_is_the_only_notice_option() {
+        let mut ns = NameSpace::default();
+        set(["-o", "notify"], &mut ns).unwrap();
+        assert!(ns.opts().notify);
+        set(["+b"], &mut ns).unwrap();
+        assert!(!ns.opts().notify);
+        assert!(set(["-o", "holdnotices"], &mut ns).is_err());
+    }
+
+    #[test]
+    fn complete_chars_wait_for_the_last_bytes() {
+        assert_eq!(complete_chars(b"ab"), 2);
+        assert_eq!(complete_chars("\u{e9}".as_bytes()), 1);
+        assert_eq!(complete_chars(&"a\u{e9}".as_bytes()[..2]), 1);
+        assert_eq!(complete_chars(&"\u{3042}".as_bytes()[..2]), 0);
+        assert_eq!(complete_chars(b"\xffa\xc3"), 2);
+    }
 }
@@ -1,39 +1,118 @@
-use super::Redirects;
+use super::{redirect, BuiltinKind, NameSpace, Redirects, SubshellExit};
 
+use crate::eval::word::{self, Split};
+use crate::eval::{fork, Block};
 use crate::job::SharedJobs;
-use crate::parse::{Arg as ParseArg, Command as ParseCmd, SpecialStr};
+use crate::parse::{Arg as ParseArg, Command as ParseCmd, Cond, SpecialStr};
 
-use std::process::{Child, Command, Stdio};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::PathBuf;
+use std::process::{Child, Command};
+
+// A command which can't be started. It fails with the status 127 if it isn't
+// found, and 126 otherwise, like other shells.
+#[derive(Debug)]
+pub struct SpawnError(String, pub i32);
+
+impl std::fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for SpawnError {}
+
+fn spawn_error(name: &str, e: std::io::Error) -> SpawnError {
+    match e.kind() {
+        std::io::ErrorKind::NotFound if !name.contains('/') => {
+            SpawnError(format!("{}: command not found", name), 127)
+        }
+        std::io::ErrorKind::NotFound => {
+            SpawnError(format!("{}: No such file or directory", name), 127)
+        }
+        _ => {
+            let reason = match e.raw_os_error() {
+                Some(errno) => nix::errno::Errno::from_i32(errno).desc().to_string(),
+                None => e.to_string(),
+            };
+            SpawnError(format!("{}: {}", name, reason), 126)
+        }
+    }
+}
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct External {
+    pub assigns: Vec<(String, SpecialStr)>,
     pub name: SpecialStr,
     pub args: Args,
     pub reds: Redirects,
     pub pipe: Option<Box<External>>,
     pub bg: bool,
+    pub cond: Option<Cond>,
+    // The source is kept for `jobs`.
+    pub source: String,
+    // The source of this stage alone, to run it in a child process.
+    pub stage: String,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Args(Vec<Arg>);
 
+// A stage of a pipeline, either a process or a builtin which has already run.
+#[derive(Debug)]
+pub enum Stage {
+    Child(Child),
+    Done(i32),
+}
+
 impl Args {
-    pub fn eval(&self, jobs: &SharedJobs) -> anyhow::Result<Vec<String>> {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn eval(&self, jobs: &SharedJobs, ns: &mut NameSpace) -> anyhow::Result<Vec<String>> {
+        self.eval_with(jobs, ns, false)
+    }
+
+    // Like `eval`, but unquoted words are also split into lines, so that
+    // `for line in (cat file)` iterates over the lines.
+    pub fn eval_lines(&self, jobs: &SharedJobs, ns: &mut NameSpace) -> anyhow::Result<Vec<String>> {
+        self.eval_with(jobs, ns, true)
+    }
+
+    fn eval_with(
+        &self,
+        jobs: &SharedJobs,
+        ns: &mut NameSpace,
+        lines: bool,
+    ) -> anyhow::Result<Vec<String>> {
         let mut res = Vec::new();
         for arg in self.0.iter() {
-            match arg {
-                Arg::Normal(s) => res.push(s.eval(jobs)?),
-                Arg::Expand(s) => {
-                    for i in s.eval(jobs)?.split_whitespace() {
-                        res.push(i.to_string());
-                    }
-                }
-            }
+            res.extend(match arg {
+                Arg::Normal(s) if lines => word::expand(s, Split::Lines, jobs, ns)?,
+                Arg::Normal(s) => word::expand(s, Split::None, jobs, ns)?,
+                Arg::Expand(s) => word::expand(s, Split::Whitespace, jobs, ns)?,
+            });
         }
         Ok(res)
     }
 }
 
+impl From<Vec<ParseArg>> for Args {
+    fn from(args: Vec<ParseArg>) -> Self {
+        Self(
+            args.into_iter()
+                .filter_map(|arg| match arg {
+                    ParseArg::Arg(s) => Some(Arg::Normal(s)),
+                    ParseArg::ExpandArg(s) => Some(Arg::Expand(s)),
+                    ParseArg::Redirect(_) => None,
+                })
+                .collect(),
+        )
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 enum Arg {
     Normal(SpecialStr),
@@ -42,11 +121,20 @@ enum Arg {
 
 impl From<ParseCmd> for External {
     fn from(cmd: ParseCmd) -> External {
+        let source = cmd.to_string().trim_end_matches(" &").to_string();
+        let stage = ParseCmd {
+            pipe: None,
+            bg: false,
+            ..cmd.clone()
+        }
+        .to_string();
         let ParseCmd {
+            assigns,
             name,
             args: arg_reds,
             pipe,
             bg,
+            cond,
         } = cmd;
 
         let mut args = Vec::new();
@@ -69,78 +157,311 @@ impl From<ParseCmd> for External {
         let reds = Redirects::new(reds);
         let pipe = pipe.map(|pipe| Box::new(Self::from(*pipe)));
         Self {
+            assigns,
             name,
             args,
             reds,
             pipe,
             bg,
+            cond,
+            source,
+            stage,
         }
     }
 }
 
 impl External {
-    pub fn eval(&self, jobs: &SharedJobs) -> anyhow::Result<()> {
-        let child = self.child(jobs, false)?;
+    // Starts the pipeline with the last stage as the job, and returns the
+    // other stages of a foreground pipeline to be waited for after it.
+    pub fn eval(&self, jobs: &SharedJobs, ns: &mut NameSpace) -> anyhow::Result<Vec<Stage>> {
+        let mut children = self.spawn(jobs, ns, None, false)?.0;
+        let child = match children.pop() {
+            Some(Stage::Child(child)) => child,
+            _ => anyhow::bail!("No process is spawned."),
+        };
         jobs.with(|jobs| {
             if self.bg {
                 let (id, pid) = jobs.new_bg(child.id() as i32)?;
+                jobs.set_label(pid, self.source.clone());
                 println!("Job %{} ({}) has started.", id, pid);
             } else {
                 jobs.new_fg(child.id() as i32)?;
             }
             Ok(())
-        })
+        })?;
+        Ok(if self.bg { Vec::new() } else { children })
     }
 
-    pub fn output(&self, jobs: &SharedJobs) -> anyhow::Result<String> {
-        let child = self.child(jobs, true)?;
-        let output = child.wait_with_output()?;
-        Ok(String::from_utf8(output.stdout)?)
+    // Captures the stdout, with the status of each stage.
+    pub fn output(
+        &self,
+        jobs: &SharedJobs,
+        ns: &mut NameSpace,
+    ) -> anyhow::Result<(String, Vec<i32>)> {
+        use std::io::Read;
+
+        let (mut children, stdout) = self.spawn(jobs, ns, None, true)?;
+        let mut buf = Vec::new();
+        if let Some(stdout) = stdout {
+            // Reads one more byte than the limit to know it is exceeded.
+            let limit = ns.opts().output_limit;
+            let max = limit.map_or(u64::MAX, |limit| limit as u64 + 1);
+            stdout.take(max).read_to_end(&mut buf)?;
+            if let Some(limit) = limit.filter(|limit| buf.len() > *limit) {
+                for child in children.iter_mut() {
+                    if let Stage::Child(child) = child {
+                        child.kill().ok();
+                        child.wait().ok();
+                    }
+                }
+                anyhow::bail!("The output exceeded the limit ({} bytes).", limit);
+            }
+        }
+        let codes = children
+            .into_iter()
+            .map(wait_code)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok((String::from_utf8(buf)?, codes))
     }
 
-    fn child(&self, jobs: &SharedJobs, output: bool) -> anyhow::Result<Child> {
-        let mut child = Command::new(&self.name.eval(jobs)?);
-        child.args(&self.args.eval(jobs)?);
+    // The values of the assignments before the name, given to the command.
+    pub fn eval_assigns(
+        &self,
+        jobs: &SharedJobs,
+        ns: &mut NameSpace,
+    ) -> anyhow::Result<Vec<(String, String)>> {
+        self.assigns
+            .iter()
+            .map(|(key, value)| {
+                let value = value.eval(jobs, ns)?;
+                ns.check_writable(key)?;
+                Ok((key.clone(), value))
+            })
+            .collect()
+    }
 
-        let heredoc = self
-            .reds
-            .redirect(&mut child, jobs, false, output || self.pipe.is_some())?;
+    // The name alone, if the command has nothing else.
+    pub fn word(&self) -> Option<&SpecialStr> {
+        if self.assigns.is_empty()
+            && self.args.0.is_empty()
+            && self.reds.is_empty()
+            && self.pipe.is_none()
+            && !self.bg
+            && self.cond.is_none()
+        {
+            Some(&self.name)
+        } else {
+            None
+        }
+    }
 
-        let mut child = child.spawn()?;
+    // Replaces the name with the words of an alias, before the arguments.
+    pub fn aliased(&self, value: &str) -> Option<Self> {
+        let mut words = value
+            .split_whitespace()
+            .map(|word| SpecialStr::from(word.to_string()));
+        let name = words.next()?;
+        let mut args = words.map(Arg::Normal).collect::<Vec<_>>();
+        args.extend(self.args.0.iter().cloned());
+        Some(Self {
+            name,
+            args: Args(args),
+            ..self.clone()
+        })
+    }
 
-        if let Some(s) = heredoc {
-            use std::io::Write;
-            child.stdin.take().unwrap().write_all(&s)?;
+    // Splits the pipeline into the stages before the last one and the last one.
+    pub fn split_last(&self) -> (Option<Self>, &Self) {
+        match &self.pipe {
+            Some(pipe) => {
+                let (head, last) = pipe.split_last();
+                let first = Self {
+                    pipe: head.map(Box::new),
+                    ..self.clone()
+                };
+                (Some(first), last)
+            }
+            None => (None, self),
         }
+    }
 
-        if let Some(pipe) = &self.pipe {
-            pipe.pipe_from(child, jobs, output)
-        } else {
-            Ok(child)
+    pub fn spawn(
+        &self,
+        jobs: &SharedJobs,
+        ns: &mut NameSpace,
+        stdin: Option<File>,
+        output: bool,
+    ) -> anyhow::Result<(Vec<Stage>, Option<File>)> {
+        let name = self.name.eval(jobs, ns)?;
+        if ns.get_proc(&name).is_some() || BuiltinKind::lookup(&name, ns).is_some() {
+            return self.spawn_builtin(jobs, ns, stdin, output);
         }
-    }
 
-    fn pipe_from(&self, other: Child, jobs: &SharedJobs, output: bool) -> anyhow::Result<Child> {
-        let mut child = Command::new(&self.name.eval(jobs)?);
-        child.args(&self.args.eval(jobs)?);
+        let path = ns.get_var("PATH");
+        let mut cmd = match ns.paths_mut().resolve(&name, path.as_deref(), search_path) {
+            Some(path) if !name.contains('/') => {
+                use std::os::unix::process::CommandExt;
+                let mut cmd = Command::new(path);
+                cmd.arg0(&name);
+                cmd
+            }
+            _ => Command::new(&name),
+        };
+        let args = self.args.eval(jobs, ns)?;
+        let vars = self.eval_assigns(jobs, ns)?;
+        super::trace_command(&vars, &name, &args, jobs, ns);
+        cmd.args(&args);
+        ns.apply_env(&mut cmd);
+        cmd.envs(vars);
 
-        let heredoc = self
+        let red = self
             .reds
-            .redirect(&mut child, jobs, true, output || self.pipe.is_some())?;
+            .redirect(&mut cmd, jobs, ns, stdin, output || self.pipe.is_some())?;
 
-        child.stdin(Stdio::from(other.stdout.unwrap()));
+        let res = cmd.spawn();
+        // Close our copies of the descriptors given to the child.
+        drop(cmd);
+        let mut child = match res {
+            Ok(child) => child,
+            // The stages after it still run, reading nothing from it.
+            Err(e) if self.pipe.is_some() => {
+                let e = spawn_error(&name, e);
+                eprintln!("{}", crate::session::color::error(format!("tish: {}", e)));
+                return self.spawn_rest(Stage::Done(e.1), jobs, ns, red.stdout, output);
+            }
+            Err(e) => return Err(spawn_error(&name, e).into()),
+        };
 
-        let mut child = child.spawn()?;
+        if let Some(s) = red.heredoc {
+            redirect::feed(child.stdin.take().unwrap(), s);
+        }
 
-        if let Some(s) = heredoc {
-            use std::io::Write;
-            child.stdin.take().unwrap().write_all(&s)?;
+        self.spawn_rest(Stage::Child(child), jobs, ns, red.stdout, output)
+    }
+
+    // Runs a builtin or a function as a stage in a subshell. One followed by
+    // another stage runs in a child process writing to the pipe as it goes, so
+    // the next stage can end it by exiting early. The last one runs in the
+    // shell, with its output collected if it is wanted.
+    fn spawn_builtin(
+        &self,
+        jobs: &SharedJobs,
+        ns: &mut NameSpace,
+        stdin: Option<File>,
+        output: bool,
+    ) -> anyhow::Result<(Vec<Stage>, Option<File>)> {
+        let stage = super::Command(Self {
+            pipe: None,
+            bg: false,
+            ..self.clone()
+        });
+        if self.pipe.is_some() {
+            let (r, w) = redirect::pipe()?;
+            let child = fork::spawn_stage(&Block::Single(stage), &self.stage, ns, stdin, w)?;
+            return self.spawn_rest(Stage::Child(child), jobs, ns, Some(r), output);
         }
 
+        let run = |sub: &mut NameSpace| {
+            match stdin {
+                Some(stdin) => {
+                    redirect::with_stdin(stdin, || stage.eval(jobs, sub)).and_then(|res| res)
+                }
+                None => stage.eval(jobs, sub),
+            }
+            .or_else(|e| exited(e).map(Some))
+        };
+        if !output {
+            let code = subshell(ns, run)?.unwrap_or(0);
+            return Ok((vec![Stage::Done(code)], None));
+        }
+
+        let (code, stdout) = redirect::capture(|| subshell(ns, run))?;
+        let code = code?.unwrap_or(0);
+        Ok((vec![Stage::Done(code)], Some(stdout)))
+    }
+
+    // Spawns the stages after `stage`, which writes to `stdout`.
+    fn spawn_rest(
+        &self,
+        stage: Stage,
+        jobs: &SharedJobs,
+        ns: &mut NameSpace,
+        stdout: Option<File>,
+        output: bool,
+    ) -> anyhow::Result<(Vec<Stage>, Option<File>)> {
         if let Some(pipe) = &self.pipe {
-            pipe.pipe_from(child, jobs, output)
+            let (mut children, stdout) = pipe.spawn(jobs, ns, stdout, output)?;
+            children.insert(0, stage);
+            Ok((children, stdout))
         } else {
-            Ok(child)
+            Ok((vec![stage], stdout))
+        }
+    }
+}
+
+// Runs `f` on a copy of the namespace like a subshell, passing only
+// `$status` back. The environment and the working directory belong to the
+// process, so they are put back afterwards. `exit` in it fails with
+// `SubshellExit`, which `exited` takes as the status.
+pub fn subshell<T, F: FnOnce(&mut NameSpace) -> T>(ns: &mut NameSpace, f: F) -> T {
+    let mut sub = ns.clone();
+    sub.set_subshell(true);
+    let env = std::env::vars().collect();
+    let cwd = std::env::current_dir().ok();
+    let res = f(&mut sub);
+    restore(&env, cwd);
+    if let Some(code) = sub.get_var("status") {
+        ns.push_var("status", code);
+    }
+    res
+}
+
+// The code `exit` has ended a subshell with, or the error.
+pub fn exited(e: anyhow::Error) -> anyhow::Result<i32> {
+    match e.downcast::<SubshellExit>() {
+        Ok(SubshellExit(code)) => Ok(code),
+        Err(e) => Err(e),
+    }
+}
+
+fn restore(env: &BTreeMap<String, String>, cwd: Option<PathBuf>) {
+    for (key, _) in std::env::vars() {
+        if !env.contains_key(&key) {
+            std::env::remove_var(key);
         }
     }
+    for (key, value) in env {
+        std::env::set_var(key, value);
+    }
+    if let Some(cwd) = cwd {
+        std::env::set_current_dir(cwd).ok();
+    }
+}
+
+pub fn wait_code(stage: Stage) -> anyhow::Result<i32> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let mut child = match stage {
+        Stage::Child(child) => child,
+        Stage::Done(code) => return Ok(code),
+    };
+    let status = child.wait()?;
+    Ok(status
+        .code()
+        .unwrap_or_else(|| 128 + status.signal().unwrap_or(0)))
+}
+
+// Entries of `$PATH` which are directories or can't be executed are skipped
+// for the later ones.
+pub fn search_path(name: &str, path: Option<&str>) -> Option<PathBuf> {
+    use nix::unistd::{access, AccessFlags};
+
+    if name.contains('/') {
+        let path = PathBuf::from(name);
+        return if path.is_file() { Some(path) } else { None };
+    }
+
+    std::env::split_paths(path?)
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file() && access(path, AccessFlags::X_OK).is_ok())
 }
@@ -0,0 +1,303 @@
+use crate::parse::{decode_escape, Escape};
+use anyhow::Context;
+use std::convert::TryFrom;
+
+// Formats `args` like printf(1). The format is reused while arguments remain,
+// and missing arguments are taken as empty strings or zeros. Nothing is
+// printed after `\c`.
+pub fn printf<T: AsRef<str>>(fmt: &str, args: &[T]) -> anyhow::Result<String> {
+    let fmt = fmt.chars().collect::<Vec<_>>();
+    let mut args = args.iter().map(|arg| arg.as_ref());
+    let mut res = String::new();
+    loop {
+        match format_once(&fmt, &mut args, &mut res)? {
+            Some(true) if args.len() > 0 => continue,
+            _ => break,
+        }
+    }
+    Ok(res)
+}
+
+// Whether an argument is consumed, or `None` if it is stopped by `\c`.
+fn format_once<'a, I: Iterator<Item = &'a str>>(
+    fmt: &[char],
+    args: &mut I,
+    res: &mut String,
+) -> anyhow::Result<Option<bool>> {
+    let mut consumed = false;
+    let mut i = 0;
+    while i < fmt.len() {
+        match fmt[i] {
+            '\\' => {
+                let (c, len) = escape(&fmt[i + 1..]);
+                match c {
+                    Some(c) => res.push_str(&c),
+                    None => return Ok(None),
+                }
+                i += len + 1;
+            }
+            '%' if fmt.get(i + 1) == Some(&'%') => {
+                res.push('%');
+                i += 2;
+            }
+            '%' => {
+                let (spec, len) = Spec::parse(&fmt[i + 1..], args, &mut consumed)?;
+                let arg = args.next();
+                consumed |= arg.is_some();
+                let (s, stopped) = spec.format(arg.unwrap_or(""))?;
+                res.push_str(&s);
+                if stopped {
+                    return Ok(None);
+                }
+                i += len + 1;
+            }
+            c => {
+                res.push(c);
+                i += 1;
+            }
+        }
+    }
+    Ok(Some(consumed))
+}
+
+// Returns the character(s) for an escape sequence and the length consumed,
+// or `None` for `\c`. Unknown ones are left as they are.
+fn escape(seq: &[char]) -> (Option<String>, usize) {
+    match decode_escape(seq) {
+        Some((Escape::Char(c), len)) => (Some(String::from(c)), len),
+        Some((Escape::Stop, len)) => (None, len),
+        None => match seq.first() {
+            Some(c) => (Some(format!("\\{}", c)), 1),
+            None => (Some(String::from("\\")), 0),
+        },
+    }
+}
+
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    width: usize,
+    precision: Option<usize>,
+    conv: char,
+}
+
+impl Spec {
+    // A width or a precision of `*` is taken from `args`, where a negative
+    // width aligns to the left.
+    fn parse<'a, I: Iterator<Item = &'a str>>(
+        fmt: &[char],
+        args: &mut I,
+        consumed: &mut bool,
+    ) -> anyhow::Result<(Self, usize)> {
+        let mut spec = Self {
+            left: false,
+            zero: false,
+            plus: false,
+            width: 0,
+            precision: None,
+            conv: 's',
+        };
+
+        let mut i = 0;
+        while let Some(&c) = fmt.get(i) {
+            match c {
+                '-' => spec.left = true,
+                '0' => spec.zero = true,
+                '+' => spec.plus = true,
+                ' ' | '#' => (),
+                _ => break,
+            }
+            i += 1;
+        }
+        if fmt.get(i) == Some(&'*') {
+            let width = star(args, consumed)?;
+            spec.left |= width < 0;
+            spec.width = width.unsigned_abs() as usize;
+            i += 1;
+        }
+        while let Some(d) = fmt.get(i).and_then(|c| c.to_digit(10)) {
+            spec.width = spec.width * 10 + d as usize;
+            i += 1;
+        }
+        if fmt.get(i) == Some(&'.') {
+            i += 1;
+            if fmt.get(i) == Some(&'*') {
+                // A negative precision is taken as none.
+                let p = star(args, consumed)?;
+                spec.precision = usize::try_from(p).ok();
+                i += 1;
+            } else {
+                let mut precision = 0;
+                while let Some(d) = fmt.get(i).and_then(|c| c.to_digit(10)) {
+                    precision = precision * 10 + d as usize;
+                    i += 1;
+                }
+                spec.precision = Some(precision);
+            }
+        }
+
+        spec.conv = *fmt.get(i).context("Missing a format character.")?;
+        Ok((spec, i + 1))
+    }
+
+    // The text, and whether `\c` in `%b` stops the output.
+    fn format(&self, arg: &str) -> anyhow::Result<(String, bool)> {
+        let mut stopped = false;
+        let body = match self.conv {
+            's' => match self.precision {
+                Some(p) => arg.chars().take(p).collect(),
+                None => arg.to_string(),
+            },
+            'b' => {
+                let chars = arg.chars().collect::<Vec<_>>();
+                let mut s = String::new();
+                let mut i = 0;
+                while i < chars.len() {
+                    if chars[i] == '\\' {
+                        let (c, len) = escape(&chars[i + 1..]);
+                        match c {
+                            Some(c) => s.push_str(&c),
+                            None => {
+                                stopped = true;
+                                break;
+                            }
+                        }
+                        i += len + 1;
+                    } else {
+                        s.push(chars[i]);
+                        i += 1;
+                    }
+                }
+                s
+            }
+            'c' => arg.chars().take(1).collect(),
+            // Quoted to be read back by the shell.
+            'q' => super::quote_word(arg),
+            'd' | 'i' => {
+                let n = int(arg)?;
+                if self.plus && n >= 0 {
+                    format!("+{}", n)
+                } else {
+                    n.to_string()
+                }
+            }
+            'u' => (int(arg)? as u64).to_string(),
+            'x' => format!("{:x}", int(arg)?),
+            'X' => format!("{:X}", int(arg)?),
+            'o' => format!("{:o}", int(arg)?),
+            'f' | 'F' => {
+                let n = float(arg)?;
+                let s = format!("{:.*}", self.precision.unwrap_or(6), n);
+                if self.plus && n >= 0.0 {
+                    format!("+{}", s)
+                } else {
+                    s
+                }
+            }
+            c => anyhow::bail!("Unknown format character: {}", c),
+        };
+
+        let len = body.chars().count();
+        if len >= self.width {
+            return Ok((body, stopped));
+        }
+        let pad = self.width - len;
+        let body = if self.left {
+            format!("{}{}", body, " ".repeat(pad))
+        } else if self.zero && !matches!(self.conv, 's' | 'b' | 'c') {
+            let (sign, digits) = match body.strip_prefix(|c| c == '-' || c == '+') {
+                Some(digits) => (&body[..1], digits),
+                None => ("", body.as_str()),
+            };
+            format!("{}{}{}", sign, "0".repeat(pad), digits)
+        } else {
+            format!("{}{}", " ".repeat(pad), body)
+        };
+        Ok((body, stopped))
+    }
+}
+
+fn star<'a, I: Iterator<Item = &'a str>>(args: &mut I, consumed: &mut bool) -> anyhow::Result<i64> {
+    let arg = args.next();
+    *consumed |= arg.is_some();
+    int(arg.unwrap_or(""))
+}
+
+fn int(arg: &str) -> anyhow::Result<i64> {
+    let arg = arg.trim();
+    if arg.is_empty() {
+        return Ok(0);
+    }
+    if let Some(c) = arg.strip_prefix('\'').or_else(|| arg.strip_prefix('"')) {
+        return Ok(c.chars().next().map_or(0, |c| c as i64));
+    }
+    let (neg, digits) = match arg.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, arg.strip_prefix('+').unwrap_or(arg)),
+    };
+    let n = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        i64::from_str_radix(hex, 16)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        i64::from_str_radix(&digits[1..], 8)
+    } else {
+        digits.parse::<i64>()
+    }
+    .with_context(|| format!("{}: invalid number", arg))?;
+    Ok(if neg { -n } else { n })
+}
+
+fn float(arg: &str) -> anyhow::Result<f64> {
+    let arg = arg.trim();
+    if arg.is_empty() {
+        return Ok(0.0);
+    }
+    arg.parse::<f64>()
+        .with_context(|| format!("{}: invalid number", arg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions() {
+        assert_eq!(printf("%5s|%-5s|", &["ab", "cd"]).unwrap(), "   ab|cd   |");
+        assert_eq!(
+            printf("%05d|%+d|%x|%o", &["-42", "7", "255", "8"]).unwrap(),
+            "-0042|+7|ff|10"
+        );
+        assert_eq!(printf("%.2s|%c|%%", &["abc", "xyz"]).unwrap(), "ab|x|%");
+        assert_eq!(printf("%d", &["'A"]).unwrap(), "65");
+        assert!(printf("%d", &["x"]).is_err());
+        assert!(printf("%z", &["x"]).is_err());
+    }
+
+    #[test]
+    fn reused_format() {
+        assert_eq!(printf("%s=%d\n", &["a", "1", "b"]).unwrap(), "a=1\nb=0\n");
+        assert_eq!(printf("x\n", &["a"]).unwrap(), "x\n");
+        assert_eq!(printf("%b|%s", &["a\\cb", "c"]).unwrap(), "a");
+        assert_eq!(printf("a\\cb", &[] as &[&str]).unwrap(), "a");
+    }
+
+    #[test]
+    fn star() {
+        assert_eq!(printf("%*d|", &["4", "7"]).unwrap(), "   7|");
+        assert_eq!(printf("%*s|", &["-3", "a"]).unwrap(), "a  |");
+        assert_eq!(printf("%.*f", &["1", "2.25"]).unwrap(), "2.2");
+        assert_eq!(printf("%*d\n", &["2", "1", "3", "2"]).unwrap(), " 1\n  2\n");
+    }
+
+    #[test]
+    fn quote() {
+        assert_eq!(
+            printf("%q %q %q", &["abc", "a b", "x$y"]).unwrap(),
+            "abc \"a b\" \"x\\$y\""
+        );
+        assert_eq!(printf("%q", &[""]).unwrap(), "\"\"");
+    }
+}
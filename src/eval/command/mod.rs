@@ -1,14 +1,18 @@
 mod builtin;
 mod external;
+mod format;
+mod json;
 mod redirect;
 
-pub use builtin::{Builtin, BuiltinKind};
-pub use external::External;
-pub use redirect::Redirects;
+pub use builtin::{read, Builtin, BuiltinKind, SubshellExit, UsageError};
+pub use external::{exited, subshell, wait_code, Args, External, SpawnError, Stage};
+pub use redirect::{pipe, with_stdin, RedirectError, Redirects};
 
-use super::NameSpace;
+use super::cond::CondError;
+use super::{NameSpace, ReadonlyError};
 use crate::job::SharedJobs;
-use crate::parse::Command as ParseCmd;
+use crate::parse::{Command as ParseCmd, SpecialStr};
+use std::collections::HashSet;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Command(External);
@@ -20,22 +24,339 @@ impl From<ParseCmd> for Command {
 }
 
 impl Command {
-    pub fn eval(&self, jobs: &SharedJobs, ns: &mut NameSpace) -> anyhow::Result<()> {
-        let name = self.0.name.eval(jobs)?;
-        let proc = ns.get_proc(&name);
+    pub fn eval(&self, jobs: &SharedJobs, ns: &mut NameSpace) -> anyhow::Result<Option<i32>> {
+        // A command which has returned after the warning of `exit` is not
+        // `exit` leaving the shell, so the next `exit` warns again.
+        let warned = ns.exit_warned();
+        let res = match self.expand_alias(jobs, ns)? {
+            Some(cmd) => cmd.eval_resolved(jobs, ns),
+            None => self.eval_resolved(jobs, ns),
+        };
+        if warned {
+            ns.set_exit_warned(false);
+        }
+        match res {
+            Err(e) if e.is::<RedirectError>() || e.is::<ReadonlyError>() => {
+                eprintln!("{}", crate::session::color::error(format!("tish: {}", e)));
+                Ok(Some(1))
+            }
+            Err(e) if e.is::<SpawnError>() => {
+                eprintln!("{}", crate::session::color::error(format!("tish: {}", e)));
+                Ok(e.downcast_ref::<SpawnError>().map(|e| e.1))
+            }
+            res => res,
+        }
+    }
+
+    // Each alias is expanded at most once, so `alias a = b; alias b = a`
+    // runs `a` itself.
+    fn expand_alias(&self, jobs: &SharedJobs, ns: &mut NameSpace) -> anyhow::Result<Option<Self>> {
+        if self.0.name.is_quoted() {
+            return Ok(None);
+        }
+
+        let mut name = self.0.name.eval(jobs, ns)?;
+        let mut visited = HashSet::new();
+        let mut cmd = None;
+        while let Some(value) = ns.aliases().get(&name) {
+            if !visited.insert(name) {
+                break;
+            }
+            let next = match cmd.as_ref().unwrap_or(&self.0).aliased(value) {
+                Some(next) => next,
+                None => break,
+            };
+            name = next.name.eval(jobs, ns)?;
+            cmd = Some(next);
+        }
+        Ok(cmd.map(Self))
+    }
+
+    fn eval_resolved(&self, jobs: &SharedJobs, ns: &mut NameSpace) -> anyhow::Result<Option<i32>> {
+        if let Some(cond) = &self.0.cond {
+            // A bad regex or integer is reported with the status 2, as a
+            // false condition is with 1.
+            let mut words = vec![String::from("[[")];
+            let res = super::cond::test(cond, &mut words, jobs, ns);
+            if ns.opts().xtrace {
+                words.push(String::from("]]"));
+                trace(&words, jobs, ns);
+            }
+            return Ok(Some(match res {
+                Ok(true) => 0,
+                Ok(false) => 1,
+                Err(e) if e.is::<CondError>() => {
+                    eprintln!("{}", crate::session::color::error(format!("tish: {}", e)));
+                    2
+                }
+                Err(e) => return Err(e),
+            }));
+        }
+
+        // Assignments alone take the status of the last substitution in them.
+        if !self.0.assigns.is_empty() && self.0.name == SpecialStr::new() {
+            ns.push_var("status", "0");
+            for (key, value) in self.0.assigns.iter() {
+                let value = value.eval(jobs, ns)?;
+                if ns.opts().xtrace {
+                    trace(&[format!("{}={}", key, quote_word(&value))], jobs, ns);
+                }
+                ns.check_writable(key)?;
+                ns.push_var(key, value);
+            }
+            let code = ns.get_var("status").and_then(|code| code.parse().ok());
+            return Ok(Some(code.unwrap_or(0)));
+        }
+
+        // Each stage of a pipeline runs in a subshell, whatever it is, so only
+        // `$status` comes back: `echo hi | read x`, `echo hi | { read x }` and
+        // `echo hi | read x | cat` all leave `x` as it was. A builtin or a
+        // function at the end runs on a copy of the namespace reading from
+        // the pipe, and isn't a job to wait for.
+        if let (Some(_), last) = self.0.split_last() {
+            let name = last.name.eval(jobs, ns)?;
+            if ns.get_proc(&name).is_some() || BuiltinKind::lookup(&name, ns).is_some() {
+                let codes = self
+                    .0
+                    .spawn(jobs, ns, None, false)?
+                    .0
+                    .into_iter()
+                    .map(wait_code)
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                return Ok(Some(pipeline_code(&codes, ns)));
+            }
+        }
+
+        // The stages of a pipeline are spawned together below.
+        let name = self.0.name.eval(jobs, ns)?;
+        let single = self.0.pipe.is_none();
+        let proc = ns.get_proc(&name).filter(|_| single);
         if let Some(proc) = proc {
-            return proc.eval_with_args(&name, self.0.args.eval(jobs)?, jobs, ns);
+            let args = self.0.args.eval(jobs, ns)?;
+            let vars = self.0.eval_assigns(jobs, ns)?;
+            trace_command(&vars, &name, &args, jobs, ns);
+            return self
+                .0
+                .reds
+                .with(jobs, ns, |ns| {
+                    ns.with_env(&vars, |ns| proc.eval_with_args(&name, args, jobs, ns))
+                })?
+                .map(Some);
         }
 
-        let kind = BuiltinKind::new(name);
+        let kind = BuiltinKind::lookup(&name, ns).filter(|_| single);
         if let Some(kind) = kind {
-            return Builtin::new(kind, self.0.args.eval(jobs)?).eval(jobs, ns);
+            let args = self.0.args.eval(jobs, ns)?;
+            let vars = self.0.eval_assigns(jobs, ns)?;
+            trace_command(&vars, &name, &args, jobs, ns);
+            let builtin = Builtin::new(kind, args);
+            return self
+                .0
+                .reds
+                .with(jobs, ns, |ns| {
+                    ns.with_env(&vars, |ns| builtin.eval(jobs, ns))
+                })?
+                .map(|code| Some(pipeline_code(&[code], ns)));
+        }
+
+        // With `autocd`, a directory alone which isn't a command is changed to.
+        if single
+            && ns.opts().autocd
+            && self.0.args.is_empty()
+            && std::path::Path::new(&name).is_dir()
+            && external::search_path(&name, ns.get_var("PATH").as_deref()).is_none()
+        {
+            return self
+                .0
+                .reds
+                .with(jobs, ns, |ns| {
+                    Builtin::new(BuiltinKind::Cd, vec![name]).eval(jobs, ns)
+                })?
+                .map(Some);
+        }
+
+        let heads = self.0.eval(jobs, ns)?;
+        let code = match jobs.wait_fg()? {
+            Some(status) if status.stopped() => return Ok(Some(status.code())),
+            Some(status) => status.code(),
+            None => return Ok(None),
+        };
+        let mut codes = heads
+            .into_iter()
+            .map(wait_code)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        codes.push(code);
+        Ok(Some(pipeline_code(&codes, ns)))
+    }
+
+    // Starts the pipeline with its stdout to be read, for a compound command
+    // piped after it.
+    pub fn spawn_output(
+        &self,
+        jobs: &SharedJobs,
+        ns: &mut NameSpace,
+    ) -> anyhow::Result<(Vec<Stage>, Option<std::fs::File>)> {
+        self.0.spawn(jobs, ns, None, true)
+    }
+
+    // A bare word is a condition by itself like `if $ok`, and anything else
+    // is run to be judged by its exit status like `while read line`.
+    pub fn test(&self, jobs: &SharedJobs, ns: &mut NameSpace) -> anyhow::Result<bool> {
+        if let Some(word) = self.0.word() {
+            match word.eval(jobs, ns)?.to_lowercase().as_str() {
+                "1" | "y" | "yes" | "true" => return Ok(true),
+                "" | "0" | "n" | "no" | "false" => return Ok(false),
+                _ => (),
+            }
+        }
+
+        // A background job leaves `$status` as it was, as in a block.
+        let code = match self.eval(jobs, ns)? {
+            Some(code) => code,
+            None => return Ok(ns.get_var("status").is_none_or(|code| code == "0")),
+        };
+        ns.push_var("status", code.to_string());
+        Ok(code == 0)
+    }
+
+    // Captures the stdout with the status, which is also set to `$status`
+    // like the command has run. The stderr isn't captured but goes to the
+    // one of the shell.
+    pub fn output(&self, jobs: &SharedJobs, ns: &mut NameSpace) -> anyhow::Result<(String, i32)> {
+        let (out, codes) = self.0.output(jobs, ns)?;
+        let code = pipeline_code(&codes, ns);
+        ns.push_var("status", code.to_string());
+        Ok((out, code))
+    }
+}
+
+// With `set -x`, prints the expanded words of a command to the stderr after
+// `$PS4`, whose first character is repeated for each function being run.
+// Variables in `$PS4` are expanded. It defaults to `+($0:$LINENO) ` showing
+// where the command is, or `+ ` where no line is counted.
+fn trace(words: &[String], jobs: &SharedJobs, ns: &mut NameSpace) {
+    use combine::Parser;
+
+    let ps4 = ns.get_var("PS4").unwrap_or_else(|| {
+        String::from(if ns.line() > 0 {
+            "+($0:$LINENO) "
+        } else {
+            "+ "
+        })
+    });
+    let mut quoted = String::from("\"");
+    for c in ps4.chars() {
+        if "\\\"(".contains(c) {
+            quoted.push('\\');
         }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    let prefix = match SpecialStr::parse().parse(quoted.as_str()) {
+        Ok((s, "")) => s.eval(jobs, ns).unwrap_or(ps4),
+        _ => ps4,
+    };
+
+    let level = prefix
+        .chars()
+        .next()
+        .map(|c| c.to_string().repeat(ns.func_depth()))
+        .unwrap_or_default();
+    eprintln!("{}{}{}", level, prefix, words.join(" "));
+}
+
+fn trace_command(
+    vars: &[(String, String)],
+    name: &str,
+    args: &[String],
+    jobs: &SharedJobs,
+    ns: &mut NameSpace,
+) {
+    if !ns.opts().xtrace {
+        return;
+    }
+    let mut words = vars
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, quote_word(value)))
+        .collect::<Vec<_>>();
+    words.push(quote_word(name));
+    words.extend(args.iter().map(|arg| quote_word(arg)));
+    trace(&words, jobs, ns);
+}
+
+// Traces the header of a compound command, as `for c in a b` each time
+// around, with the expanded `items` between `head` and `tail`.
+pub fn trace_header(
+    head: &str,
+    items: &[String],
+    tail: &str,
+    jobs: &SharedJobs,
+    ns: &mut NameSpace,
+) {
+    if !ns.opts().xtrace {
+        return;
+    }
+    let mut words = vec![head.to_string()];
+    words.extend(items.iter().map(|item| quote_word(item)));
+    if !tail.is_empty() {
+        words.push(tail.to_string());
+    }
+    trace(&words, jobs, ns);
+}
+
+// Quotes a word only when it would be read back differently.
+pub fn quote_word(word: &str) -> String {
+    let plain = !word.is_empty()
+        && !word
+            .chars()
+            .any(|c| c.is_whitespace() || "\"'\\$()|&;<>#*?[]{}~`".contains(c));
+    if plain {
+        word.to_string()
+    } else {
+        SpecialStr::from(word.to_string()).to_string()
+    }
+}
+
+// The status of the last stage, or with `pipefail` the last failed one.
+// Each of them is kept in `$PIPESTATUS`.
+fn pipeline_code(codes: &[i32], ns: &mut NameSpace) -> i32 {
+    ns.push_array(
+        "PIPESTATUS",
+        codes.iter().map(|code| code.to_string()).collect(),
+    );
+    let last = codes.last().copied().unwrap_or(0);
+    if ns.opts().pipefail {
+        codes
+            .iter()
+            .rev()
+            .find(|code| **code != 0)
+            .copied()
+            .unwrap_or(0)
+    } else {
+        last
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        self.0.eval(jobs)
+    #[test]
+    fn pipefail() {
+        let mut ns = NameSpace::default();
+        assert_eq!(pipeline_code(&[1, 2, 0], &mut ns), 0);
+        ns.opts_mut().pipefail = true;
+        assert_eq!(pipeline_code(&[1, 2, 0], &mut ns), 2);
+        assert_eq!(pipeline_code(&[0, 0], &mut ns), 0);
+        assert_eq!(ns.get_var("PIPESTATUS[1]").as_deref(), Some("0"));
+        assert_eq!(ns.get_var("PIPESTATUS[2]"), None);
     }
 
-    pub fn output(&self, jobs: &SharedJobs) -> anyhow::Result<String> {
-        self.0.output(jobs)
+    #[test]
+    fn quoted_words() {
+        assert_eq!(quote_word("abc"), "abc");
+        assert_eq!(quote_word(""), "\"\"");
+        assert_eq!(quote_word("a b"), "\"a b\"");
+        assert_eq!(quote_word("$x"), "\"\\$x\"");
     }
 }
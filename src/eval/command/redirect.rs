@@ -1,174 +1,336 @@
+use crate::eval::word::{self, Split};
+use crate::eval::NameSpace;
 use crate::job::SharedJobs;
 use crate::parse::{RedKind, RedTarget, Redirect, SpecialStr};
+use anyhow::Context;
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::unistd;
 use std::fs::{File, OpenOptions};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::process::{Command, Stdio};
 
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub struct Redirects(RedirectsInner);
+pub struct Redirects(Vec<Redirect>);
+
+// A redirect which can't be opened, where the command doesn't run and fails
+// with the status 1.
+#[derive(Debug)]
+pub struct RedirectError(String);
+
+impl std::fmt::Display for RedirectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for RedirectError {}
+
+// Reports the reason without "(os error N)", like `nodir/out: No such file or
+// directory`.
+fn open_error(path: &str, e: std::io::Error) -> anyhow::Error {
+    let reason = match e.raw_os_error() {
+        Some(errno) => nix::errno::Errno::from_i32(errno).desc().to_string(),
+        None => e.to_string(),
+    };
+    RedirectError(format!("{}: {}", path, reason)).into()
+}
+
+pub struct Redirected {
+    pub heredoc: Option<Vec<u8>>,
+    pub stdout: Option<File>,
+}
 
 impl Redirects {
     pub fn new(reds: Vec<Redirect>) -> Self {
-        Self(RedirectsInner::new(reds))
+        Self(reds)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
     }
 
+    // Connects the pipes first and then applies the redirects in the source
+    // order, so `> out 2>&1` and `2>&1 > out` differ like other shells.
     pub fn redirect(
         &self,
         cmd: &mut Command,
         jobs: &SharedJobs,
-        piped_in: bool,
+        ns: &mut NameSpace,
+        piped_in: Option<File>,
         piped_out: bool,
-    ) -> anyhow::Result<Option<Vec<u8>>> {
-        self.0.redirect(cmd, jobs, piped_in, piped_out)
+    ) -> anyhow::Result<Redirected> {
+        let (stdin, stdout, stderr, reader) = self.resolve(jobs, ns, piped_in, piped_out)?;
+        let heredoc = match stdin {
+            Fd::HereDoc(s) => {
+                cmd.stdin(Stdio::piped());
+                Some(s)
+            }
+            stdin => {
+                cmd.stdin(stdin.into_stdio(0)?);
+                None
+            }
+        };
+        cmd.stdout(stdout.into_stdio(1)?);
+        cmd.stderr(stderr.into_stdio(2)?);
+
+        Ok(Redirected {
+            heredoc,
+            stdout: reader,
+        })
     }
-}
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-enum RedirectsInner {
-    Each(Option<RedIn>, Option<RedOut>, Option<RedOut>),
-    Bind(Option<RedIn>, Option<RedOut>),
-}
+    // Runs `f` with the redirects applied to the shell itself, for builtins
+    // and functions.
+    pub fn with<T, F>(&self, jobs: &SharedJobs, ns: &mut NameSpace, f: F) -> anyhow::Result<T>
+    where
+        F: FnOnce(&mut NameSpace) -> T,
+    {
+        use std::io::Write;
 
-fn target2str(target: RedTarget) -> SpecialStr {
-    match target {
-        RedTarget::Stdin => SpecialStr::from(String::from("/dev/stdin")),
-        RedTarget::Stdout => SpecialStr::from(String::from("/dev/stdout")),
-        RedTarget::Stderr => SpecialStr::from(String::from("/dev/stderr")),
-        RedTarget::Null => SpecialStr::from(String::from("/dev/null")),
-        RedTarget::Other(s) => s,
+        if self.is_empty() {
+            return Ok(f(ns));
+        }
+
+        let (stdin, stdout, stderr, _) = self.resolve(jobs, ns, None, false)?;
+        // Every source is taken before any of the descriptors is replaced,
+        // since `2>&1` refers to the stdout before the redirect.
+        let fds = vec![stdin, stdout, stderr]
+            .into_iter()
+            .enumerate()
+            .map(|(own, fd)| Ok((own as RawFd, fd.into_file(own as RawFd)?)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        std::io::stdout().flush().ok();
+        let mut saved = Vec::new();
+        for (own, file) in fds.iter() {
+            if let Some(file) = file {
+                saved.push((
+                    *own,
+                    unistd::dup(*own).context("Failed to duplicate the descriptor.")?,
+                ));
+                unistd::dup2(file.as_raw_fd(), *own)
+                    .context("Failed to duplicate the descriptor.")?;
+            }
+        }
+        drop(fds);
+
+        let res = f(ns);
+
+        std::io::stdout().flush().ok();
+        for (own, fd) in saved {
+            unistd::dup2(fd, own).context("Failed to duplicate the descriptor.")?;
+            unistd::close(fd).context("Failed to close the descriptor.")?;
+        }
+        Ok(res)
     }
-}
 
-impl RedirectsInner {
-    fn new(reds: Vec<Redirect>) -> Self {
-        let mut stdout = None;
-        let mut stderr = None;
-        let mut stdin = None;
-        for red in reds {
+    fn resolve(
+        &self,
+        jobs: &SharedJobs,
+        ns: &mut NameSpace,
+        piped_in: Option<File>,
+        piped_out: bool,
+    ) -> anyhow::Result<(Fd, Fd, Fd, Option<File>)> {
+        let mut stdin = piped_in.map_or(Fd::Inherit(0), Fd::File);
+        let mut stdout = Fd::Inherit(1);
+        let mut stderr = Fd::Inherit(2);
+        let mut reader = None;
+        if piped_out {
+            let (r, w) = pipe()?;
+            stdout = Fd::File(w);
+            reader = Some(r);
+        }
+
+        for red in self.0.iter() {
             match red.kind {
-                RedKind::OverwriteStdout => {
-                    stdout = Some(RedOut::overwrite(red.target));
+                RedKind::OverwriteStdout | RedKind::AppendStdout | RedKind::ForceStdout => {
+                    stdout = open_out(
+                        &red.target,
+                        OutMode::from(&red.kind),
+                        [&stdin, &stdout, &stderr],
+                        jobs,
+                        ns,
+                    )?;
                 }
-                RedKind::AppendStdout => {
-                    stdout = Some(RedOut::append(red.target));
+                RedKind::OverwriteStderr | RedKind::AppendStderr | RedKind::ForceStderr => {
+                    stderr = open_out(
+                        &red.target,
+                        OutMode::from(&red.kind),
+                        [&stdin, &stdout, &stderr],
+                        jobs,
+                        ns,
+                    )?;
                 }
-                RedKind::OverwriteStderr => {
-                    stderr = Some(RedOut::overwrite(red.target));
-                }
-                RedKind::AppendStderr => {
-                    stderr = Some(RedOut::append(red.target));
-                }
-                RedKind::OverwriteBoth => {
-                    stdout = Some(RedOut::overwrite(red.target));
-                    stderr = stdout.clone();
-                }
-                RedKind::AppendBoth => {
-                    stdout = Some(RedOut::append(red.target));
-                    stderr = stdout.clone();
+                RedKind::OverwriteBoth | RedKind::AppendBoth | RedKind::ForceBoth => {
+                    stdout = open_out(
+                        &red.target,
+                        OutMode::from(&red.kind),
+                        [&stdin, &stdout, &stderr],
+                        jobs,
+                        ns,
+                    )?;
+                    stderr = stdout.try_clone()?;
                 }
                 RedKind::Stdin => {
-                    stdin = Some(RedIn {
-                        mode: InMode::Normal,
-                        target: target2str(red.target),
-                    });
+                    stdin = match &red.target {
+                        RedTarget::Stdin => stdin,
+                        RedTarget::Stdout => stdout.try_clone()?,
+                        RedTarget::Stderr => stderr.try_clone()?,
+                        RedTarget::Null => Fd::File(File::open("/dev/null")?),
+                        RedTarget::Other(s) => {
+                            let path = eval_path(s, jobs, ns)?;
+                            Fd::File(File::open(&path).map_err(|e| open_error(&path, e))?)
+                        }
+                    };
                 }
                 RedKind::HereDoc => {
-                    stdin = Some(RedIn {
-                        mode: InMode::HereDoc,
-                        target: target2str(red.target),
-                    });
+                    let target = target2str(red.target.clone());
+                    stdin = Fd::HereDoc(target.eval(jobs, ns)?.into_bytes());
                 }
             }
         }
 
-        if stdout == stderr {
-            Self::Bind(stdin, stdout)
-        } else {
-            Self::Each(stdin, stdout, stderr)
-        }
+        Ok((stdin, stdout, stderr, reader))
     }
+}
 
-    fn redirect(
-        &self,
-        cmd: &mut Command,
-        jobs: &SharedJobs,
-        piped_in: bool,
-        piped_out: bool,
-    ) -> anyhow::Result<Option<Vec<u8>>> {
-        let stdin = match self {
-            Self::Bind(stdin, Some(stdout)) if piped_out => {
-                let err = stdout.mode.option().open(&stdout.target.eval(jobs)?)?;
-                cmd.stdout(Stdio::piped());
-                cmd.stderr(Stdio::from(err));
-                stdin
-            }
-            Self::Bind(stdin, Some(stdout)) => {
-                let out = stdout.mode.option().open(&stdout.target.eval(jobs)?)?;
-                let err = out.try_clone()?;
-                cmd.stdout(Stdio::from(out));
-                cmd.stderr(Stdio::from(err));
-                stdin
-            }
-            Self::Bind(stdin, None) if piped_out => {
-                cmd.stdout(Stdio::piped());
-                stdin
-            }
-            Self::Bind(stdin, None) => stdin,
-            Self::Each(stdin, stdout, stderr) => {
-                if piped_out {
-                    cmd.stdout(Stdio::piped());
-                } else if let Some(stdout) = stdout {
-                    let out = stdout.mode.option().open(&stdout.target.eval(jobs)?)?;
-                    cmd.stdout(Stdio::from(out));
-                }
+pub fn pipe() -> anyhow::Result<(File, File)> {
+    let (r, w) = unistd::pipe2(OFlag::O_CLOEXEC).context("Failed to create a pipe.")?;
+    Ok(unsafe { (File::from_raw_fd(r), File::from_raw_fd(w)) })
+}
 
-                if let Some(stderr) = stderr {
-                    let err = stderr.mode.option().open(&stderr.target.eval(jobs)?)?;
-                    cmd.stderr(Stdio::from(err));
-                }
+// Writes `buf` on another thread, so that a large body doesn't block the
+// shell, and a reader exiting early only ends the writing.
+pub fn feed<W: std::io::Write + Send + 'static>(mut w: W, buf: Vec<u8>) {
+    std::thread::spawn(move || w.write_all(&buf));
+}
 
-                stdin
-            }
-        };
+// Runs `f` with the shell's own stdin replaced by `file`, for builtins and
+// functions reading from a pipe.
+pub fn with_stdin<T, F: FnOnce() -> T>(file: File, f: F) -> anyhow::Result<T> {
+    with_fd(0, file, f)
+}
 
-        let mut s = None;
-        if piped_in {
-            cmd.stdin(Stdio::piped());
-        } else if let Some(stdin) = stdin {
-            let target = stdin.target.eval(jobs)?;
-            match stdin.mode {
-                InMode::Normal => {
-                    cmd.stdin(Stdio::from(File::open(&target)?));
-                }
-                InMode::HereDoc => {
-                    cmd.stdin(Stdio::piped());
-                    s = Some(target.into_bytes());
-                }
-            }
-        }
+// Runs `f` with the shell's own stdout replaced by `file`, for builtins and
+// functions writing to a pipe.
+pub fn with_stdout<T, F: FnOnce() -> T>(file: File, f: F) -> anyhow::Result<T> {
+    use std::io::Write;
+
+    std::io::stdout().flush().ok();
+    with_fd(1, file, || {
+        let res = f();
+        std::io::stdout().flush().ok();
+        res
+    })
+}
+
+// Runs `f` with the shell's own stdout collected, and returns a pipe to read
+// it back from.
+pub fn capture<T, F: FnOnce() -> T>(f: F) -> anyhow::Result<(T, File)> {
+    use std::io::Read;
+
+    let (mut r, w) = pipe()?;
+    let reader = std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        r.read_to_end(&mut buf).map(|_| buf)
+    });
+    let res = with_stdout(w, f)?;
+    let buf = reader.join().expect("The reader thread panicked.")?;
+
+    let (r, w) = pipe()?;
+    feed(w, buf);
+    Ok((res, r))
+}
+
+fn with_fd<T, F: FnOnce() -> T>(own: RawFd, file: File, f: F) -> anyhow::Result<T> {
+    let saved = unistd::dup(own).context("Failed to duplicate the descriptor.")?;
+    unistd::dup2(file.as_raw_fd(), own).context("Failed to duplicate the descriptor.")?;
+    drop(file);
+    let res = f();
+    unistd::dup2(saved, own).context("Failed to duplicate the descriptor.")?;
+    unistd::close(saved).context("Failed to close the descriptor.")?;
+    Ok(res)
+}
+
+fn target2str(target: RedTarget) -> SpecialStr {
+    match target {
+        RedTarget::Stdin => SpecialStr::from(String::from("/dev/stdin")),
+        RedTarget::Stdout => SpecialStr::from(String::from("/dev/stdout")),
+        RedTarget::Stderr => SpecialStr::from(String::from("/dev/stderr")),
+        RedTarget::Null => SpecialStr::from(String::from("/dev/null")),
+        RedTarget::Other(s) => s,
+    }
+}
 
-        Ok(s)
+// A target is expanded like an argument, and must be one word.
+fn eval_path(target: &SpecialStr, jobs: &SharedJobs, ns: &mut NameSpace) -> anyhow::Result<String> {
+    let mut paths = word::expand(target, Split::None, jobs, ns)?;
+    match paths.len() {
+        1 => Ok(paths.remove(0)),
+        _ => Err(RedirectError(format!("{}: ambiguous redirect", target)).into()),
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-struct RedOut {
+fn open_out(
+    target: &RedTarget,
     mode: OutMode,
-    target: SpecialStr,
+    [stdin, stdout, stderr]: [&Fd; 3],
+    jobs: &SharedJobs,
+    ns: &mut NameSpace,
+) -> anyhow::Result<Fd> {
+    Ok(match target {
+        RedTarget::Stdin => stdin.try_clone()?,
+        RedTarget::Stdout => stdout.try_clone()?,
+        RedTarget::Stderr => stderr.try_clone()?,
+        RedTarget::Null => Fd::File(mode.open("/dev/null", false)?),
+        RedTarget::Other(s) => Fd::File(mode.open(&eval_path(s, jobs, ns)?, ns.opts().noclobber)?),
+    })
 }
 
-impl RedOut {
-    fn overwrite(target: RedTarget) -> Self {
-        Self {
-            mode: OutMode::Overwrite,
-            target: target2str(target),
-        }
+enum Fd {
+    Inherit(RawFd),
+    File(File),
+    HereDoc(Vec<u8>),
+}
+
+impl Fd {
+    fn try_clone(&self) -> anyhow::Result<Self> {
+        Ok(match self {
+            Self::Inherit(fd) => Self::Inherit(*fd),
+            Self::File(file) => Self::File(file.try_clone()?),
+            Self::HereDoc(_) => anyhow::bail!("Can't duplicate a here document."),
+        })
     }
 
-    fn append(target: RedTarget) -> Self {
-        Self {
-            mode: OutMode::Append,
-            target: target2str(target),
-        }
+    fn into_stdio(self, own: RawFd) -> anyhow::Result<Stdio> {
+        Ok(match self {
+            Self::Inherit(fd) if fd == own => Stdio::inherit(),
+            Self::Inherit(fd) => {
+                let fd = fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(0))
+                    .context("Failed to duplicate the descriptor.")?;
+                Stdio::from(unsafe { File::from_raw_fd(fd) })
+            }
+            Self::File(file) => Stdio::from(file),
+            Self::HereDoc(_) => Stdio::piped(),
+        })
+    }
+
+    // A file to put on the descriptor `own`, or `None` to leave it as is.
+    fn into_file(self, own: RawFd) -> anyhow::Result<Option<File>> {
+        Ok(match self {
+            Self::Inherit(fd) if fd == own => None,
+            Self::Inherit(fd) => {
+                let fd = fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(0))
+                    .context("Failed to duplicate the descriptor.")?;
+                Some(unsafe { File::from_raw_fd(fd) })
+            }
+            Self::File(file) => Some(file),
+            Self::HereDoc(s) => {
+                let (r, w) = pipe()?;
+                feed(w, s);
+                Some(r)
+            }
+        })
     }
 }
 
@@ -176,27 +338,41 @@ impl RedOut {
 enum OutMode {
     Overwrite,
     Append,
+    Force,
+}
+
+impl From<&RedKind> for OutMode {
+    fn from(kind: &RedKind) -> Self {
+        match kind {
+            RedKind::AppendStdout | RedKind::AppendStderr | RedKind::AppendBoth => Self::Append,
+            RedKind::ForceStdout | RedKind::ForceStderr | RedKind::ForceBoth => Self::Force,
+            _ => Self::Overwrite,
+        }
+    }
 }
 
 impl OutMode {
-    fn option(&self) -> OpenOptions {
+    // With `noclobber`, `>` doesn't truncate an existing regular file, while
+    // `>|` always does.
+    fn open(&self, path: &str, noclobber: bool) -> anyhow::Result<File> {
         let mut opt = OpenOptions::new();
         match self {
-            Self::Overwrite => opt.write(true).create(true),
-            Self::Append => opt.write(true).append(true),
+            Self::Overwrite if noclobber => match opt.write(true).create_new(true).open(path) {
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    if std::fs::metadata(path).is_ok_and(|meta| meta.is_file()) {
+                        let msg = format!("{}: cannot overwrite existing file", path);
+                        return Err(RedirectError(msg).into());
+                    }
+                    return OpenOptions::new()
+                        .write(true)
+                        .open(path)
+                        .map_err(|e| open_error(path, e));
+                }
+                res => return res.map_err(|e| open_error(path, e)),
+            },
+            Self::Overwrite | Self::Force => opt.write(true).create(true).truncate(true),
+            Self::Append => opt.append(true).create(true),
         };
-        opt
+        opt.open(path).map_err(|e| open_error(path, e))
     }
 }
-
-#[derive(Clone, Debug, PartialEq, Eq)]
-struct RedIn {
-    mode: InMode,
-    target: SpecialStr,
-}
-
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-enum InMode {
-    Normal,
-    HereDoc,
-}
@@ -0,0 +1,159 @@
+use super::command::quote_word;
+use super::regex::{self, Regex};
+use super::{glob, NameSpace};
+use crate::job::SharedJobs;
+use crate::parse::Cond;
+use std::fmt;
+use std::fs;
+
+// A bad regex or integer in `[[ ... ]]`, which fails with the status 2.
+#[derive(Debug)]
+pub struct CondError(String);
+
+impl fmt::Display for CondError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for CondError {}
+
+// Evaluates `[[ ... ]]`. Words are neither split nor globbed, an unquoted
+// right side of `==` and `!=` is a pattern, and `=~` sets the whole match
+// and the groups to `$BASH_REMATCH`. The expanded words and the operators
+// are pushed to `trace`, with the source of a side left untested.
+pub fn test(
+    cond: &Cond,
+    trace: &mut Vec<String>,
+    jobs: &SharedJobs,
+    ns: &mut NameSpace,
+) -> anyhow::Result<bool> {
+    Ok(match cond {
+        Cond::Word(word) => {
+            let word = word.eval(jobs, ns)?;
+            trace.push(quote_word(&word));
+            !word.is_empty()
+        }
+        Cond::Unary(op, word) => {
+            let word = word.eval(jobs, ns)?;
+            trace.push(op.clone());
+            trace.push(quote_word(&word));
+            unary(op, &word)
+        }
+        Cond::Binary(lhs, op, rhs) => {
+            let lhs = lhs.eval(jobs, ns)?;
+            let quoted = rhs.is_quoted();
+            let rhs = rhs.eval(jobs, ns)?;
+            trace.push(quote_word(&lhs));
+            trace.push(op.clone());
+            trace.push(quote_word(&rhs));
+            binary(&lhs, op, rhs, quoted, ns)?
+        }
+        Cond::Not(cond) => {
+            trace.push(String::from("!"));
+            !test(cond, trace, jobs, ns)?
+        }
+        Cond::And(lhs, rhs) | Cond::Or(lhs, rhs) => {
+            let and = matches!(cond, Cond::And(_, _));
+            let first = test(lhs, trace, jobs, ns)?;
+            trace.push(String::from(if and { "&&" } else { "||" }));
+            if first == and {
+                test(rhs, trace, jobs, ns)?
+            } else {
+                source(rhs, trace);
+                first
+            }
+        }
+    })
+}
+
+fn binary(
+    lhs: &str,
+    op: &str,
+    rhs: String,
+    quoted: bool,
+    ns: &mut NameSpace,
+) -> anyhow::Result<bool> {
+    Ok(match op {
+        "==" | "=" | "!=" => {
+            let matched = if quoted {
+                lhs == rhs
+            } else {
+                glob::matches(&rhs, lhs)
+            };
+            matched == (op != "!=")
+        }
+        "=~" => {
+            let re = if quoted { regex::escape(&rhs) } else { rhs };
+            let caps = Regex::new(&re)
+                .and_then(|re| re.captures(lhs))
+                .map_err(|e| CondError(e.to_string()))?;
+            let matched = caps.is_some();
+            ns.push_array("BASH_REMATCH", caps.unwrap_or_default());
+            matched
+        }
+        "<" => lhs < rhs.as_str(),
+        ">" => lhs > rhs.as_str(),
+        op => {
+            let lhs = integer(lhs)?;
+            let rhs = integer(&rhs)?;
+            match op {
+                "-eq" => lhs == rhs,
+                "-ne" => lhs != rhs,
+                "-lt" => lhs < rhs,
+                "-le" => lhs <= rhs,
+                "-gt" => lhs > rhs,
+                "-ge" => lhs >= rhs,
+                op => return Err(CondError(format!("{}: unknown operator", op)).into()),
+            }
+        }
+    })
+}
+
+// Pushes the words of `cond` as they are written.
+fn source(cond: &Cond, trace: &mut Vec<String>) {
+    match cond {
+        Cond::Word(word) => trace.push(word.to_string()),
+        Cond::Unary(op, word) => trace.extend(vec![op.clone(), word.to_string()]),
+        Cond::Binary(lhs, op, rhs) => {
+            trace.extend(vec![lhs.to_string(), op.clone(), rhs.to_string()])
+        }
+        Cond::Not(cond) => {
+            trace.push(String::from("!"));
+            source(cond, trace);
+        }
+        Cond::And(lhs, rhs) | Cond::Or(lhs, rhs) => {
+            source(lhs, trace);
+            trace.push(String::from(if matches!(cond, Cond::And(_, _)) {
+                "&&"
+            } else {
+                "||"
+            }));
+            source(rhs, trace);
+        }
+    }
+}
+
+fn unary(op: &str, word: &str) -> bool {
+    use nix::unistd::{access, AccessFlags};
+
+    match op {
+        "-z" => word.is_empty(),
+        "-n" => !word.is_empty(),
+        "-e" => fs::metadata(word).is_ok(),
+        "-f" => fs::metadata(word).is_ok_and(|meta| meta.is_file()),
+        "-d" => fs::metadata(word).is_ok_and(|meta| meta.is_dir()),
+        "-s" => fs::metadata(word).is_ok_and(|meta| meta.len() > 0),
+        "-L" | "-h" => fs::symlink_metadata(word).is_ok_and(|meta| meta.file_type().is_symlink()),
+        "-r" => access(word, AccessFlags::R_OK).is_ok(),
+        "-w" => access(word, AccessFlags::W_OK).is_ok(),
+        "-x" => access(word, AccessFlags::X_OK).is_ok(),
+        _ => false,
+    }
+}
+
+fn integer(s: &str) -> Result<i64, CondError> {
+    s.trim()
+        .parse()
+        .map_err(|_| CondError(format!("{}: integer expression expected", s)))
+}
@@ -0,0 +1,41 @@
+use super::command::pipe;
+use super::{fork, Block, NameSpace};
+use crate::job::SharedJobs;
+use nix::unistd;
+use std::os::unix::io::IntoRawFd;
+
+// Starts `block` in a child process with its stdin and stdout connected to
+// the shell by pipes. The descriptors for reading its output and writing its
+// input are set to `$NAME[0]` and `$NAME[1]`, so they can be redirected as
+// `/dev/fd/${NAME[1]}`, and the pid to `$NAME_PID`.
+pub fn start(
+    name: &str,
+    block: &Block,
+    source: &str,
+    jobs: &SharedJobs,
+    ns: &mut NameSpace,
+) -> anyhow::Result<()> {
+    // Only one coprocess is kept for each name.
+    for i in 0..2 {
+        if let Some(fd) = ns.get_var(format!("{}[{}]", name, i)) {
+            fd.parse().map(unistd::close).ok();
+        }
+    }
+
+    // The ends of the shell are not inherited by the other commands, so the
+    // coprocess gets EOF when the shell exits.
+    let (out_r, out_w) = pipe()?;
+    let (in_r, in_w) = pipe()?;
+    let child = fork::spawn(block, source, ns, |cmd| {
+        cmd.stdin(in_r);
+        cmd.stdout(out_w);
+    })?;
+    let (out_r, in_w) = (out_r.into_raw_fd(), in_w.into_raw_fd());
+    ns.push_array(name, vec![out_r.to_string(), in_w.to_string()]);
+    ns.push_var(format!("{}_PID", name), child.id().to_string());
+    jobs.with(|jobs| {
+        let (id, pid) = jobs.new_bg(child.id() as i32)?;
+        println!("Job %{} ({}) has started.", id, pid);
+        Ok(())
+    })
+}
@@ -0,0 +1,148 @@
+use super::namespace::{Reader, Writer};
+use super::{located, Block, NameSpace};
+use crate::job::SharedJobs;
+use anyhow::Context;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag, OFlag};
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::signal::{self, SigHandler, Signal};
+use nix::unistd;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Set in a stage of a pipeline when the next stage has exited.
+static UNREAD: AtomicBool = AtomicBool::new(false);
+
+// Runs `block` in a subshell in a child process, after `setup` has given it
+// its stdin and stdout. The shell may have other threads by then, which can
+// hold locks the child would wait for forever, so the child doesn't run the
+// block itself after `fork`, but starts the shell again with `--child`. The
+// namespace and the source of the block with its lines are passed through a
+// pipe, and the child starts with no jobs and the default signal handlers.
+pub fn spawn<F: FnOnce(&mut Command)>(
+    block: &Block,
+    source: &str,
+    ns: &NameSpace,
+    setup: F,
+) -> anyhow::Result<Child> {
+    start(block, source, ns, false, setup)
+}
+
+// Like `spawn`, for a stage of a pipeline writing to the next one. A loop in
+// it ends when the next stage exits, even if only the commands it runs write
+// to the pipe, as `while true { echo y } | head -n 1` does.
+pub fn spawn_stage(
+    block: &Block,
+    source: &str,
+    ns: &NameSpace,
+    stdin: Option<File>,
+    stdout: File,
+) -> anyhow::Result<Child> {
+    start(block, source, ns, true, |cmd| {
+        cmd.stdin(stdin.map_or_else(Stdio::inherit, Stdio::from));
+        cmd.stdout(stdout);
+    })
+}
+
+fn start<F: FnOnce(&mut Command)>(
+    block: &Block,
+    source: &str,
+    ns: &NameSpace,
+    stage: bool,
+    setup: F,
+) -> anyhow::Result<Child> {
+    let (r, w) = unistd::pipe2(OFlag::O_CLOEXEC).context("Failed to create a pipe.")?;
+    let (r, mut w) = unsafe { (File::from_raw_fd(r), File::from_raw_fd(w)) };
+    // Only the end to read is inherited by the child.
+    fcntl(r.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::empty())).context("Failed to set up a pipe.")?;
+
+    let mut cmd = Command::new(ns.shell_path()?);
+    cmd.arg("--child").arg(r.as_raw_fd().to_string());
+    ns.apply_env(&mut cmd);
+    setup(&mut cmd);
+    std::io::stdout().flush().ok();
+    let child = cmd.spawn().context("Failed to start a subshell.")?;
+    // Close our copies of the descriptors given to the child.
+    drop(cmd);
+    drop(r);
+
+    let mut state = Writer::default();
+    ns.encode(&mut state);
+    state.str(source);
+    state.list(block.lines().into_iter(), |w, line| w.num(line));
+    state.bool(stage);
+    // The child reads all of it before running anything, so this only fails
+    // if the child has already exited.
+    w.write_all(state.into_string().as_bytes()).ok();
+    Ok(child)
+}
+
+// Runs the subshell passed through `fd` by `spawn`, and returns its status.
+pub fn run_child(fd: RawFd) -> anyhow::Result<i32> {
+    // Writing to a closed pipe ends it quietly like other commands.
+    unsafe { signal::signal(Signal::SIGPIPE, SigHandler::SigDfl) }
+        .context("Failed to set a signal handler.")?;
+
+    let mut state = String::new();
+    unsafe { File::from_raw_fd(fd) }
+        .read_to_string(&mut state)
+        .context("Failed to read the state of the shell.")?;
+    let mut r = Reader::new(&state);
+    let (mut ns, source, lines, stage) = match (
+        NameSpace::decode(&mut r),
+        r.str(),
+        r.list(Reader::num),
+        r.bool(),
+    ) {
+        (Some(ns), Some(source), Some(lines), Some(stage)) => (ns, source, lines, stage),
+        _ => anyhow::bail!("The state of the shell is broken."),
+    };
+    if stage {
+        watch_stdout();
+    }
+
+    let line = ns.line();
+    let block = Block::parse_at(&source, line).map(|mut block| {
+        block.set_lines(&lines);
+        block
+    });
+    let code = match block.and_then(|block| block.eval(&SharedJobs::new(), &mut ns)) {
+        Ok(()) => ns
+            .get_var("status")
+            .and_then(|code| code.parse().ok())
+            .unwrap_or(0),
+        Err(e) => {
+            let e = located(e, line);
+            eprintln!("{}", crate::session::color::error(e.to_string()));
+            1
+        }
+    };
+    std::io::stdout().flush().ok();
+    Ok(code)
+}
+
+// Notes when the pipe of the stdout has no reader any more, which is reported
+// as an error by `poll`.
+fn watch_stdout() {
+    std::thread::spawn(|| {
+        let mut fds = [PollFd::new(1, PollFlags::empty())];
+        while poll(&mut fds, -1).is_err() {}
+        if fds[0]
+            .revents()
+            .is_some_and(|ev| ev.contains(PollFlags::POLLERR))
+        {
+            UNREAD.store(true, Ordering::SeqCst);
+        }
+    });
+}
+
+// Ends the stage with SIGPIPE before the next iteration of a loop once its
+// stdout is unread. A stage which doesn't loop finishes with its own status,
+// so `false | true` fails with pipefail even if `true` exits first.
+pub fn check_stdout() {
+    if UNREAD.load(Ordering::SeqCst) {
+        signal::kill(unistd::getpid(), Signal::SIGPIPE).ok();
+    }
+}
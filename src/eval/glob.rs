@@ -0,0 +1,280 @@
+use std::fs;
+use std::path::Path;
+
+pub fn is_pattern(pat: &str) -> bool {
+    let mut chars = pat.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            _ => (),
+        }
+    }
+    false
+}
+
+pub fn matches(pat: &str, s: &str) -> bool {
+    let pat = pat.chars().collect::<Vec<_>>();
+    let s = s.chars().collect::<Vec<_>>();
+
+    let (mut p, mut i) = (0, 0);
+    let mut star = None;
+    while i < s.len() {
+        let step = match pat.get(p) {
+            Some('*') => {
+                star = Some((p, i));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(1),
+            Some('[') => match class(&pat[p..], s[i]) {
+                Some((true, len)) => Some(len),
+                Some((false, _)) => None,
+                None if s[i] == '[' => Some(1),
+                None => None,
+            },
+            Some('\\') if p + 1 < pat.len() => {
+                if pat[p + 1] == s[i] {
+                    Some(2)
+                } else {
+                    None
+                }
+            }
+            Some(&c) if c == s[i] => Some(1),
+            _ => None,
+        };
+
+        match (step, star) {
+            (Some(len), _) => {
+                p += len;
+                i += 1;
+            }
+            (None, Some((sp, si))) => {
+                star = Some((sp, si + 1));
+                p = sp + 1;
+                i = si + 1;
+            }
+            (None, None) => return false,
+        }
+    }
+
+    pat[p..].iter().all(|c| *c == '*')
+}
+
+// Matches a bracket expression at the start of `pat` against `c`, returning
+// whether it matched and the length of the expression.
+fn class(pat: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negate = matches!(pat.get(i), Some('!') | Some('^'));
+    if negate {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let start = *pat.get(i)?;
+        if start == ']' && !first {
+            break;
+        }
+        first = false;
+
+        let (lo, next) = match start {
+            '\\' => (*pat.get(i + 1)?, i + 2),
+            _ => (start, i + 1),
+        };
+        match (pat.get(next), pat.get(next + 1)) {
+            (Some('-'), Some(&hi)) if hi != ']' => {
+                matched |= lo <= c && c <= hi;
+                i = next + 2;
+            }
+            _ => {
+                matched |= lo == c;
+                i = next;
+            }
+        }
+    }
+
+    Some((matched != negate, i + 1))
+}
+
+fn unescape(pat: &str) -> String {
+    let mut res = String::new();
+    let mut chars = pat.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => res.extend(chars.next()),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+// Hidden files are only matched by a component starting with a dot, and
+// the paths are sorted by bytes regardless of the locale.
+pub fn expand(pat: &str, globstar: bool) -> Vec<String> {
+    let (root, rest) = match pat.strip_prefix('/') {
+        Some(rest) => (String::from("/"), rest),
+        None => (String::new(), pat),
+    };
+
+    let comps = rest.split('/').collect::<Vec<_>>();
+    let mut paths = vec![root];
+    for (n, comp) in comps.iter().enumerate() {
+        let last = n + 1 == comps.len();
+        if globstar && *comp == "**" {
+            // Directories are kept with the trailing slash unless `**` is
+            // the last component, which matches files too.
+            let mut next = Vec::new();
+            for base in paths.iter() {
+                if !last {
+                    next.push(base.clone());
+                }
+                walk(base, last, &mut next);
+            }
+            paths = next;
+            continue;
+        }
+
+        let mut next = Vec::new();
+        for base in paths.iter() {
+            if !is_pattern(comp) {
+                let path = format!("{}{}", base, unescape(comp));
+                if Path::new(&path).exists() {
+                    next.push(path);
+                }
+                continue;
+            }
+
+            let dir = if base.is_empty() { "." } else { base.as_str() };
+            let entries = match fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            let names = entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .filter(|name| {
+                    (comp.starts_with('.') || !name.starts_with('.')) && matches(comp, name)
+                })
+                .collect::<Vec<_>>();
+            next.extend(names.into_iter().map(|name| format!("{}{}", base, name)));
+        }
+
+        paths = if last {
+            next
+        } else {
+            next.into_iter().map(|path| path + "/").collect()
+        };
+    }
+
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+// Collects the entries under `base` recursively except hidden ones, without
+// following symbolic links.
+fn walk(base: &str, files: bool, out: &mut Vec<String>) {
+    let dir = if base.is_empty() { "." } else { base };
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+
+        let path = format!("{}{}", base, name);
+        if entry.file_type().is_ok_and(|ty| ty.is_dir()) {
+            let dir = format!("{}/", path);
+            out.push(if files { path } else { dir.clone() });
+            walk(&dir, files, out);
+        } else if files {
+            out.push(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns() {
+        assert!(is_pattern("*.rs"));
+        assert!(is_pattern("a[bc]"));
+        assert!(!is_pattern("a\\*b"));
+        assert!(!is_pattern("plain"));
+    }
+
+    #[test]
+    fn stars_and_questions() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(matches("*", ""));
+        assert!(matches("a*b*c", "aXbYbZc"));
+        assert!(!matches("a*b", "aXc"));
+        assert!(matches("?x", "ax"));
+        assert!(!matches("?x", "x"));
+        assert!(matches("a\\*", "a*"));
+        assert!(!matches("a\\*", "ab"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(matches("[abc]", "b"));
+        assert!(matches("[a-z]1", "q1"));
+        assert!(!matches("[!a-z]", "q"));
+        assert!(matches("[^a-z]", "Q"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("[a-]", "-"));
+        // An unclosed bracket is taken literally.
+        assert!(matches("[ab", "[ab"));
+    }
+
+    #[test]
+    fn hidden_and_globstar() {
+        let root = std::env::temp_dir().join(format!("tish-glob-{}", std::process::id()));
+        for dir in ["a/b", ".hidden"].iter() {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in [
+            "x.rs",
+            ".x.rs",
+            "a/y.rs",
+            "a/b/z.rs",
+            "a/B.rs",
+            ".hidden/h.rs",
+        ]
+        .iter()
+        {
+            fs::write(root.join(file), "").unwrap();
+        }
+        let root_str = format!("{}/", root.display());
+        let expand = |pat: &str, globstar: bool| {
+            expand(&format!("{}{}", root_str, pat), globstar)
+                .into_iter()
+                .map(|path| path[root_str.len()..].to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(expand("*.rs", false), ["x.rs"]);
+        assert_eq!(expand(".*.rs", false), [".x.rs"]);
+        assert_eq!(expand("*/*.rs", false), ["a/B.rs", "a/y.rs"]);
+        assert_eq!(
+            expand("**/*.rs", true),
+            ["a/B.rs", "a/b/z.rs", "a/y.rs", "x.rs"]
+        );
+        assert_eq!(
+            expand("a/**", true),
+            ["a/B.rs", "a/b", "a/b/z.rs", "a/y.rs"]
+        );
+        assert_eq!(expand("**/*.rs", false), ["a/B.rs", "a/y.rs"]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
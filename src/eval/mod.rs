@@ -1,7 +1,18 @@
 mod block;
 mod command;
+mod cond;
+mod coproc;
+mod fork;
+mod glob;
 mod namespace;
+mod param;
+mod regex;
+mod temp;
+mod word;
 
-pub use block::Block;
-pub use command::Command;
-pub use namespace::NameSpace;
+pub use block::{located, Block, LineError};
+pub use command::{Command, RedirectError, SubshellExit, UsageError};
+pub use fork::run_child;
+pub use namespace::{CompSpec, Completions, History, Jump, NameSpace, ReadonlyError};
+pub use param::expand_param;
+pub use temp::{make_temp, temp_dir, TempFile};
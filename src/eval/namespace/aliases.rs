@@ -0,0 +1,45 @@
+use super::state::{Reader, Writer};
+use std::collections::HashMap;
+
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct Aliases(HashMap<String, String>);
+
+impl Aliases {
+    pub fn push<T: Into<String>, U: Into<String>>(&mut self, name: T, value: U) {
+        self.0.insert(name.into(), value.into());
+    }
+
+    pub fn get<T: AsRef<str>>(&self, name: T) -> Option<&str> {
+        self.0.get(name.as_ref()).map(|s| s.as_str())
+    }
+
+    pub fn remove<T: AsRef<str>>(&mut self, name: T) -> bool {
+        self.0.remove(name.as_ref()).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    pub fn list(&self) -> Vec<(&str, &str)> {
+        let mut list = self
+            .0
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect::<Vec<_>>();
+        list.sort();
+        list
+    }
+
+    pub fn encode(&self, w: &mut Writer) {
+        w.list(self.0.iter(), |w, (name, value)| {
+            w.str(name);
+            w.str(value);
+        });
+    }
+
+    pub fn decode(r: &mut Reader) -> Option<Self> {
+        let aliases = r.list(|r| Some((r.str()?, r.str()?)))?;
+        Some(Self(aliases.into_iter().collect()))
+    }
+}
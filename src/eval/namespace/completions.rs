@@ -0,0 +1,97 @@
+use super::state::{Reader, Writer};
+use std::collections::HashMap;
+
+// How the arguments of a command are completed.
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct CompSpec {
+    pub words: Vec<String>,
+    pub files: bool,
+}
+
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct Completions(HashMap<String, CompSpec>);
+
+impl Completions {
+    pub fn push<T: Into<String>>(&mut self, name: T, spec: CompSpec) {
+        self.0.insert(name.into(), spec);
+    }
+
+    pub fn get<T: AsRef<str>>(&self, name: T) -> Option<&CompSpec> {
+        self.0.get(name.as_ref())
+    }
+
+    pub fn remove<T: AsRef<str>>(&mut self, name: T) -> bool {
+        self.0.remove(name.as_ref()).is_some()
+    }
+
+    pub fn list(&self) -> Vec<(&str, &CompSpec)> {
+        let mut list = self
+            .0
+            .iter()
+            .map(|(k, v)| (k.as_str(), v))
+            .collect::<Vec<_>>();
+        list.sort_by_key(|(name, _)| *name);
+        list
+    }
+
+    // Candidates for the argument starting with `prefix` of the command.
+    pub fn words<'a>(&'a self, name: &str, prefix: &'a str) -> Vec<&'a str> {
+        self.get(name).map_or(Vec::new(), |spec| {
+            spec.words
+                .iter()
+                .map(|word| word.as_str())
+                .filter(|word| word.starts_with(prefix))
+                .collect()
+        })
+    }
+
+    pub fn encode(&self, w: &mut Writer) {
+        w.list(self.0.iter(), |w, (name, spec)| {
+            w.str(name);
+            w.list(spec.words.iter(), |w, word| w.str(word));
+            w.bool(spec.files);
+        });
+    }
+
+    pub fn decode(r: &mut Reader) -> Option<Self> {
+        let specs = r.list(|r| {
+            let name = r.str()?;
+            let words = r.list(Reader::str)?;
+            Some((
+                name,
+                CompSpec {
+                    words,
+                    files: r.bool()?,
+                },
+            ))
+        })?;
+        Some(Self(specs.into_iter().collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words() {
+        let mut completions = Completions::default();
+        completions.push(
+            "myservice",
+            CompSpec {
+                words: vec![
+                    String::from("start"),
+                    String::from("stop"),
+                    String::from("restart"),
+                ],
+                files: false,
+            },
+        );
+        assert_eq!(completions.words("myservice", "st"), ["start", "stop"]);
+        assert_eq!(
+            completions.words("myservice", ""),
+            ["start", "stop", "restart"]
+        );
+        assert!(completions.words("other", "").is_empty());
+    }
+}
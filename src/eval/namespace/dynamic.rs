@@ -0,0 +1,71 @@
+use super::state::{Reader, Writer};
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+// Variables computed each time they are read: `$RANDOM` and `$SECONDS`.
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct Dynamic {
+    seed: Cell<u32>,
+    start: Option<Instant>,
+}
+
+impl Dynamic {
+    pub fn new() -> Self {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        Self {
+            seed: Cell::new(now.subsec_nanos() ^ std::process::id()),
+            start: Some(Instant::now()),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        match key {
+            "RANDOM" => Some(self.random().to_string()),
+            "SECONDS" => Some(
+                self.start
+                    .map_or(0, |start| start.elapsed().as_secs())
+                    .to_string(),
+            ),
+            _ => None,
+        }
+    }
+
+    // Assigning to `$RANDOM` seeds it, and to `$SECONDS` resets the count.
+    // Returns false for the other variables.
+    pub fn set(&mut self, key: &str, value: &str) -> bool {
+        let value = value.trim().parse::<u64>().unwrap_or(0);
+        match key {
+            "RANDOM" => self.seed.set(value as u32),
+            "SECONDS" => {
+                let now = Instant::now();
+                self.start = Some(now.checked_sub(Duration::from_secs(value)).unwrap_or(now));
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    // A subshell counts `$SECONDS` on from the shell, but takes a seed of its
+    // own so that `$RANDOM` differs from the one of the shell.
+    pub fn encode(&self, w: &mut Writer) {
+        w.num(
+            self.start
+                .map_or(0, |start| start.elapsed().as_secs() as usize),
+        );
+    }
+
+    pub fn decode(r: &mut Reader) -> Option<Self> {
+        let mut dynamic = Self::new();
+        dynamic.set("SECONDS", &r.num()?.to_string());
+        Some(dynamic)
+    }
+
+    // A linear congruential generator, giving 0 to 32767.
+    fn random(&self) -> u32 {
+        let seed = self.seed.get().wrapping_mul(1103515245).wrapping_add(12345);
+        self.seed.set(seed);
+        (seed >> 16) & 0x7fff
+    }
+}
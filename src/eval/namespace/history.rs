@@ -0,0 +1,243 @@
+use super::state::{Reader, Writer};
+use anyhow::Context;
+use std::fs;
+use std::path::Path;
+
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct History(Vec<String>);
+
+impl History {
+    pub fn push<T: Into<String>>(&mut self, line: T) {
+        self.0.push(line.into());
+    }
+
+    pub fn get(&self, n: usize) -> Option<&str> {
+        self.0.get(n.checked_sub(1)?).map(|s| s.as_str())
+    }
+
+    pub fn last(&self) -> Option<&str> {
+        self.0.last().map(|s| s.as_str())
+    }
+
+    // Drops the oldest entries to keep at most `size`.
+    pub fn truncate(&mut self, size: usize) {
+        let over = self.0.len().saturating_sub(size);
+        self.0.drain(..over);
+    }
+
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> anyhow::Result<()> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e).context("Failed to read the history file."),
+        };
+        self.0.extend(content.lines().map(String::from));
+        Ok(())
+    }
+
+    // Appends a line to the history file, keeping at most `size` lines.
+    pub fn append_file<P: AsRef<Path>>(path: P, line: &str, size: usize) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let mut history = Self::default();
+        history.load(path)?;
+        history.push(line);
+        history.truncate(size);
+        let mut content = history.0.join("\n");
+        content.push('\n');
+        fs::write(path, content).context("Failed to write the history file.")
+    }
+
+    pub fn encode(&self, w: &mut Writer) {
+        w.list(self.0.iter(), |w, line| w.str(line));
+    }
+
+    pub fn decode(r: &mut Reader) -> Option<Self> {
+        Some(Self(r.list(Reader::str)?))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // Finds an entry by its number, by an offset back from the newest one like
+    // `-1`, or by the prefix of the latest matching one before the newest.
+    pub fn resolve<T: AsRef<str>>(&self, spec: T) -> Option<usize> {
+        let spec = spec.as_ref();
+        match spec.parse::<isize>() {
+            Ok(n) if n < 0 => self.len().checked_sub(n.unsigned_abs()).filter(|n| *n > 0),
+            Ok(n) => Some(n as usize).filter(|n| (1..=self.len()).contains(n)),
+            Err(_) => (1..self.len())
+                .rev()
+                .find(|n| self.0[n - 1].starts_with(spec)),
+        }
+    }
+
+    pub fn find<T: AsRef<str>>(&self, prefix: T) -> Option<&str> {
+        self.0
+            .iter()
+            .rev()
+            .find(|line| line.starts_with(prefix.as_ref()))
+            .map(|s| s.as_str())
+    }
+
+    pub fn expand<T: AsRef<str>>(&self, line: T) -> anyhow::Result<String> {
+        let mut res = String::new();
+        let mut chars = line.as_ref().chars().peekable();
+        let mut quote = None;
+        while let Some(c) = chars.next() {
+            match (c, quote) {
+                ('\\', None) => {
+                    match chars.next() {
+                        Some('!') => res.push('!'),
+                        Some(c) => {
+                            res.push('\\');
+                            res.push(c);
+                        }
+                        None => res.push('\\'),
+                    }
+                    continue;
+                }
+                ('\\', Some('"')) => {
+                    res.push(c);
+                    res.extend(chars.next());
+                    continue;
+                }
+                ('\'', None) | ('"', None) => quote = Some(c),
+                (c, Some(q)) if c == q => quote = None,
+                ('!', None) => {
+                    if let Some(event) = self.event(&mut chars)? {
+                        res.push_str(event);
+                        continue;
+                    }
+                }
+                _ => (),
+            }
+            res.push(c);
+        }
+
+        Ok(res)
+    }
+
+    fn event<I: Iterator<Item = char>>(
+        &self,
+        chars: &mut std::iter::Peekable<I>,
+    ) -> anyhow::Result<Option<&str>> {
+        let designator = match chars.peek() {
+            Some('!') => {
+                chars.next();
+                String::from("!")
+            }
+            // The designator ends where a word of the parser does, or at a
+            // quote.
+            Some(&c) if c == '-' || c.is_ascii_alphanumeric() => {
+                let mut s = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !crate::parse::is_word_char(c) || c == '"' || c == '\'' {
+                        break;
+                    }
+                    s.push(c);
+                    chars.next();
+                }
+                s
+            }
+            _ => return Ok(None),
+        };
+
+        let event = match designator.as_str() {
+            "!" => self.last(),
+            s => match s.parse::<isize>() {
+                Ok(n) if n < 0 => (self.len() + 1)
+                    .checked_sub(n.unsigned_abs())
+                    .and_then(|n| self.get(n)),
+                Ok(n) => self.get(n as usize),
+                Err(_) => self.find(s),
+            },
+        };
+        event
+            .map(Some)
+            .with_context(|| format!("!{}: event not found", designator))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history() -> History {
+        let mut history = History::default();
+        history.push("ls -l");
+        history.push("echo a");
+        history
+    }
+
+    #[test]
+    fn designator_ends_at_operators() {
+        let history = history();
+        for op in "#|&;${}()".chars() {
+            let line = format!("!ec{}x", op);
+            assert_eq!(history.expand(&line).unwrap(), format!("echo a{}x", op));
+        }
+        assert_eq!(history.expand("!l\"x\"").unwrap(), "ls -l\"x\"");
+        assert_eq!(history.expand("!-2'x'").unwrap(), "ls -l'x'");
+        assert_eq!(history.expand("!! x").unwrap(), "echo a x");
+    }
+
+    #[test]
+    fn events() {
+        let history = history();
+        assert_eq!(history.expand("!!").unwrap(), "echo a");
+        assert_eq!(history.expand("!1 x").unwrap(), "ls -l x");
+        assert_eq!(history.expand("!-2").unwrap(), "ls -l");
+        assert_eq!(history.expand("!ls | !ec").unwrap(), "ls -l | echo a");
+        assert!(history.expand("!3").is_err());
+        assert!(history.expand("!-3").is_err());
+    }
+
+    #[test]
+    fn quotes_and_backslashes() {
+        let history = history();
+        assert_eq!(history.expand("'!!' \\!!").unwrap(), "'!!' !!");
+        assert_eq!(history.expand("\"!! \\\" !!\"").unwrap(), "\"!! \\\" !!\"");
+        assert_eq!(history.expand("\\n !!").unwrap(), "\\n echo a");
+    }
+
+    #[test]
+    fn truncate_drops_the_oldest() {
+        let mut history = history();
+        history.push("pwd");
+        history.truncate(2);
+        assert_eq!(history.get(1), Some("echo a"));
+        assert_eq!(history.len(), 2);
+        history.truncate(0);
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn file_keeps_the_newest() {
+        let path = std::env::temp_dir().join(format!("tish-history-{}", std::process::id()));
+        for line in ["a", "b", "c"].iter() {
+            History::append_file(&path, line, 2).unwrap();
+        }
+        let mut history = History::default();
+        history.load(&path).unwrap();
+        fs::remove_file(&path).ok();
+        assert_eq!(history.get(1), Some("b"));
+        assert_eq!(history.last(), Some("c"));
+        assert_eq!(history.len(), 2);
+    }
+
+    #[test]
+    fn designator_takes_other_characters() {
+        let history = history();
+        assert_eq!(
+            history.expand("!ls\\ x").unwrap_err().to_string(),
+            "!ls\\: event not found"
+        );
+        assert!(history.expand("!e-x").is_err());
+        assert_eq!(history.expand("a!").unwrap(), "a!");
+    }
+}
@@ -1,18 +1,87 @@
+mod aliases;
+mod completions;
+mod dynamic;
+mod history;
+mod opts;
+mod paths;
 mod procs;
+mod state;
 mod vars;
 
+pub use aliases::Aliases;
+pub use completions::{CompSpec, Completions};
+use dynamic::Dynamic;
+pub use history::History;
+pub use opts::Options;
+pub use paths::PathCache;
 use procs::Procs;
+pub use state::{Reader, Writer};
+pub use vars::ReadonlyError;
 use vars::Vars;
 
 use crate::eval::Block;
+use std::collections::HashSet;
 
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct NameSpace {
     vars: Vars,
     procs: Procs,
+    opts: Options,
+    history: History,
+    aliases: Aliases,
+    completions: Completions,
+    paths: PathCache,
+    dynamic: Dynamic,
+    line: usize,
+    frames: Vec<Frame>,
+    exit_warned: bool,
+    loops: usize,
+    jump: Option<Jump>,
+    // Builtins disabled by `enable -n`.
+    disabled: HashSet<String>,
+    // The binary which subshells in child processes are started from.
+    shell: Option<String>,
+    // Whether this is a copy for a subshell in the shell process itself.
+    subshell: bool,
+}
+
+// A function being run, or a sourced file without a name, with the line and
+// the source it was called from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Frame {
+    name: Option<String>,
+    line: usize,
+    source: String,
+}
+
+// `break` or `continue` which is on the way to the enclosing loop.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Jump {
+    Break,
+    Continue,
 }
 
 impl NameSpace {
+    // A namespace for a new shell with its standard variables set.
+    pub fn new() -> Self {
+        let mut ns = Self {
+            dynamic: Dynamic::new(),
+            ..Self::default()
+        };
+        let level = std::env::var("SHLVL")
+            .ok()
+            .and_then(|level| level.parse::<usize>().ok())
+            .unwrap_or(0);
+        ns.push_gvar("SHLVL", (level + 1).to_string());
+        if let Ok(pwd) = std::env::current_dir() {
+            ns.push_gvar("PWD", pwd.display().to_string());
+        }
+        ns.push_var("PPID", nix::unistd::getppid().to_string());
+        ns.push_var("$", std::process::id().to_string());
+        ns.push_var("0", std::env::args().next().unwrap_or_default());
+        ns
+    }
+
     pub fn set_args<T, U, US>(&mut self, name: T, args: US)
     where
         T: AsRef<str>,
@@ -22,26 +91,283 @@ impl NameSpace {
         self.vars.set_args(name, args);
     }
 
+    // Runs `f` as the function `name`, with its own scope and arguments.
+    pub fn with_func<T, U, US, R, F>(&mut self, name: T, args: US, f: F) -> anyhow::Result<R>
+    where
+        T: Into<String>,
+        U: AsRef<str>,
+        US: IntoIterator<Item = U>,
+        F: FnOnce(&mut Self) -> anyhow::Result<R>,
+    {
+        self.vars.mark();
+        self.vars.set_positional(args);
+        let res = self.with_frame(Some(name.into()), f);
+        self.vars.drop();
+        res
+    }
+
+    // Runs `f` as a sourced file.
+    pub fn with_source<T, F: FnOnce(&mut Self) -> T>(&mut self, f: F) -> T {
+        self.with_frame(None, f)
+    }
+
+    fn with_frame<T, F: FnOnce(&mut Self) -> T>(&mut self, name: Option<String>, f: F) -> T {
+        self.frames.push(Frame {
+            name,
+            line: self.line,
+            source: self.get_var("0").unwrap_or_default(),
+        });
+        let res = f(self);
+        self.frames.pop();
+        res
+    }
+
+    // The line, the function name (`source` for a sourced file) and the
+    // source of the `level`th call from the innermost one.
+    pub fn caller(&self, level: usize) -> Option<(usize, &str, &str)> {
+        let frame = self.frames.iter().rev().nth(level)?;
+        let name = frame.name.as_deref().unwrap_or("source");
+        Some((frame.line, name, frame.source.as_str()))
+    }
+
+    // Runs `f` with `vars` exported for it alone, as `X=1 cmd`.
+    pub fn with_env<T, F: FnOnce(&mut Self) -> T>(&mut self, vars: &[(String, String)], f: F) -> T {
+        let saved = self.vars.export_temp(vars);
+        let res = f(self);
+        self.vars.restore_temp(saved);
+        res
+    }
+
+    pub fn builtin_enabled(&self, name: &str) -> bool {
+        !self.disabled.contains(name)
+    }
+
+    pub fn set_builtin_enabled(&mut self, name: &str, enabled: bool) {
+        if enabled {
+            self.disabled.remove(name);
+        } else {
+            self.disabled.insert(name.to_string());
+        }
+    }
+
+    pub fn with_loop<R, F: FnOnce(&mut Self) -> R>(&mut self, f: F) -> R {
+        self.loops += 1;
+        let res = f(self);
+        self.loops -= 1;
+        res
+    }
+
+    pub fn in_loop(&self) -> bool {
+        self.loops > 0
+    }
+
+    pub fn set_jump(&mut self, jump: Jump) {
+        self.jump = Some(jump);
+    }
+
+    pub fn jumping(&self) -> bool {
+        self.jump.is_some()
+    }
+
+    pub fn take_jump(&mut self) -> Option<Jump> {
+        self.jump.take()
+    }
+
+    // Whether `exit` has warned about the jobs, so the next command exits if
+    // it is `exit` again.
+    pub fn exit_warned(&self) -> bool {
+        self.exit_warned
+    }
+
+    pub fn set_exit_warned(&mut self, warned: bool) {
+        self.exit_warned = warned;
+    }
+
+    pub fn in_subshell(&self) -> bool {
+        self.subshell
+    }
+
+    pub fn set_subshell(&mut self, subshell: bool) {
+        self.subshell = subshell;
+    }
+
+    // The number of functions being run.
+    pub fn func_depth(&self) -> usize {
+        self.frames
+            .iter()
+            .filter(|frame| frame.name.is_some())
+            .count()
+    }
+
+    // The running binary unless an embedder has set another one, as it
+    // doesn't start subshells.
+    pub fn shell_path(&self) -> anyhow::Result<std::path::PathBuf> {
+        match &self.shell {
+            Some(path) => Ok(path.into()),
+            None => Ok(std::env::current_exe()?),
+        }
+    }
+
+    pub fn set_shell_path<T: Into<String>>(&mut self, path: T) {
+        self.shell = Some(path.into());
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn set_line(&mut self, line: usize) {
+        self.line = line;
+    }
+
     pub fn push_var<T: Into<String>, U: AsRef<str>>(&mut self, key: T, value: U) {
-        self.vars.push(key, value);
+        let key = key.into();
+        if !self.dynamic.set(&key, value.as_ref()) {
+            self.vars.push(key, value);
+        }
+    }
+
+    pub fn push_array<T: Into<String>>(&mut self, key: T, values: Vec<String>) {
+        self.vars.push_array(key, values);
+    }
+
+    // `$LINENO` and `$FUNCNAME` are always taken from the evaluator.
+    pub fn get_var<T: AsRef<str>>(&self, key: T) -> Option<String> {
+        match key.as_ref() {
+            "LINENO" => Some(self.line.to_string()),
+            "FUNCNAME" => Some(
+                self.frames
+                    .iter()
+                    .rev()
+                    .find_map(|frame| frame.name.clone())
+                    .unwrap_or_default(),
+            ),
+            key => self.dynamic.get(key).or_else(|| self.vars.get(key)),
+        }
     }
 
     pub fn push_gvar<T: AsRef<str>, U: AsRef<str>>(&mut self, key: T, value: U) {
-        self.vars.gpush(key, value);
+        if !self.dynamic.set(key.as_ref(), value.as_ref()) {
+            self.vars.gpush(key, value);
+        }
+    }
+
+    pub fn env_snapshot(&self) -> std::collections::BTreeMap<String, String> {
+        self.vars.env_snapshot()
+    }
+
+    // Starts the environment of commands from `env` instead of the one of
+    // the process, keeping the variables the shell sets itself.
+    pub fn set_base_env(&mut self, env: std::collections::BTreeMap<String, String>) {
+        let own = ["SHLVL", "PWD"]
+            .iter()
+            .filter_map(|key| Some((*key, self.get_var(key)?)))
+            .collect::<Vec<_>>();
+        self.vars.set_base_env(env);
+        for (key, value) in own {
+            self.push_gvar(key, value);
+        }
+    }
+
+    // Gives `cmd` the environment of commands, which is inherited from the
+    // process unless a base environment is set.
+    pub fn apply_env(&self, cmd: &mut std::process::Command) {
+        if self.vars.has_base_env() {
+            cmd.env_clear();
+            cmd.envs(self.env_snapshot());
+        }
+    }
+
+    pub fn vars_snapshot(&self) -> std::collections::BTreeMap<String, String> {
+        self.vars.snapshot()
+    }
+
+    pub fn remove_var(&mut self, key: &str) -> Result<(), ReadonlyError> {
+        self.vars.check_writable(key)?;
+        self.vars.remove(key);
+        Ok(())
+    }
+
+    // Variables made by `readonly` can't be assigned or removed by scripts,
+    // which check this before doing so.
+    pub fn check_writable(&self, key: &str) -> Result<(), ReadonlyError> {
+        self.vars.check_writable(key)
+    }
+
+    pub fn set_readonly<T: Into<String>>(&mut self, key: T) {
+        self.vars.set_readonly(key);
+    }
+
+    pub fn readonly_snapshot(&self) -> std::collections::BTreeMap<String, String> {
+        self.vars.readonly_snapshot()
     }
 
-    pub fn push_proc<T: Into<String>>(&mut self, name: T, block: Block) {
-        self.procs.push(name, block);
+    pub fn push_proc<T: Into<String>, U: Into<String>>(
+        &mut self,
+        name: T,
+        block: Block,
+        source: U,
+    ) {
+        self.procs.push(name, block, source);
+    }
+
+    pub fn proc_source<T: AsRef<str>>(&self, name: T) -> Option<&str> {
+        self.procs.source(name)
+    }
+
+    pub fn proc_names(&self) -> Vec<&str> {
+        self.procs.names()
     }
 
     pub fn get_proc<T: AsRef<str>>(&self, name: T) -> Option<Block> {
         self.procs.get(name)
     }
 
+    pub fn opts(&self) -> &Options {
+        &self.opts
+    }
+
+    pub fn opts_mut(&mut self) -> &mut Options {
+        &mut self.opts
+    }
+
+    pub fn aliases(&self) -> &Aliases {
+        &self.aliases
+    }
+
+    pub fn aliases_mut(&mut self) -> &mut Aliases {
+        &mut self.aliases
+    }
+
+    pub fn completions(&self) -> &Completions {
+        &self.completions
+    }
+
+    pub fn completions_mut(&mut self) -> &mut Completions {
+        &mut self.completions
+    }
+
+    pub fn paths(&self) -> &PathCache {
+        &self.paths
+    }
+
+    pub fn paths_mut(&mut self) -> &mut PathCache {
+        &mut self.paths
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    pub fn history_mut(&mut self) -> &mut History {
+        &mut self.history
+    }
+
     pub fn mark(&mut self) {
         self.vars.mark();
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn drop(&mut self) {
         self.vars.drop();
     }
@@ -0,0 +1,84 @@
+use super::state::{Reader, Writer};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Options {
+    pub loop_limit: Option<usize>,
+    pub output_limit: Option<usize>,
+    pub nullglob: bool,
+    // `**` matches any levels of directories.
+    pub globstar: bool,
+    // A directory alone as a command is changed to.
+    pub autocd: bool,
+    pub failglob: bool,
+    pub parse_skip: bool,
+    pub pipefail: bool,
+    pub noclobber: bool,
+    pub nounset: bool,
+    pub checkjobs: bool,
+    // Vi keybindings at the prompt instead of emacs ones.
+    pub vi: bool,
+    // Messages about background jobs are printed at once instead of at the
+    // next prompt.
+    pub notify: bool,
+    // Case conversion only maps ASCII letters.
+    pub asciicase: bool,
+    // Commands and the headers of `for`, `select` and `case` are printed
+    // after `$PS4` before they run.
+    pub xtrace: bool,
+    // EOF at the prompt doesn't leave the shell.
+    pub ignoreeof: bool,
+}
+
+impl Options {
+    pub fn encode(&self, w: &mut Writer) {
+        for limit in [self.loop_limit, self.output_limit].iter() {
+            w.opt(limit.map(|limit| limit.to_string()).as_deref());
+        }
+        for flag in [
+            self.nullglob,
+            self.globstar,
+            self.autocd,
+            self.failglob,
+            self.parse_skip,
+            self.pipefail,
+            self.noclobber,
+            self.nounset,
+            self.checkjobs,
+            self.vi,
+            self.notify,
+            self.asciicase,
+            self.xtrace,
+            self.ignoreeof,
+        ]
+        .iter()
+        {
+            w.bool(*flag);
+        }
+    }
+
+    pub fn decode(r: &mut Reader) -> Option<Self> {
+        let mut limit = || {
+            r.opt()
+                .map(|limit| limit.and_then(|limit| limit.parse().ok()))
+        };
+        let (loop_limit, output_limit) = (limit()?, limit()?);
+        Some(Self {
+            loop_limit,
+            output_limit,
+            nullglob: r.bool()?,
+            globstar: r.bool()?,
+            autocd: r.bool()?,
+            failglob: r.bool()?,
+            parse_skip: r.bool()?,
+            pipefail: r.bool()?,
+            noclobber: r.bool()?,
+            nounset: r.bool()?,
+            checkjobs: r.bool()?,
+            vi: r.bool()?,
+            notify: r.bool()?,
+            asciicase: r.bool()?,
+            xtrace: r.bool()?,
+            ignoreeof: r.bool()?,
+        })
+    }
+}
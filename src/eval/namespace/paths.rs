@@ -0,0 +1,117 @@
+use super::state::{Reader, Writer};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+// Paths of commands found in `$PATH`, which are forgotten when it changes.
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct PathCache {
+    path: Option<String>,
+    entries: HashMap<String, (PathBuf, usize)>,
+}
+
+impl PathCache {
+    // Looks up `name` in `path` with `search` unless it is cached, counting
+    // the hits.
+    pub fn resolve<F>(&mut self, name: &str, path: Option<&str>, search: F) -> Option<PathBuf>
+    where
+        F: FnOnce(&str, Option<&str>) -> Option<PathBuf>,
+    {
+        if path != self.path.as_deref() {
+            self.entries.clear();
+            self.path = path.map(String::from);
+        }
+
+        if let Some((path, hits)) = self.entries.get_mut(name) {
+            if path.is_file() {
+                *hits += 1;
+                return Some(path.clone());
+            }
+        }
+        let found = search(name, path)?;
+        self.entries.insert(name.to_string(), (found.clone(), 1));
+        Some(found)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn list(&self, path: Option<&str>) -> Vec<(&str, &PathBuf, usize)> {
+        if path != self.path.as_deref() {
+            return Vec::new();
+        }
+        let mut list = self
+            .entries
+            .iter()
+            .map(|(k, (path, hits))| (k.as_str(), path, *hits))
+            .collect::<Vec<_>>();
+        list.sort_by_key(|(name, _, _)| *name);
+        list
+    }
+
+    pub fn encode(&self, w: &mut Writer) {
+        w.opt(self.path.as_deref());
+        w.list(self.entries.iter(), |w, (name, (path, hits))| {
+            w.str(name);
+            w.str(&path.to_string_lossy());
+            w.num(*hits);
+        });
+    }
+
+    pub fn decode(r: &mut Reader) -> Option<Self> {
+        let path = r.opt()?;
+        let entries = r.list(|r| Some((r.str()?, (PathBuf::from(r.str()?), r.num()?))))?;
+        Some(Self {
+            path,
+            entries: entries.into_iter().collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Resolves `name` to the test binary, counting the searches.
+    fn resolve(cache: &mut PathCache, name: &str, path: &str, searched: &mut usize) -> PathBuf {
+        cache
+            .resolve(name, Some(path), |_, _| {
+                *searched += 1;
+                std::env::current_exe().ok()
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn hits() {
+        let mut cache = PathCache::default();
+        let mut searched = 0;
+        resolve(&mut cache, "a", "/bin", &mut searched);
+        resolve(&mut cache, "a", "/bin", &mut searched);
+        assert_eq!(searched, 1);
+        assert_eq!(cache.list(Some("/bin"))[0].2, 2);
+        assert!(cache.list(Some("/usr/bin")).is_empty());
+    }
+
+    #[test]
+    fn clear() {
+        let mut cache = PathCache::default();
+        let mut searched = 0;
+        resolve(&mut cache, "a", "/bin", &mut searched);
+        cache.clear();
+        assert!(cache.list(Some("/bin")).is_empty());
+        resolve(&mut cache, "a", "/bin", &mut searched);
+        assert_eq!(searched, 2);
+        assert_eq!(cache.list(Some("/bin"))[0].2, 1);
+    }
+
+    #[test]
+    fn path_changed() {
+        let mut cache = PathCache::default();
+        let mut searched = 0;
+        resolve(&mut cache, "a", "/bin", &mut searched);
+        resolve(&mut cache, "a", "/usr/bin", &mut searched);
+        assert_eq!(searched, 2);
+        assert!(cache.list(Some("/bin")).is_empty());
+    }
+}
@@ -1,16 +1,46 @@
+use super::state::{Reader, Writer};
 use crate::eval::Block;
 use std::collections::HashMap;
 
 #[derive(Clone, Default, Debug, PartialEq, Eq)]
-pub struct Procs(HashMap<String, Block>);
+pub struct Procs(HashMap<String, (Block, String)>);
 
 impl Procs {
-    pub fn push<T: Into<String>>(&mut self, name: T, block: Block) {
+    pub fn push<T: Into<String>, U: Into<String>>(&mut self, name: T, block: Block, source: U) {
         let name = name.into();
-        self.0.insert(name, block);
+        self.0.insert(name, (block, source.into()));
     }
 
     pub fn get<T: AsRef<str>>(&self, name: T) -> Option<Block> {
-        self.0.get(name.as_ref()).cloned()
+        self.0.get(name.as_ref()).map(|(block, _)| block.clone())
+    }
+
+    pub fn source<T: AsRef<str>>(&self, name: T) -> Option<&str> {
+        self.0.get(name.as_ref()).map(|(_, source)| source.as_str())
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        let mut names = self.0.keys().map(|name| name.as_str()).collect::<Vec<_>>();
+        names.sort_unstable();
+        names
+    }
+
+    // The bodies are parsed again from their sources in a subshell.
+    pub fn encode(&self, w: &mut Writer) {
+        w.list(self.0.iter(), |w, (name, (block, source))| {
+            w.str(name);
+            w.str(source);
+            w.list(block.lines().into_iter(), |w, line| w.num(line));
+        });
+    }
+
+    pub fn decode(r: &mut Reader) -> Option<Self> {
+        let procs = r.list(|r| {
+            let (name, source) = (r.str()?, r.str()?);
+            let mut block = Block::parse_at(&source, 1).ok()?;
+            block.set_lines(&r.list(Reader::num)?);
+            Some((name, (block, source)))
+        })?;
+        Some(Self(procs.into_iter().collect()))
     }
 }
@@ -0,0 +1,189 @@
+use super::{Aliases, Completions, Dynamic, Frame, History, NameSpace, Options, PathCache};
+use super::{Procs, Vars};
+
+// The namespace is passed to a subshell in a child process as a sequence of
+// strings, each prefixed with its length in bytes and `:`.
+#[derive(Default, Debug)]
+pub struct Writer(String);
+
+impl Writer {
+    pub fn str(&mut self, s: &str) {
+        self.0.push_str(&s.len().to_string());
+        self.0.push(':');
+        self.0.push_str(s);
+    }
+
+    pub fn num(&mut self, n: usize) {
+        self.str(&n.to_string());
+    }
+
+    pub fn bool(&mut self, b: bool) {
+        self.num(b as usize);
+    }
+
+    pub fn opt(&mut self, s: Option<&str>) {
+        self.bool(s.is_some());
+        if let Some(s) = s {
+            self.str(s);
+        }
+    }
+
+    // Writes the number of `items` and then each with `f`.
+    pub fn list<T, I, F>(&mut self, items: I, mut f: F)
+    where
+        I: ExactSizeIterator<Item = T>,
+        F: FnMut(&mut Self, T),
+    {
+        self.num(items.len());
+        for item in items {
+            f(self, item);
+        }
+    }
+
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+#[derive(Debug)]
+pub struct Reader<'a>(&'a str);
+
+impl<'a> Reader<'a> {
+    pub fn new(s: &'a str) -> Self {
+        Self(s)
+    }
+
+    pub fn str(&mut self) -> Option<String> {
+        let (len, rest) = self.0.split_at(self.0.find(':')?);
+        let len = len.parse::<usize>().ok()?;
+        let s = rest[1..].get(..len)?;
+        self.0 = &rest[len + 1..];
+        Some(s.to_string())
+    }
+
+    pub fn num(&mut self) -> Option<usize> {
+        self.str()?.parse().ok()
+    }
+
+    pub fn bool(&mut self) -> Option<bool> {
+        Some(self.num()? != 0)
+    }
+
+    pub fn opt(&mut self) -> Option<Option<String>> {
+        Some(if self.bool()? {
+            Some(self.str()?)
+        } else {
+            None
+        })
+    }
+
+    // Reads `n` and then `n` items with `f`.
+    pub fn list<T, F: FnMut(&mut Self) -> Option<T>>(&mut self, mut f: F) -> Option<Vec<T>> {
+        (0..self.num()?).map(|_| f(self)).collect()
+    }
+}
+
+// Jobs, `break` and `continue` on the way to a loop, the warning of `exit`
+// and whether it is a subshell in the shell process belong to the shell
+// itself, so they are left out.
+impl NameSpace {
+    pub fn encode(&self, w: &mut Writer) {
+        self.vars.encode(w);
+        self.procs.encode(w);
+        self.opts.encode(w);
+        self.history.encode(w);
+        self.aliases.encode(w);
+        self.completions.encode(w);
+        self.paths.encode(w);
+        self.dynamic.encode(w);
+        w.num(self.line);
+        w.list(self.frames.iter(), |w, frame| {
+            w.opt(frame.name.as_deref());
+            w.num(frame.line);
+            w.str(&frame.source);
+        });
+        w.num(self.loops);
+        w.list(self.disabled.iter(), |w, name| w.str(name));
+        w.opt(self.shell.as_deref());
+    }
+
+    pub fn decode(r: &mut Reader) -> Option<Self> {
+        Some(Self {
+            vars: Vars::decode(r)?,
+            procs: Procs::decode(r)?,
+            opts: Options::decode(r)?,
+            history: History::decode(r)?,
+            aliases: Aliases::decode(r)?,
+            completions: Completions::decode(r)?,
+            paths: PathCache::decode(r)?,
+            dynamic: Dynamic::decode(r)?,
+            line: r.num()?,
+            frames: r.list(|r| {
+                Some(Frame {
+                    name: r.opt()?,
+                    line: r.num()?,
+                    source: r.str()?,
+                })
+            })?,
+            exit_warned: false,
+            loops: r.num()?,
+            jump: None,
+            disabled: r.list(Reader::str)?.into_iter().collect(),
+            shell: r.opt()?,
+            subshell: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strings() {
+        let mut w = Writer::default();
+        for s in ["", "a:b", "12:3", "日本語", "\0\n"].iter() {
+            w.str(s);
+        }
+        w.opt(None);
+        w.opt(Some("x"));
+        let s = w.into_string();
+        let mut r = Reader::new(&s);
+        for s in ["", "a:b", "12:3", "日本語", "\0\n"].iter() {
+            assert_eq!(r.str().as_deref(), Some(*s));
+        }
+        assert_eq!(r.opt(), Some(None));
+        assert_eq!(r.opt(), Some(Some(String::from("x"))));
+        assert_eq!(r.str(), None);
+    }
+
+    #[test]
+    fn namespace() {
+        let mut ns = NameSpace::default();
+        ns.push_var("x", "a b");
+        ns.push_array("arr", vec![String::from("1"), String::from("")]);
+        ns.set_readonly("x");
+        ns.mark();
+        ns.push_var("y", "local");
+        ns.aliases_mut().push("ll", "ls -l");
+        ns.history_mut().push("echo 1");
+        ns.opts_mut().pipefail = true;
+        ns.opts_mut().loop_limit = Some(10);
+        ns.set_builtin_enabled("echo", false);
+        let source = "{\n    echo ${x}\n}";
+        ns.push_proc("f", source.parse().unwrap(), source);
+        ns.set_line(3);
+
+        let mut w = Writer::default();
+        ns.encode(&mut w);
+        let s = w.into_string();
+        let decoded = NameSpace::decode(&mut Reader::new(&s)).unwrap();
+        assert_eq!(decoded.vars, ns.vars);
+        assert_eq!(decoded.procs, ns.procs);
+        assert_eq!(decoded.opts, ns.opts);
+        assert_eq!(decoded.history, ns.history);
+        assert_eq!(decoded.aliases, ns.aliases);
+        assert_eq!(decoded.disabled, ns.disabled);
+        assert_eq!(decoded.line, 3);
+    }
+}
@@ -1,11 +1,37 @@
+use super::state::{Reader, Writer};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
+use std::fmt;
 
+// A variable with its exported value and its local value.
+type Saved = (String, Option<String>, Option<String>);
+
+// Exported variables live in the environment of the process, so commands
+// inherit them, while the others are kept in `locals`. A base environment
+// given by an embedder is kept in `env` instead, and replaces the one of the
+// process for commands.
 #[derive(Clone, Default, Debug, PartialEq, Eq)]
 pub struct Vars {
     keys: Vec<String>,
     offsets: Vec<usize>,
+    locals: HashMap<String, String>,
+    arrays: HashMap<String, Vec<String>>,
+    readonly: HashSet<String>,
+    env: Option<BTreeMap<String, String>>,
+}
+
+// An assignment to or `unset` of a variable made by `readonly`.
+#[derive(Debug)]
+pub struct ReadonlyError(String);
+
+impl fmt::Display for ReadonlyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: readonly variable", self.0)
+    }
 }
 
+impl std::error::Error for ReadonlyError {}
+
 impl Vars {
     pub fn set_args<T, U, US>(&mut self, name: T, args: US)
     where
@@ -13,10 +39,18 @@ impl Vars {
         U: AsRef<str>,
         US: IntoIterator<Item = U>,
     {
-        let name = name.as_ref();
+        self.push("0", name.as_ref());
+        self.set_positional(args);
+    }
+
+    // Sets `$1`, `$2`, ..., `$#` and `$@`, leaving `$0` as is.
+    pub fn set_positional<U, US>(&mut self, args: US)
+    where
+        U: AsRef<str>,
+        US: IntoIterator<Item = U>,
+    {
         let args = args.into_iter().collect::<Vec<_>>();
         self.push("#", args.len().to_string());
-        self.push("0", name);
         for (i, arg) in args.iter().enumerate() {
             self.push((i + 1).to_string(), arg);
         }
@@ -32,17 +66,159 @@ impl Vars {
     pub fn push<T: Into<String>, U: AsRef<str>>(&mut self, key: T, value: U) {
         let key = key.into();
         let value = value.as_ref();
-        let exists = env::var(&key).is_ok();
-        env::set_var(&key, value);
+        if self.env_get(&key).is_some() {
+            self.env_set(&key, value);
+        } else if self.locals.insert(key.clone(), value.to_string()).is_none() {
+            self.keys.push(key);
+        }
+    }
+
+    pub fn push_array<T: Into<String>>(&mut self, key: T, values: Vec<String>) {
+        let key = key.into();
+        let exists = self.arrays.contains_key(&key)
+            || self.locals.contains_key(&key)
+            || self.env_get(&key).is_some();
+        self.env_remove(&key);
+        self.locals.remove(&key);
+        self.arrays.insert(key.clone(), values);
         if !exists {
             self.keys.push(key);
         }
     }
 
+    // Resolves `name`, `name[N]`, `name[@]`, `#name[@]` (the number of
+    // elements) and `#name` (the length of the value).
+    pub fn get(&self, key: &str) -> Option<String> {
+        if let Some(name) = key.strip_prefix('#').filter(|name| !name.is_empty()) {
+            return match subscript(name) {
+                Some((name, "@")) | Some((name, "*")) => {
+                    Some(self.arrays.get(name).map_or(0, |arr| arr.len()).to_string())
+                }
+                _ => Some(self.get(name)?.chars().count().to_string()),
+            };
+        }
+
+        match subscript(key) {
+            Some((name, "@")) | Some((name, "*")) => Some(self.arrays.get(name)?.join(" ")),
+            Some((name, index)) => self
+                .arrays
+                .get(name)?
+                .get(index.parse::<usize>().ok()?)
+                .cloned(),
+            None => match self.arrays.get(key) {
+                Some(arr) => arr.first().cloned(),
+                None => self.locals.get(key).cloned().or_else(|| self.env_get(key)),
+            },
+        }
+    }
+
     pub fn gpush<T: AsRef<str>, U: AsRef<str>>(&mut self, key: T, value: U) {
         let key = key.as_ref();
         let value = value.as_ref();
-        env::set_var(key, value);
+        self.locals.remove(key);
+        self.env_set(key, value);
+    }
+
+    // Exports `vars` for a while, returning what they have replaced.
+    pub fn export_temp(&mut self, vars: &[(String, String)]) -> Vec<Saved> {
+        vars.iter()
+            .map(|(key, value)| {
+                let saved = (key.clone(), self.env_get(key), self.locals.remove(key));
+                self.env_set(key, value);
+                saved
+            })
+            .collect()
+    }
+
+    pub fn restore_temp(&mut self, saved: Vec<Saved>) {
+        for (key, env, local) in saved.into_iter().rev() {
+            match env {
+                Some(value) => self.env_set(&key, &value),
+                None => self.env_remove(&key),
+            }
+            if let Some(local) = local {
+                self.locals.insert(key, local);
+            }
+        }
+    }
+
+    // The environment given to commands.
+    pub fn env_snapshot(&self) -> BTreeMap<String, String> {
+        match &self.env {
+            Some(vars) => vars.clone(),
+            None => env::vars().collect(),
+        }
+    }
+
+    // Whether commands get `env_snapshot` instead of inheriting the
+    // environment of the process.
+    pub fn has_base_env(&self) -> bool {
+        self.env.is_some()
+    }
+
+    // Exported variables are kept in `env` from now on, starting from `vars`.
+    pub fn set_base_env(&mut self, vars: BTreeMap<String, String>) {
+        for key in vars.keys() {
+            self.locals.remove(key);
+        }
+        self.env = Some(vars);
+    }
+
+    fn env_get(&self, key: &str) -> Option<String> {
+        match &self.env {
+            Some(vars) => vars.get(key).cloned(),
+            None => env::var(key).ok(),
+        }
+    }
+
+    fn env_set(&mut self, key: &str, value: &str) {
+        match &mut self.env {
+            Some(vars) => {
+                vars.insert(key.to_string(), value.to_string());
+            }
+            None => env::set_var(key, value),
+        }
+    }
+
+    fn env_remove(&mut self, key: &str) {
+        match &mut self.env {
+            Some(vars) => {
+                vars.remove(key);
+            }
+            None => env::remove_var(key),
+        }
+    }
+
+    // The exported variables and the local ones, without arrays.
+    pub fn snapshot(&self) -> BTreeMap<String, String> {
+        let mut vars = self.env_snapshot();
+        vars.extend(self.locals.clone());
+        vars
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.env_remove(key);
+        self.locals.remove(key);
+        self.arrays.remove(key);
+    }
+
+    pub fn set_readonly<T: Into<String>>(&mut self, key: T) {
+        self.readonly.insert(key.into());
+    }
+
+    pub fn check_writable(&self, key: &str) -> Result<(), ReadonlyError> {
+        if self.readonly.contains(key) {
+            Err(ReadonlyError(key.to_string()))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn readonly_snapshot(&self) -> BTreeMap<String, String> {
+        self.readonly
+            .iter()
+            .map(|key| (key.clone(), self.get(key).unwrap_or_default()))
+            .collect()
     }
 
     pub fn mark(&mut self) {
@@ -52,8 +228,55 @@ impl Vars {
 
     pub fn drop(&mut self) {
         let offset = self.offsets.pop().unwrap_or(0);
-        for key in self.keys.drain(offset..) {
-            env::remove_var(key);
+        for key in self.keys.split_off(offset) {
+            self.env_remove(&key);
+            self.locals.remove(&key);
+            self.arrays.remove(&key);
+            self.readonly.remove(&key);
+        }
+    }
+}
+
+impl Vars {
+    pub fn encode(&self, w: &mut Writer) {
+        let pair = |w: &mut Writer, (key, value): (&String, &String)| {
+            w.str(key);
+            w.str(value);
+        };
+        w.list(self.keys.iter(), |w, key| w.str(key));
+        w.list(self.offsets.iter(), |w, offset| w.num(*offset));
+        w.list(self.locals.iter(), pair);
+        w.list(self.arrays.iter(), |w, (key, values)| {
+            w.str(key);
+            w.list(values.iter(), |w, value| w.str(value));
+        });
+        w.list(self.readonly.iter(), |w, key| w.str(key));
+        w.bool(self.env.is_some());
+        if let Some(vars) = &self.env {
+            w.list(vars.iter(), pair);
         }
     }
+
+    pub fn decode(r: &mut Reader) -> Option<Self> {
+        let pair = |r: &mut Reader| Some((r.str()?, r.str()?));
+        Some(Self {
+            keys: r.list(Reader::str)?,
+            offsets: r.list(Reader::num)?,
+            locals: r.list(pair)?.into_iter().collect(),
+            arrays: r
+                .list(|r| Some((r.str()?, r.list(Reader::str)?)))?
+                .into_iter()
+                .collect(),
+            readonly: r.list(Reader::str)?.into_iter().collect(),
+            env: match r.bool()? {
+                true => Some(r.list(pair)?.into_iter().collect()),
+                false => None,
+            },
+        })
+    }
+}
+
+fn subscript(key: &str) -> Option<(&str, &str)> {
+    let (name, rest) = key.split_at(key.find('[')?);
+    Some((name, rest.strip_prefix('[')?.strip_suffix(']')?))
 }
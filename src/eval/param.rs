@@ -0,0 +1,172 @@
+use super::{glob, NameSpace};
+
+// Expands the inside of `${...}`, a name followed by an operator if any.
+pub fn expand_param(key: &str, ns: &NameSpace) -> anyhow::Result<Option<String>> {
+    // `${!name}` takes the value of the variable named by `$name`, which is
+    // resolved only once.
+    if let Some(inner) = key.strip_prefix('!').filter(|inner| !inner.is_empty()) {
+        let (name, op) = split(inner);
+        return match ns.get_var(name) {
+            Some(target) if is_name(&target) => expand_param(&format!("{}{}", target, op), ns),
+            Some(target) => anyhow::bail!("{}: invalid variable name", target),
+            None => Ok(None),
+        };
+    }
+
+    let (name, op) = split(key);
+    let value = match ns.get_var(name) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+
+    // Letters are mapped by Unicode, or only ASCII ones with `asciicase`
+    // like the C locale.
+    let ascii = ns.opts().asciicase;
+    Ok(Some(match op {
+        "" => value,
+        "^" if ascii => map_first(&value, |c| std::iter::once(c.to_ascii_uppercase())),
+        "^^" if ascii => value.to_ascii_uppercase(),
+        "," if ascii => map_first(&value, |c| std::iter::once(c.to_ascii_lowercase())),
+        ",," if ascii => value.to_ascii_lowercase(),
+        "^" => map_first(&value, char::to_uppercase),
+        "^^" => value.to_uppercase(),
+        "," => map_first(&value, char::to_lowercase),
+        ",," => value.to_lowercase(),
+        _ if op.starts_with("##") => trim_prefix(&value, &op[2..], true),
+        _ if op.starts_with('#') => trim_prefix(&value, &op[1..], false),
+        _ if op.starts_with("%%") => trim_suffix(&value, &op[2..], true),
+        _ if op.starts_with('%') => trim_suffix(&value, &op[1..], false),
+        _ => anyhow::bail!("${{{}}}: bad substitution", key),
+    }))
+}
+
+// `#name` is the length of a name, and a subscript belongs to the name.
+fn split(key: &str) -> (&str, &str) {
+    if key.len() > 1 && key.starts_with('#') {
+        return (key, "");
+    }
+
+    let end = match key.chars().next() {
+        Some(c) if c.is_ascii_alphanumeric() || c == '_' => key
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(key.len()),
+        Some(c) => c.len_utf8(),
+        None => 0,
+    };
+    let end = match key[end..].strip_prefix('[') {
+        Some(rest) => rest.find(']').map_or(end, |i| end + i + 2),
+        None => end,
+    };
+    key.split_at(end)
+}
+
+// A name, a positional parameter or an element of an array.
+fn is_name(target: &str) -> bool {
+    target
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+        && split(target).1.is_empty()
+}
+
+fn map_first<I: Iterator<Item = char>>(s: &str, f: fn(char) -> I) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) => f(c).chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+// Removes the shortest (or the longest) prefix matching the pattern.
+fn trim_prefix(s: &str, pat: &str, longest: bool) -> String {
+    let mut ends = s
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(Some(s.len()))
+        .collect::<Vec<_>>();
+    if longest {
+        ends.reverse();
+    }
+    match ends.into_iter().find(|i| glob::matches(pat, &s[..*i])) {
+        Some(i) => s[i..].to_string(),
+        None => s.to_string(),
+    }
+}
+
+// Removes the shortest (or the longest) suffix matching the pattern.
+fn trim_suffix(s: &str, pat: &str, longest: bool) -> String {
+    let mut starts = s
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(Some(s.len()))
+        .collect::<Vec<_>>();
+    if !longest {
+        starts.reverse();
+    }
+    match starts.into_iter().find(|i| glob::matches(pat, &s[*i..])) {
+        Some(i) => s[..i].to_string(),
+        None => s.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ns(vars: &[(&str, &str)]) -> NameSpace {
+        let mut ns = NameSpace::default();
+        for (key, value) in vars {
+            ns.push_var(*key, value);
+        }
+        ns
+    }
+
+    #[test]
+    fn indirection() {
+        let ns = ns(&[("a", "b"), ("b", "hello")]);
+        assert_eq!(expand_param("!a", &ns).unwrap().as_deref(), Some("hello"));
+        assert_eq!(expand_param("!a^", &ns).unwrap().as_deref(), Some("Hello"));
+    }
+
+    #[test]
+    fn indirection_resolves_one_level() {
+        let ns = ns(&[("a", "!a")]);
+        let e = expand_param("!a", &ns).unwrap_err();
+        assert_eq!(e.to_string(), "!a: invalid variable name");
+    }
+
+    #[test]
+    fn indirection_to_an_element() {
+        let mut ns = ns(&[("a", "arr[1]")]);
+        ns.push_array("arr", vec![String::from("x"), String::from("y")]);
+        assert_eq!(expand_param("!a", &ns).unwrap().as_deref(), Some("y"));
+    }
+
+    fn expand(key: &str, ns: &NameSpace) -> String {
+        expand_param(key, ns).unwrap().unwrap()
+    }
+
+    #[test]
+    fn case() {
+        let mut ns = ns(&[("v", "éCOLE mixed")]);
+        assert_eq!(expand("v^", &ns), "ÉCOLE mixed");
+        assert_eq!(expand("v^^", &ns), "ÉCOLE MIXED");
+        assert_eq!(expand("v,", &ns), "éCOLE mixed");
+        assert_eq!(expand("v,,", &ns), "école mixed");
+
+        ns.opts_mut().asciicase = true;
+        assert_eq!(expand("v^", &ns), "éCOLE mixed");
+        assert_eq!(expand("v^^", &ns), "éCOLE MIXED");
+    }
+
+    #[test]
+    fn trim() {
+        let ns = ns(&[("f", "/usr/src/a.tar.gz")]);
+        assert_eq!(expand("f%.*", &ns), "/usr/src/a.tar");
+        assert_eq!(expand("f%%.*", &ns), "/usr/src/a");
+        assert_eq!(expand("f#*/", &ns), "usr/src/a.tar.gz");
+        assert_eq!(expand("f##*/", &ns), "a.tar.gz");
+        assert_eq!(expand("f%.zip", &ns), "/usr/src/a.tar.gz");
+        assert_eq!(expand("f#/usr/", &ns), "src/a.tar.gz");
+    }
+}
@@ -0,0 +1,106 @@
+use anyhow::Context;
+use nix::libc;
+use std::ffi::CString;
+
+// A POSIX extended regular expression from the C library, like `=~` of bash,
+// with the number of the groups in it.
+pub struct Regex(Box<libc::regex_t>, usize);
+
+impl Regex {
+    pub fn new(pat: &str) -> anyhow::Result<Self> {
+        let pat = CString::new(pat).context("The regex contains a null character.")?;
+        let mut re = Box::new(unsafe { std::mem::zeroed::<libc::regex_t>() });
+        let err = unsafe { libc::regcomp(&mut *re, pat.as_ptr(), libc::REG_EXTENDED) };
+        if err != 0 {
+            anyhow::bail!("{}: invalid regex", pat.to_string_lossy());
+        }
+        Ok(Self(re, groups(&pat.to_string_lossy())))
+    }
+
+    // The whole match and the groups, with an empty string for a group which
+    // didn't take part in the match.
+    pub fn captures(&self, s: &str) -> anyhow::Result<Option<Vec<String>>> {
+        let cs = CString::new(s).context("The string contains a null character.")?;
+        let mut matches = vec![
+            libc::regmatch_t {
+                rm_so: -1,
+                rm_eo: -1
+            };
+            self.1 + 1
+        ];
+        let err = unsafe {
+            libc::regexec(
+                &*self.0,
+                cs.as_ptr(),
+                matches.len(),
+                matches.as_mut_ptr(),
+                0,
+            )
+        };
+        if err != 0 {
+            return Ok(None);
+        }
+        Ok(Some(
+            matches
+                .iter()
+                .map(|m| match (m.rm_so, m.rm_eo) {
+                    (so, eo) if so >= 0 => {
+                        String::from_utf8_lossy(&s.as_bytes()[so as usize..eo as usize])
+                            .into_owned()
+                    }
+                    _ => String::new(),
+                })
+                .collect(),
+        ))
+    }
+}
+
+impl Drop for Regex {
+    fn drop(&mut self) {
+        unsafe { libc::regfree(&mut *self.0) };
+    }
+}
+
+// Counts the opening parentheses outside brackets, since `regex_t` doesn't
+// expose the number.
+fn groups(pat: &str) -> usize {
+    let mut chars = pat.chars();
+    let mut count = 0;
+    let mut bracket = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if !bracket => {
+                chars.next();
+            }
+            '[' if !bracket => {
+                bracket = true;
+                // `]` right after `[` or `[^` is a member.
+                let rest = chars.as_str();
+                let skip = if rest.starts_with("^]") {
+                    2
+                } else {
+                    rest.starts_with(']') as usize
+                };
+                for _ in 0..skip {
+                    chars.next();
+                }
+            }
+            ']' if bracket => bracket = false,
+            '(' if !bracket => count += 1,
+            _ => (),
+        }
+    }
+    count
+}
+
+// Matches the characters of `s` literally.
+pub fn escape(s: &str) -> String {
+    let mut res = String::new();
+    for c in s.chars() {
+        if "\\^$.|?*+()[]{}".contains(c) {
+            res.push('\\');
+        }
+        res.push(c);
+    }
+    res
+}
@@ -0,0 +1,95 @@
+use super::NameSpace;
+use anyhow::Context;
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+// A file under `$TMPDIR` (or the system one), removed when dropped.
+#[derive(Debug)]
+pub struct TempFile(PathBuf);
+
+impl TempFile {
+    pub fn new(ns: &NameSpace, prefix: &str) -> anyhow::Result<(Self, File)> {
+        let dir = temp_dir(ns);
+        loop {
+            let n = COUNT.fetch_add(1, Ordering::Relaxed);
+            let path = dir.join(format!("tish-{}-{}-{}", prefix, std::process::id(), n));
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => return Ok((Self(path), file)),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => {
+                    return Err(e).with_context(|| {
+                        format!("Failed to create a temporary file in {}.", dir.display())
+                    })
+                }
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.0).ok();
+    }
+}
+
+// `$TMPDIR`, or the system one if it is unset or empty.
+pub fn temp_dir(ns: &NameSpace) -> PathBuf {
+    ns.get_var("TMPDIR")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+}
+
+// Creates a file, or a directory with `dir`, only the user can access, with
+// the `X`s at the end of the template replaced, like `mktemp`. The path is
+// kept unlike `TempFile`.
+pub fn make_temp(template: &str, dir: bool) -> anyhow::Result<PathBuf> {
+    use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+
+    let base = template.trim_end_matches('X');
+    let len = template.len() - base.len();
+    if len < 3 {
+        anyhow::bail!("{}: too few X's in the template", template);
+    }
+
+    const CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64);
+    let mut seed = nanos ^ ((std::process::id() as u64) << 32);
+    loop {
+        seed = seed
+            .wrapping_add(COUNT.fetch_add(1, Ordering::Relaxed) as u64)
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        let mut bits = seed;
+        let mut path = String::from(base);
+        for _ in 0..len {
+            path.push(CHARS[(bits % CHARS.len() as u64) as usize] as char);
+            bits /= CHARS.len() as u64;
+        }
+
+        let res = if dir {
+            std::fs::DirBuilder::new().mode(0o700).create(&path)
+        } else {
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&path)
+                .map(|_| ())
+        };
+        match res {
+            Ok(()) => return Ok(PathBuf::from(path)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => anyhow::bail!("{}: {}", path, e),
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use super::{glob, NameSpace};
+use crate::job::SharedJobs;
+use crate::parse::SpecialStr;
+
+// How the value of a word is split into fields before globbing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Split {
+    None,
+    // By whitespace, as `!word` does even when it is quoted.
+    Whitespace,
+    // By lines, as the list of `for` does.
+    Lines,
+}
+
+// Expands a word the same way wherever it appears, as an argument, in the
+// list of `for` or as a redirect target. Tildes, parameters, command
+// substitutions and arithmetic are expanded by `SpecialStr::eval`, and then
+// an unquoted value is split into fields and each of them is globbed. The
+// subject of `case` and the words of `[[ ... ]]` stay one word as in bash,
+// so they only take the first step.
+pub fn expand(
+    word: &SpecialStr,
+    split: Split,
+    jobs: &SharedJobs,
+    ns: &mut NameSpace,
+) -> anyhow::Result<Vec<String>> {
+    let value = word.eval(jobs, ns)?;
+    let fields = match split {
+        Split::Whitespace => value.split_whitespace().collect::<Vec<_>>(),
+        _ if word.is_quoted() => return Ok(vec![value]),
+        Split::Lines => value.lines().collect(),
+        Split::None => vec![value.as_str()],
+    };
+
+    let mut res = Vec::new();
+    for field in fields {
+        res.extend(glob_field(field, ns)?);
+    }
+    Ok(res)
+}
+
+// A pattern without matches is left as it is, unless `nullglob` or
+// `failglob` is set.
+fn glob_field(field: &str, ns: &NameSpace) -> anyhow::Result<Vec<String>> {
+    if !glob::is_pattern(field) {
+        return Ok(vec![field.to_string()]);
+    }
+
+    let paths = glob::expand(field, ns.opts().globstar);
+    if !paths.is_empty() {
+        Ok(paths)
+    } else if ns.opts().failglob {
+        anyhow::bail!("No match: {}", field)
+    } else if ns.opts().nullglob {
+        Ok(Vec::new())
+    } else {
+        Ok(vec![field.to_string()])
+    }
+}
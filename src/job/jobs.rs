@@ -1,21 +1,33 @@
-use super::{Process, Status};
+use super::{Process, Signal, Status};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
 use anyhow::Context;
 
-#[derive(Debug)]
+// Set on SIGINT, so that builtins blocking in the shell itself can give up.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// Whether SIGINT has arrived since the last call.
+pub fn interrupted() -> bool {
+    INTERRUPTED.swap(false, Ordering::SeqCst)
+}
+
+#[derive(Debug, Default)]
 pub struct SharedJobs(Arc<Mutex<Jobs>>);
 
+// Clones share the same table.
+impl Clone for SharedJobs {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
 impl SharedJobs {
     pub fn new() -> Self {
         Self(Arc::new(Mutex::new(Jobs::new())))
     }
 
-    pub fn clone(&self) -> Self {
-        Self(Arc::clone(&self.0))
-    }
-
     pub fn with<F, T>(&self, f: F) -> anyhow::Result<T>
     where
         F: FnOnce(&mut Jobs) -> anyhow::Result<T>,
@@ -28,11 +40,41 @@ impl SharedJobs {
         f(&mut lock)
     }
 
+    // The lock is released while waiting, so that signals and background
+    // jobs are handled meanwhile.
     pub fn wait_fg(&self) -> anyhow::Result<Option<Status>> {
-        let mut jobs = self.get()?;
-        let status = jobs.wait_fg()?;
-        self.store(jobs)?;
-        Ok(status)
+        let proc = match self.with(|jobs| Ok(jobs.0.get(&0).copied()))? {
+            Some(proc) => proc,
+            None => return Ok(None),
+        };
+        let status = proc.wait()?;
+        self.with(|jobs| jobs.finish_fg(status)).map(Some)
+    }
+
+    // Blocks until the background job `pid`, or any one if `None`, finishes,
+    // and returns its status. `None` is returned if there is no such job.
+    pub fn wait_bg(&self, pid: Option<i32>) -> anyhow::Result<Option<(usize, i32, Status)>> {
+        interrupted();
+        loop {
+            let (finished, running) = self.with(|jobs| {
+                jobs.reap()?;
+                let finished = jobs
+                    .take_finished(pid)
+                    .map(|(id, proc, status)| (id, proc.pid(), status));
+                let running = jobs
+                    .0
+                    .iter()
+                    .any(|(id, proc)| *id != 0 && pid.is_none_or(|pid| proc.pid() == pid));
+                Ok((finished, running))
+            })?;
+            if finished.is_some() || !running {
+                return Ok(finished);
+            }
+            if interrupted() {
+                anyhow::bail!("Interrupted");
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
     }
 
     pub fn get(&self) -> anyhow::Result<Jobs> {
@@ -43,25 +85,30 @@ impl SharedJobs {
 
         Ok((*lock).clone())
     }
-
-    pub fn store(&self, jobs: Jobs) -> anyhow::Result<()> {
-        let cloned = Arc::clone(&self.0);
-        let mut lock = match cloned.lock() {
-            Ok(l) => l,
-            Err(e) => anyhow::bail!("Failed to get the lock: {}", e),
-        };
-
-        *lock = jobs;
-        Ok(())
-    }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct Jobs(HashMap<usize, Process>);
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Jobs(
+    HashMap<usize, Process>,
+    Vec<(usize, Process, Status)>,
+    Notices,
+    // The pid of the last background job for `$!`.
+    Option<i32>,
+    // The sources of compound commands in the background by pid.
+    HashMap<i32, String>,
+);
+
+// Messages about background jobs, which are held until the next prompt while
+// `hold` is set.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Notices {
+    hold: bool,
+    pending: Vec<String>,
+}
 
 impl Jobs {
     pub fn new() -> Self {
-        Self(HashMap::new())
+        Self::default()
     }
 
     pub fn new_fg(&mut self, pid: i32) -> anyhow::Result<()> {
@@ -73,90 +120,117 @@ impl Jobs {
         Ok(())
     }
 
-    #[allow(dead_code)]
     pub fn new_bg(&mut self, pid: i32) -> anyhow::Result<(usize, i32)> {
         let id = self.get_available_id();
         let proc = Process::from(pid);
         self.0.insert(id, proc);
+        self.3 = Some(pid);
         Ok((id, pid))
     }
 
-    pub fn wait_fg(&mut self) -> anyhow::Result<Option<Status>> {
-        let res = match self.0.get(&0) {
-            Some(proc) => Some(proc.wait()?),
-            None => None,
-        };
-        let res = match res {
-            Some(status) if status.stopped() => {
-                let mut proc = self.0.remove(&0).unwrap();
+    fn finish_fg(&mut self, status: Status) -> anyhow::Result<Status> {
+        let proc = self.0.remove(&0);
+        if status.stopped() {
+            if let Some(mut proc) = proc {
                 let id = self.get_available_id();
                 eprintln!("\nSuspended: %{} ({})", id, proc.pid());
                 proc.suspended = true;
                 self.0.insert(id, proc);
-                res
-            }
-            Some(status) if status.interrupted() => {
-                anyhow::bail!("Interrupted");
             }
-            Some(_) => {
-                self.0.remove(&0);
-                res
-            }
-            None => None,
-        };
-        Ok(res)
+        } else if status.interrupted() {
+            anyhow::bail!("Interrupted");
+        }
+        Ok(status)
     }
 
     pub fn sigchld(&mut self) -> anyhow::Result<()> {
-        let (pid, status) = match super::process::sigchld()? {
-            Some(s) => s,
-            None => return Ok(()),
-        };
+        for (id, proc, status) in self.reap()? {
+            let pid = proc.pid();
+            let state = match status {
+                s if s.continued() => String::from("continued"),
+                s if s.stopped() => String::from("stopped"),
+                Status::Signaled(s) => format!("terminated with signal \"{}\"", s),
+                Status::Exited(c) => format!("exited with code \"{}\"", c),
+            };
+            self.notify(format!("[Background process %{} ({}) {}]", id, pid, state));
+        }
 
-        if self.0.get(&0).map(|proc| proc.pid()) == Some(pid) {
-            return Ok(());
+        Ok(())
+    }
+
+    // Each message is written at once, so that it isn't split by the output
+    // of other processes.
+    fn notify(&mut self, msg: String) {
+        use std::io::Write;
+
+        if self.2.hold {
+            self.2.pending.push(msg);
+        } else {
+            std::io::stderr()
+                .write_all(format!("\n{}\n", msg).as_bytes())
+                .ok();
         }
+    }
 
-        let id = match self.from_pid(pid) {
-            Some(id) => id,
-            None => return Ok(()),
-        };
+    pub fn set_hold(&mut self, hold: bool) {
+        self.2.hold = hold;
+    }
 
-        let mut proc = self.0.remove(&id).context("Failed to get the process.")?;
+    pub fn take_notices(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.2.pending)
+    }
 
-        match status {
-            s if s.continued() => {
-                eprintln!("\n[Background process %{} ({}) continued]", id, pid);
-                proc.suspended = false;
-                self.0.insert(id, proc);
+    // Updates the background jobs whose state has changed, and returns them.
+    // Finished ones are kept aside until they are waited for.
+    fn reap(&mut self) -> anyhow::Result<Vec<(usize, Process, Status)>> {
+        let mut changed = Vec::new();
+        for (id, mut proc) in self.list() {
+            if id == 0 {
+                continue;
             }
-            s if s.stopped() => {
-                eprintln!("\n[Background process %{} ({}) stopped]", id, pid);
-                proc.suspended = true;
+            let status = match proc.try_wait()? {
+                Some(status) => status,
+                None => continue,
+            };
+
+            if status.continued() || status.stopped() {
+                proc.suspended = status.stopped();
                 self.0.insert(id, proc);
+            } else {
+                self.0.remove(&id);
+                self.4.remove(&proc.pid());
+                self.1.push((id, proc, status));
             }
-            Status::Signaled(s) => {
-                eprintln!(
-                    "\n[Background process %{} ({}) terminated with signal \"{}\"]",
-                    id, pid, s
-                );
-            }
-            Status::Exited(c) => {
-                eprintln!(
-                    "\n[Background process %{} ({}) exited with code \"{}\"]",
-                    id, pid, c
-                );
-            }
+            changed.push((id, proc, status));
         }
+        Ok(changed)
+    }
 
-        Ok(())
+    // Takes a finished job, `pid` or any one.
+    fn take_finished(&mut self, pid: Option<i32>) -> Option<(usize, Process, Status)> {
+        let index = self
+            .1
+            .iter()
+            .position(|(_, proc, _)| pid.is_none_or(|pid| proc.pid() == pid))?;
+        Some(self.1.remove(index))
     }
 
     pub fn sigint(&mut self) -> anyhow::Result<()> {
+        INTERRUPTED.store(true, Ordering::SeqCst);
         self.interrupt(0)?;
         Ok(())
     }
 
+    // Passes SIGHUP on to the jobs, except those with `disown -h`, and exits.
+    pub fn sighup(&mut self) -> anyhow::Result<()> {
+        for (_, proc) in self.list() {
+            if !proc.nohup {
+                proc.hangup().ok();
+            }
+        }
+        std::process::exit(128 + Signal::SIGHUP as i32);
+    }
+
     pub fn sigtstp(&mut self) -> anyhow::Result<()> {
         self.suspend(0)
     }
@@ -198,7 +272,81 @@ impl Jobs {
         Ok(())
     }
 
-    pub fn from_pid(&self, pid: i32) -> Option<usize> {
+    // Forgets the job, leaving the process as it is.
+    pub fn disown(&mut self, id: usize) -> Option<Process> {
+        self.0.remove(&id)
+    }
+
+    pub fn nohup(&mut self, id: usize) {
+        if let Some(proc) = self.0.get_mut(&id) {
+            proc.nohup = true;
+        }
+    }
+
+    pub fn set_label(&mut self, pid: i32, label: String) {
+        self.4.insert(pid, label);
+    }
+
+    pub fn label(&self, pid: i32) -> Option<&str> {
+        self.4.get(&pid).map(|label| label.as_str())
+    }
+
+    pub fn last_pid(&self) -> Option<i32> {
+        self.3
+    }
+
+    // The id of the most recent background job.
+    pub fn last_id(&self) -> Option<usize> {
+        self.0.keys().filter(|id| **id != 0).max().copied()
+    }
+
+    // Resolves a pid or a job spec: `%%`, `%+` or `%` for the current job,
+    // `%-` for the previous one, `%n` by the number, `%str` by the start of
+    // the command and `%?str` by a part of it. The current job is the latest
+    // suspended one, or the latest one if none is suspended. A command
+    // matching several jobs resolves to none.
+    pub fn resolve_job_spec(&self, spec: &str) -> Option<usize> {
+        let spec = match spec.strip_prefix('%') {
+            Some(spec) => spec,
+            None => return self.find_pid(spec.parse().ok()?),
+        };
+
+        let mut recent = self
+            .list()
+            .into_iter()
+            .filter(|(id, _)| *id != 0)
+            .collect::<Vec<_>>();
+        recent.sort_by_key(|(id, proc)| std::cmp::Reverse((proc.suspended(), *id)));
+        let matching = |f: &dyn Fn(&str) -> bool| {
+            let mut found = recent
+                .iter()
+                .filter(|(_, proc)| self.label(proc.pid()).is_some_and(f));
+            match (found.next(), found.next()) {
+                (Some((id, _)), None) => Some(*id),
+                _ => None,
+            }
+        };
+        match spec {
+            "" | "%" | "+" => recent.first().map(|(id, _)| *id),
+            "-" => recent.get(1).map(|(id, _)| *id),
+            n if n.parse::<usize>().is_ok() => {
+                let id = n.parse().ok()?;
+                Some(id).filter(|id| *id != 0 && self.0.contains_key(id))
+            }
+            spec => match spec.strip_prefix('?') {
+                Some(part) => matching(&|label| label.contains(part)),
+                None => matching(&|label| label.starts_with(spec)),
+            },
+        }
+    }
+
+    pub fn list(&self) -> Vec<(usize, Process)> {
+        let mut list = self.0.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>();
+        list.sort_by_key(|(id, _)| *id);
+        list
+    }
+
+    pub fn find_pid(&self, pid: i32) -> Option<usize> {
         self.0.iter().find(|(_, v)| v.pid() == pid).map(|(k, _)| *k)
     }
 
@@ -211,6 +359,25 @@ impl Jobs {
     }
 
     fn get_available_id(&self) -> usize {
-        (1..).find(|i| !self.0.contains_key(&i)).unwrap()
+        (1..).find(|i| !self.0.contains_key(i)).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn held_notices() {
+        let mut jobs = Jobs::new();
+        jobs.set_hold(true);
+        jobs.notify(String::from("first"));
+        jobs.notify(String::from("second"));
+        assert_eq!(jobs.take_notices(), ["first", "second"]);
+        assert!(jobs.take_notices().is_empty());
+
+        jobs.set_hold(false);
+        jobs.notify(String::from("written"));
+        assert!(jobs.take_notices().is_empty());
     }
 }
@@ -2,9 +2,13 @@ extern crate nix;
 
 mod jobs;
 mod process;
+mod rusage;
+mod signal;
 mod status;
 
-pub use jobs::{Jobs, SharedJobs};
+pub use jobs::{interrupted, SharedJobs};
 pub use nix::sys::signal::Signal;
 pub use process::Process;
+pub use rusage::{format_time, CpuTimes};
+pub use signal::{parse_signal, signal_name};
 pub use status::Status;
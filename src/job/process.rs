@@ -11,6 +11,7 @@ use super::{Signal, Status};
 pub struct Process {
     pub(super) pid: Pid,
     pub(super) suspended: bool,
+    pub(super) nohup: bool,
 }
 
 impl fmt::Display for Process {
@@ -31,13 +32,14 @@ impl From<i32> for Process {
         Self {
             pid: Pid::from_raw(id),
             suspended: false,
+            nohup: false,
         }
     }
 }
 
-impl Into<i32> for Process {
-    fn into(self) -> i32 {
-        self.pid.as_raw()
+impl From<Process> for i32 {
+    fn from(proc: Process) -> Self {
+        proc.pid.as_raw()
     }
 }
 
@@ -46,13 +48,14 @@ impl From<Pid> for Process {
         Self {
             pid: id,
             suspended: false,
+            nohup: false,
         }
     }
 }
 
-impl Into<Pid> for Process {
-    fn into(self) -> Pid {
-        self.pid
+impl From<Process> for Pid {
+    fn from(proc: Process) -> Self {
+        proc.pid
     }
 }
 
@@ -70,6 +73,15 @@ impl Process {
         Ok(Status::Signaled(Signal::SIGINT))
     }
 
+    // Sends SIGHUP, and SIGCONT to let a stopped process receive it.
+    pub fn hangup(self) -> anyhow::Result<()> {
+        kill(self.into(), Signal::SIGHUP).context("Failed to hang up the process.")?;
+        if self.suspended {
+            kill(self.into(), Signal::SIGCONT).context("Failed to hang up the process.")?;
+        }
+        Ok(())
+    }
+
     pub fn suspend(&mut self) -> anyhow::Result<Status> {
         if self.suspended {
             anyhow::bail!("The process is already suspended.");
@@ -88,6 +100,24 @@ impl Process {
         Ok(Status::Signaled(Signal::SIGCONT))
     }
 
+    // Checks the state without blocking, and the process is reaped if it has
+    // finished.
+    pub fn try_wait(&self) -> anyhow::Result<Option<Status>> {
+        use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+
+        let flags = WaitPidFlag::WNOHANG | WaitPidFlag::WUNTRACED | WaitPidFlag::WCONTINUED;
+        Ok(
+            match waitpid(self.pid, Some(flags)).context("Failed to wait the process.")? {
+                WaitStatus::Exited(_, code) => Some(Status::Exited(code)),
+                WaitStatus::Signaled(_, sig, _) | WaitStatus::Stopped(_, sig) => {
+                    Some(Status::Signaled(sig))
+                }
+                WaitStatus::Continued(_) => Some(Status::Signaled(Signal::SIGCONT)),
+                _ => None,
+            },
+        )
+    }
+
     pub fn wait(&self) -> anyhow::Result<Status> {
         let (code, status, is_error) = unsafe {
             let mut siginfo = std::mem::zeroed();
@@ -116,33 +146,3 @@ impl Process {
         })
     }
 }
-
-pub fn sigchld() -> anyhow::Result<Option<(i32, Status)>> {
-    let (pid, code, status, is_error) = unsafe {
-        let mut siginfo = std::mem::zeroed();
-        let error = libc::waitid(
-            libc::P_ALL,
-            0,
-            &mut siginfo,
-            libc::WEXITED | libc::WSTOPPED | libc::WCONTINUED | libc::WNOWAIT,
-        );
-        let siginfo = siginfo as libc::siginfo_t;
-        (
-            siginfo.si_pid() as i32,
-            siginfo.si_code as i32,
-            siginfo.si_status() as i32,
-            error == -1,
-        )
-    };
-
-    if is_error {
-        return Ok(None);
-    }
-
-    let status = match code {
-        libc::CLD_EXITED => Status::Exited(status),
-        _ => Status::Signaled(Signal::try_from(status).context("Unnexpected signal.")?),
-    };
-
-    Ok(Some((pid, status)))
-}
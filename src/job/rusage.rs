@@ -0,0 +1,100 @@
+use nix::libc;
+use std::ops::{Add, Sub};
+use std::time::Duration;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CpuTimes {
+    pub user: Duration,
+    pub sys: Duration,
+}
+
+impl CpuTimes {
+    pub fn shell() -> Self {
+        Self::get(libc::RUSAGE_SELF)
+    }
+
+    pub fn children() -> Self {
+        Self::get(libc::RUSAGE_CHILDREN)
+    }
+
+    fn get(who: libc::c_int) -> Self {
+        let usage = unsafe {
+            let mut usage = std::mem::zeroed();
+            libc::getrusage(who, &mut usage);
+            usage
+        };
+
+        let duration =
+            |tv: libc::timeval| Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000);
+
+        Self {
+            user: duration(usage.ru_utime),
+            sys: duration(usage.ru_stime),
+        }
+    }
+}
+
+impl Add for CpuTimes {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        Self {
+            user: self.user + other.user,
+            sys: self.sys + other.sys,
+        }
+    }
+}
+
+impl Sub for CpuTimes {
+    type Output = Self;
+    fn sub(self, other: Self) -> Self {
+        Self {
+            user: self.user.saturating_sub(other.user),
+            sys: self.sys.saturating_sub(other.sys),
+        }
+    }
+}
+
+// Minutes and seconds, like `1m2.345s`.
+pub fn format_time(time: Duration) -> String {
+    // Rounded first, so that 59.9996s is `1m0.000s` and not `0m60.000s`.
+    let millis = (time.as_secs_f64() * 1000.0).round() as u64;
+    format!(
+        "{}m{}.{:03}s",
+        millis / 60_000,
+        millis % 60_000 / 1000,
+        millis % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn times() {
+        assert_eq!(format_time(Duration::from_millis(0)), "0m0.000s");
+        assert_eq!(format_time(Duration::from_millis(102)), "0m0.102s");
+        assert_eq!(format_time(Duration::from_millis(62_005)), "1m2.005s");
+        assert_eq!(format_time(Duration::from_micros(59_999_600)), "1m0.000s");
+    }
+
+    #[test]
+    fn sub_saturates() {
+        let more = CpuTimes {
+            user: Duration::from_secs(2),
+            sys: Duration::from_secs(1),
+        };
+        let less = CpuTimes {
+            user: Duration::from_secs(1),
+            sys: Duration::from_secs(3),
+        };
+        assert_eq!(
+            more - less,
+            CpuTimes {
+                user: Duration::from_secs(1),
+                sys: Duration::from_secs(0),
+            }
+        );
+        assert_eq!(less + more - more, less);
+    }
+}
@@ -0,0 +1,45 @@
+use super::Signal;
+use std::convert::TryFrom;
+
+// Takes a signal by its number or its name, with or without `SIG` in any
+// case, like `15`, `TERM`, `sigterm` or `SIGTERM`.
+pub fn parse_signal(s: &str) -> Option<i32> {
+    let s = s.trim();
+    if let Ok(n) = s.parse::<i32>() {
+        return Signal::try_from(n).ok().map(|sig| sig as i32);
+    }
+
+    let upper = s.to_ascii_uppercase();
+    let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+    Signal::iterator()
+        .find(|sig| &sig.as_str()[3..] == name)
+        .map(|sig| sig as i32)
+}
+
+// The name of a signal without `SIG`, as `kill -l` shows it.
+pub fn signal_name(n: i32) -> Option<&'static str> {
+    Signal::try_from(n).ok().map(|sig| &sig.as_str()[3..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_and_numbers() {
+        for s in ["15", "TERM", "SIGTERM", "sigterm", "Term", " term "].iter() {
+            assert_eq!(parse_signal(s), Some(15), "{}", s);
+        }
+        assert_eq!(parse_signal("INT"), Some(2));
+        assert_eq!(parse_signal("sigkill"), Some(9));
+        assert_eq!(parse_signal("HUP"), Some(1));
+        assert_eq!(parse_signal("0"), None);
+        assert_eq!(parse_signal("NOSUCH"), None);
+        assert_eq!(parse_signal("SIG"), None);
+
+        assert_eq!(signal_name(15), Some("TERM"));
+        assert_eq!(signal_name(9), Some("KILL"));
+        assert_eq!(signal_name(0), None);
+        assert_eq!(signal_name(1000), None);
+    }
+}
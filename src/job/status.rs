@@ -17,6 +17,13 @@ impl fmt::Display for Status {
 }
 
 impl Status {
+    pub fn code(&self) -> i32 {
+        match self {
+            Self::Exited(c) => *c,
+            Self::Signaled(s) => 128 + *s as i32,
+        }
+    }
+
     pub fn stopped(&self) -> bool {
         matches!(
             self,
@@ -0,0 +1,9 @@
+// The parsers nest deep enough to exceed the default limit.
+#![recursion_limit = "256"]
+
+extern crate anyhow;
+
+pub mod eval;
+pub mod job;
+pub mod parse;
+pub mod session;
@@ -1,18 +1,44 @@
-extern crate anyhow;
-
-mod eval;
-mod job;
-mod parse;
-mod session;
+use tish::{eval, session};
 
 fn inner_main() -> anyhow::Result<()> {
-    let mut namespace = eval::NameSpace::default();
-    let mut session = session::Session::new(session::PromptReader::new())?;
-    session.all(&mut namespace)
+    let mut namespace = eval::NameSpace::new();
+    let mut args = std::env::args().skip(1).peekable();
+    let mut dump_ast = false;
+    while let Some(flag) = args.next_if(|arg| arg.starts_with("--")) {
+        match flag.as_str() {
+            "--parse-skip" => namespace.opts_mut().parse_skip = true,
+            "--dump-ast" => dump_ast = true,
+            // A subshell started by the shell itself.
+            "--child" => {
+                let fd = args.next().and_then(|fd| fd.parse().ok());
+                let fd = fd.ok_or_else(|| anyhow::anyhow!("No descriptor for --child."))?;
+                std::process::exit(eval::run_child(fd)?);
+            }
+            _ => anyhow::bail!("Unknown option: {}", flag),
+        }
+    }
+
+    match args.next() {
+        Some(file) => {
+            let reader = session::IOReader::new_file(&file)?;
+            let mut session = session::Session::with_namespace(reader, namespace)?;
+            session.dump_ast(dump_ast);
+            session.all_with_args(file, args).map(|_| ())
+        }
+        None => {
+            namespace.opts_mut().checkjobs = true;
+            let reader = session::PromptReader::new();
+            let mut session = session::Session::with_namespace(reader, namespace)?;
+            session.dump_ast(dump_ast);
+            session.load_history()?;
+            session.all()
+        }
+    }
 }
 
 fn main() {
     inner_main().unwrap_or_else(|e| {
-        eprintln!("{}", e);
+        eprintln!("{}", session::color::error(e.to_string()));
+        std::process::exit(1);
     })
 }
@@ -1,34 +1,109 @@
-use super::{spaces_line, Command, SpecialStr};
+use super::{is_word_char, spaces, spaces_line, Arg, Command, SpecialStr};
 
 use combine::parser::char;
-use combine::{attempt, choice, many, many1, optional, satisfy, sep_by, Parser, Stream};
-use combine::{sep_end_by, token};
+use combine::stream::position::SourcePosition;
+use combine::{attempt, choice, many, many1, one_of, optional, satisfy, sep_by, Parser, Stream};
+use combine::{look_ahead, not_followed_by, position, sep_end_by, token};
+use std::fmt;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Block {
     Single(Command),
-    Multi(Vec<Self>),
-    If(SpecialStr, Box<Self>, Option<Box<Self>>),
-    Case(SpecialStr, Vec<(Vec<SpecialStr>, Self)>),
-    For(String, SpecialStr, Box<Self>),
-    While(SpecialStr, Box<Self>),
+    Multi(Vec<(usize, Self)>),
+    // The condition of `if` and `while` is a command, or a block judged by
+    // its status.
+    If(Box<Self>, Box<Self>, Option<Box<Self>>),
+    Case(SpecialStr, Vec<(Vec<SpecialStr>, Self, CaseEnd)>),
+    For(String, Vec<Arg>, Box<Self>),
+    Select(String, Vec<Arg>, Box<Self>),
+    While(Box<Self>, Box<Self>),
     Proc(String, Box<Self>),
+    Time(bool, Box<Self>),
+    Coproc(String, Box<Self>),
+    // A compound command followed by `&`.
+    Background(Box<Self>),
+    And(Box<Self>, Box<Self>),
+    Or(Box<Self>, Box<Self>),
+    Pipe(Box<Self>, Box<Self>),
     Break,
     Continue,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaseEnd {
+    Break,
+    FallThrough,
+    Continue,
+}
+
 impl Block {
-    pub fn parse<I: Stream<Token = char>>() -> impl Parser<I, Output = Self> {
+    pub fn parse<I: Stream<Token = char, Position = SourcePosition>>(
+    ) -> impl Parser<I, Output = Self> {
         block()
     }
 
-    fn parse_<I: Stream<Token = char>>() -> impl Parser<I, Output = Self> {
+    // Statements chained with `&&` and `||`, which are left associative with
+    // the same precedence.
+    fn parse_<I: Stream<Token = char, Position = SourcePosition>>() -> impl Parser<I, Output = Self>
+    {
+        (
+            Self::parse_pipeline(),
+            many(
+                (
+                    attempt(spaces().with(char::string("&&").or(char::string("||")))),
+                    Self::parse_pipeline(),
+                )
+                    .map(|(op, block)| (op == "&&", block)),
+            ),
+        )
+            .map(|(first, rest): (_, Vec<_>)| {
+                rest.into_iter().fold(first, |lhs, (and, rhs)| {
+                    if and {
+                        Self::And(Box::new(lhs), Box::new(rhs))
+                    } else {
+                        Self::Or(Box::new(lhs), Box::new(rhs))
+                    }
+                })
+            })
+    }
+
+    // A pipeline with compound commands in it, as `seq 3 | while read n {}`.
+    // Commands alone are piped by `Command` itself.
+    fn parse_pipeline<I: Stream<Token = char, Position = SourcePosition>>(
+    ) -> impl Parser<I, Output = Self> {
+        (
+            Self::parse_item(),
+            many(
+                attempt(spaces().with(token('|')).skip(not_followed_by(token('|'))))
+                    .with(Self::parse_item()),
+            ),
+        )
+            .map(|(first, rest): (_, Vec<_>)| {
+                rest.into_iter()
+                    .fold(first, |lhs, rhs| Self::Pipe(Box::new(lhs), Box::new(rhs)))
+            })
+    }
+
+    // A single command becomes a background job by itself, and the others
+    // run in a child process of the shell.
+    fn background(self) -> Self {
+        match self {
+            Self::Single(cmd) => Self::Single(Command { bg: true, ..cmd }),
+            block => Self::Background(Box::new(block)),
+        }
+    }
+
+    fn parse_item<I: Stream<Token = char, Position = SourcePosition>>(
+    ) -> impl Parser<I, Output = Self> {
         spaces_line().with(choice((
             attempt(char::string("break")).map(|_| Self::Break),
             attempt(char::string("continue")).map(|_| Self::Continue),
+            time().map(|(posix, block)| Self::Time(posix, block)),
+            coproc().map(|(name, block)| Self::Coproc(name, block)),
             proc().map(|(name, block)| Self::Proc(name, block)),
             while_().map(|(cond, block)| Self::While(cond, block)),
             for_().map(|(c, iter, block)| Self::For(c, iter, block)),
+            select().map(|(c, items, block)| Self::Select(c, items, block)),
             case().map(|(cond, blocks)| Self::Case(cond, blocks)),
             if_().map(|(cond, first, second)| Self::If(cond, first, second)),
             multi().map(Self::Multi),
@@ -37,47 +112,286 @@ impl Block {
     }
 }
 
+// An indented tree for `--dump-ast`.
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.dump(f, 0, "")
+    }
+}
+
+impl Block {
+    fn dump(&self, f: &mut fmt::Formatter, depth: usize, label: &str) -> fmt::Result {
+        write!(f, "{:indent$}{}", "", label, indent = depth * 2)?;
+        match self {
+            Self::Single(cmd) => writeln!(f, "Single: {}", cmd),
+            Self::Multi(blocks) => {
+                writeln!(f, "Multi")?;
+                for (line, block) in blocks.iter() {
+                    block.dump(f, depth + 1, &format!("line {}: ", line))?;
+                }
+                Ok(())
+            }
+            Self::If(cond, first, second) => {
+                cond.dump_cond(f, depth, "If")?;
+                first.dump(f, depth + 1, "then: ")?;
+                if let Some(second) = second {
+                    second.dump(f, depth + 1, "else: ")?;
+                }
+                Ok(())
+            }
+            Self::Case(cond, arms) => {
+                writeln!(f, "Case: {}", cond)?;
+                for (pats, block, end) in arms.iter() {
+                    let pats = pats.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+                    let label = format!("{} ({:?}): ", pats.join(" | "), end);
+                    block.dump(f, depth + 1, &label)?;
+                }
+                Ok(())
+            }
+            Self::For(c, iter, block) => {
+                let iter = iter.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+                writeln!(f, "For: {} in {}", c, iter.join(" "))?;
+                block.dump(f, depth + 1, "")
+            }
+            Self::Select(c, items, block) => {
+                let items = items.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+                writeln!(f, "Select: {} in {}", c, items.join(" "))?;
+                block.dump(f, depth + 1, "")
+            }
+            Self::While(cond, block) => {
+                cond.dump_cond(f, depth, "While")?;
+                block.dump(f, depth + 1, "")
+            }
+            Self::Proc(name, block) => {
+                writeln!(f, "Proc: {}", name)?;
+                block.dump(f, depth + 1, "")
+            }
+            Self::Time(posix, block) => {
+                writeln!(f, "Time{}", if *posix { " -p" } else { "" })?;
+                block.dump(f, depth + 1, "")
+            }
+            Self::Coproc(name, block) => {
+                writeln!(f, "Coproc: {}", name)?;
+                block.dump(f, depth + 1, "")
+            }
+            Self::Background(block) => {
+                writeln!(f, "Background")?;
+                block.dump(f, depth + 1, "")
+            }
+            Self::And(lhs, rhs) => {
+                writeln!(f, "And")?;
+                lhs.dump(f, depth + 1, "")?;
+                rhs.dump(f, depth + 1, "")
+            }
+            Self::Or(lhs, rhs) => {
+                writeln!(f, "Or")?;
+                lhs.dump(f, depth + 1, "")?;
+                rhs.dump(f, depth + 1, "")
+            }
+            Self::Pipe(lhs, rhs) => {
+                writeln!(f, "Pipe")?;
+                lhs.dump(f, depth + 1, "")?;
+                rhs.dump(f, depth + 1, "")
+            }
+            Self::Break => writeln!(f, "Break"),
+            Self::Continue => writeln!(f, "Continue"),
+        }
+    }
+}
+
+impl Block {
+    fn dump_cond(&self, f: &mut fmt::Formatter, depth: usize, name: &str) -> fmt::Result {
+        match self {
+            Self::Single(cmd) => writeln!(f, "{}: {}", name, cmd),
+            cond => {
+                writeln!(f, "{}", name)?;
+                cond.dump(f, depth + 1, "cond: ")
+            }
+        }
+    }
+}
+
+impl Block {
+    // The source text, which parses back into the same block.
+    pub fn source(&self) -> String {
+        let mut out = String::new();
+        self.write_source(&mut out, 0);
+        out
+    }
+
+    fn write_source(&self, out: &mut String, depth: usize) {
+        let indent = |depth: usize| "    ".repeat(depth);
+        match self {
+            Self::Single(cmd) => out.push_str(&cmd.to_string()),
+            Self::Multi(blocks) => {
+                out.push_str("{\n");
+                for (_, block) in blocks.iter() {
+                    out.push_str(&indent(depth + 1));
+                    block.write_source(out, depth + 1);
+                    out.push('\n');
+                }
+                out.push_str(&indent(depth));
+                out.push('}');
+            }
+            Self::If(cond, first, second) => {
+                out.push_str("if ");
+                cond.write_source(out, depth);
+                out.push(' ');
+                first.write_source(out, depth);
+                if let Some(second) = second {
+                    out.push_str(" else ");
+                    second.write_source(out, depth);
+                }
+            }
+            Self::Case(cond, arms) => {
+                out.push_str(&format!("case {} {{\n", cond));
+                for (pats, block, end) in arms.iter() {
+                    let pats = pats.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+                    out.push_str(&format!("{}{} => ", indent(depth + 1), pats.join(" | ")));
+                    block.write_source(out, depth + 1);
+                    out.push_str(match end {
+                        CaseEnd::Break => ";;\n",
+                        CaseEnd::FallThrough => ";&\n",
+                        CaseEnd::Continue => ";;&\n",
+                    });
+                }
+                out.push_str(&indent(depth));
+                out.push('}');
+            }
+            Self::For(c, iter, block) => {
+                let iter = iter.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+                out.push_str(&format!("for {} in {} ", c, iter.join(" ")));
+                block.write_source(out, depth);
+            }
+            Self::Select(c, items, block) => {
+                let items = items.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+                out.push_str(&format!("select {} in {} ", c, items.join(" ")));
+                block.write_source(out, depth);
+            }
+            Self::While(cond, block) => {
+                out.push_str("while ");
+                cond.write_source(out, depth);
+                out.push(' ');
+                block.write_source(out, depth);
+            }
+            Self::Proc(name, block) => {
+                out.push_str(&format!("{} ", name));
+                block.write_source(out, depth);
+            }
+            Self::Coproc(name, block) => {
+                out.push_str(&format!("coproc {} ", name));
+                block.write_source(out, depth);
+            }
+            Self::Background(block) => {
+                block.write_source(out, depth);
+                out.push_str(" &");
+            }
+            Self::Time(posix, block) => {
+                out.push_str(if *posix { "time -p " } else { "time " });
+                block.write_source(out, depth);
+            }
+            Self::And(lhs, rhs) | Self::Or(lhs, rhs) => {
+                lhs.write_source(out, depth);
+                out.push_str(if matches!(self, Self::And(_, _)) {
+                    " && "
+                } else {
+                    " || "
+                });
+                rhs.write_source(out, depth);
+            }
+            Self::Pipe(lhs, rhs) => {
+                lhs.write_source(out, depth);
+                out.push_str(" | ");
+                rhs.write_source(out, depth);
+            }
+            Self::Break => out.push_str("break"),
+            Self::Continue => out.push_str("continue"),
+        }
+    }
+}
+
 combine::parser! {
     fn block[I]()(I) -> Block
-    where [I: Stream<Token = char>]
+    where [I: Stream<Token = char, Position = SourcePosition>]
     {
         Block::parse_()
     }
 }
 
-fn multi<I: Stream<Token = char>>() -> impl Parser<I, Output = Vec<Block>> {
+// The body of `if`, `for` and `while`, which doesn't take `|`, `&&` or `||`
+// after it, so they apply to the whole statement.
+combine::parser! {
+    fn body[I]()(I) -> Block
+    where [I: Stream<Token = char, Position = SourcePosition>]
+    {
+        Block::parse_item()
+    }
+}
+
+// Statements separated by newlines, `;` or `&`, each with the line it starts
+// on. A statement followed by `&` runs in the background, and a separator
+// after the last statement is allowed.
+pub fn statements<I: Stream<Token = char, Position = SourcePosition>>(
+) -> impl Parser<I, Output = Vec<(usize, Block)>> {
+    sep_end_by(
+        (
+            position(),
+            Block::parse(),
+            optional(look_ahead(attempt(
+                spaces().with(token('&')).skip(not_followed_by(token('&'))),
+            ))),
+        )
+            .map(|(pos, block, bg): (SourcePosition, Block, _)| {
+                let block = if bg.is_some() {
+                    block.background()
+                } else {
+                    block
+                };
+                (pos.line as usize, block)
+            }),
+        attempt(spaces().with(one_of("\n;&".chars()))).with(spaces_line()),
+    )
+    .skip(spaces_line())
+}
+
+fn multi<I: Stream<Token = char, Position = SourcePosition>>(
+) -> impl Parser<I, Output = Vec<(usize, Block)>> {
     token('{')
         .skip(spaces_line())
-        .with(sep_end_by(
-            Block::parse(),
-            token('\n').or(token(';')).with(spaces_line()),
-        ))
+        .with(statements())
         .skip(token('}'))
 }
 
-fn if_<I: Stream<Token = char>>(
-) -> impl Parser<I, Output = (SpecialStr, Box<Block>, Option<Box<Block>>)> {
+// A braced condition is tried before a command.
+fn condition<I: Stream<Token = char, Position = SourcePosition>>(
+) -> impl Parser<I, Output = Box<Block>> {
+    multi()
+        .map(Block::Multi)
+        .or(Command::parse().map(Block::Single))
+        .map(Box::new)
+}
+
+fn if_<I: Stream<Token = char, Position = SourcePosition>>(
+) -> impl Parser<I, Output = (Box<Block>, Box<Block>, Option<Box<Block>>)> {
     (
         attempt(char::string("if")),
         spaces_line(),
-        SpecialStr::parse(),
-        spaces_line(),
-        Block::parse().map(Box::new),
+        condition(),
         spaces_line(),
+        body().map(Box::new),
         optional(
-            (
-                char::string("else"),
-                spaces_line(),
-                Block::parse().map(Box::new),
-            )
-                .map(|(_, _, line)| line),
+            attempt(spaces_line().with(char::string("else")))
+                .with(spaces_line())
+                .with(body().map(Box::new)),
         ),
     )
-        .map(|(_, _, cond, _, first, _, second)| (cond, first, second))
+        .map(|(_, _, cond, _, first, second)| (cond, first, second))
 }
 
-fn case<I: Stream<Token = char>>(
-) -> impl Parser<I, Output = (SpecialStr, Vec<(Vec<SpecialStr>, Block)>)> {
+type CaseArm = (Vec<SpecialStr>, Block, CaseEnd);
+
+fn case<I: Stream<Token = char, Position = SourcePosition>>(
+) -> impl Parser<I, Output = (SpecialStr, Vec<CaseArm>)> {
     (
         attempt(char::string("case")),
         spaces_line(),
@@ -93,48 +407,117 @@ fn case<I: Stream<Token = char>>(
                 ),
                 char::string("=>"),
                 spaces_line(),
-                Block::parse(),
+                optional(Block::parse()).map(|block| block.unwrap_or(Block::Multi(Vec::new()))),
+                spaces_line(),
+                case_end(),
                 spaces_line(),
             )
-                .map(|(pats, _, _, block, _)| (pats, block)),
+                .map(|(pats, _, _, block, _, end, _)| (pats, block, end)),
         ),
         token('}'),
     )
         .map(|(_, _, cond, _, _, _, blocks, _)| (cond, blocks))
 }
 
-fn for_<I: Stream<Token = char>>() -> impl Parser<I, Output = (String, SpecialStr, Box<Block>)> {
+fn case_end<I: Stream<Token = char, Position = SourcePosition>>() -> impl Parser<I, Output = CaseEnd>
+{
+    optional(token(';').with(choice((
+        token('&').map(|_| CaseEnd::FallThrough),
+        token(';').with(optional(token('&'))).map(|amp| match amp {
+            Some(_) => CaseEnd::Continue,
+            None => CaseEnd::Break,
+        }),
+    ))))
+    .map(|end| end.unwrap_or(CaseEnd::Break))
+}
+
+fn for_<I: Stream<Token = char, Position = SourcePosition>>(
+) -> impl Parser<I, Output = (String, Vec<Arg>, Box<Block>)> {
     (
         attempt(char::string("for")),
         spaces_line(),
         many1(satisfy(|c: char| !c.is_whitespace())),
         spaces_line(),
         char::string("in"),
+        spaces(),
+        sep_end_by(Arg::parse_word(), spaces()),
         spaces_line(),
-        SpecialStr::parse(),
-        spaces_line(),
-        Block::parse().map(Box::new),
+        body().map(Box::new),
     )
         .map(|(_, _, c, _, _, _, iter, _, block)| (c, iter, block))
 }
 
-fn while_<I: Stream<Token = char>>() -> impl Parser<I, Output = (SpecialStr, Box<Block>)> {
+fn select<I: Stream<Token = char, Position = SourcePosition>>(
+) -> impl Parser<I, Output = (String, Vec<Arg>, Box<Block>)> {
+    (
+        attempt(char::string("select").skip(char::space())),
+        spaces_line(),
+        many1(satisfy(|c: char| !c.is_whitespace())),
+        spaces_line(),
+        char::string("in"),
+        spaces(),
+        sep_end_by(Arg::parse_word(), spaces()),
+        spaces_line(),
+        body().map(Box::new),
+    )
+        .map(|(_, _, c, _, _, _, items, _, block)| (c, items, block))
+}
+
+fn while_<I: Stream<Token = char, Position = SourcePosition>>(
+) -> impl Parser<I, Output = (Box<Block>, Box<Block>)> {
     (
         attempt(char::string("while")),
         spaces_line(),
-        SpecialStr::parse(),
+        condition(),
         spaces_line(),
-        Block::parse().map(Box::new),
+        body().map(Box::new),
     )
         .map(|(_, _, cond, _, block)| (cond, block))
 }
 
-fn proc<I: Stream<Token = char>>() -> impl Parser<I, Output = (String, Box<Block>)> {
+fn time<I: Stream<Token = char, Position = SourcePosition>>(
+) -> impl Parser<I, Output = (bool, Box<Block>)> {
+    (
+        attempt(char::string("time").skip(char::space())),
+        spaces(),
+        optional(attempt(char::string("-p").skip(char::space()))),
+        Block::parse().map(Box::new),
+    )
+        .map(|(_, _, posix, block)| (posix.is_some(), block))
+}
+
+// `coproc NAME { ... }`, or `coproc cmd` named `COPROC`.
+fn coproc<I: Stream<Token = char, Position = SourcePosition>>(
+) -> impl Parser<I, Output = (String, Box<Block>)> {
+    (
+        attempt(char::string("coproc").skip(char::space())),
+        spaces(),
+        optional(attempt(
+            many1(satisfy(|c: char| c.is_ascii_alphanumeric() || c == '_'))
+                .skip(spaces_line())
+                .skip(look_ahead(token('{'))),
+        )),
+        body().map(Box::new),
+    )
+        .map(|(_, _, name, block)| (name.unwrap_or_else(|| String::from("COPROC")), block))
+}
+
+// `if {` and `while {` start braced conditions instead of functions.
+fn proc<I: Stream<Token = char, Position = SourcePosition>>(
+) -> impl Parser<I, Output = (String, Box<Block>)> {
     attempt((
-        many1(satisfy(|c: char| !c.is_whitespace() && c != '{')),
+        not_followed_by(
+            attempt(
+                choice((attempt(char::string("if")), char::string("while")))
+                    .skip(spaces_line())
+                    .skip(token('{')),
+            )
+            .map(|_| "a keyword"),
+        ),
+        many1(satisfy(is_word_char)),
         spaces_line(),
         combine::look_ahead(token('{')),
     ))
-    .map(|(name, _, _)| name)
+    .map(|(_, name, _, _)| name)
     .and(multi().map(|blocks| Box::new(Block::Multi(blocks))))
 }
@@ -1,18 +1,24 @@
-use combine::parser::char;
 use combine::parser::repeat::skip_until;
-use combine::{satisfy, skip_many, token};
+use combine::{optional, satisfy, skip_many, token};
 use combine::{Parser, Stream};
 
+// Characters which end an unquoted word, as they start an operator.
+pub const OPERATORS: &str = "#|&;${}()";
+
+pub fn is_word_char(c: char) -> bool {
+    !c.is_whitespace() && !OPERATORS.contains(c)
+}
+
+fn comment<I: Stream<Token = char>>() -> impl Parser<I, Output = ()> {
+    token('#').and(skip_until(token('\n'))).map(|_| ())
+}
+
+// Blanks within a line, with a comment up to the end of the line.
 pub fn spaces<I: Stream<Token = char>>() -> impl Parser<I, Output = ()> {
-    token('#')
-        .and(skip_until(token('\n')))
-        .map(|_| ())
-        .or(skip_many(satisfy(|c: char| c.is_whitespace() && c != '\n')))
+    skip_many(satisfy(|c: char| c.is_whitespace() && c != '\n')).skip(optional(comment()))
 }
 
+// Blanks, newlines and comments, as many as they are.
 pub fn spaces_line<I: Stream<Token = char>>() -> impl Parser<I, Output = ()> {
-    token('#')
-        .and(skip_until(token('\n')))
-        .map(|_| ())
-        .or(char::spaces())
+    skip_many(comment().or(satisfy(char::is_whitespace).map(|_| ())))
 }
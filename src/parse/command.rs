@@ -1,22 +1,28 @@
-use super::{spaces, spaces_line, Redirect, SpecialStr};
-use combine::{attempt, eof, optional, sep_end_by, token};
-use combine::{Parser, Stream};
+use super::{spaces, spaces_line, Cond, Redirect, SpecialStr};
+use combine::{attempt, eof, look_ahead, many, not_followed_by, one_of, optional, satisfy, token};
+use combine::{sep_end_by, sep_end_by1, Parser, Stream};
+use std::fmt;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Command {
+    pub assigns: Vec<(String, SpecialStr)>,
     pub name: SpecialStr,
     pub args: Vec<Arg>,
     pub pipe: Option<Box<Command>>,
     pub bg: bool,
+    // `[[ ... ]]` in place of a command.
+    pub cond: Option<Cond>,
 }
 
 impl Command {
     fn empty() -> Self {
         Self {
+            assigns: Vec::new(),
             name: SpecialStr::new(),
             args: Vec::new(),
             pipe: None,
             bg: false,
+            cond: None,
         }
     }
 
@@ -26,22 +32,71 @@ impl Command {
 
     fn parse_<I: Stream<Token = char>>() -> impl Parser<I, Output = Self> {
         spaces_line().with(
-            eof().map(|_| Self::empty()).or((
-                SpecialStr::parse().skip(spaces()),
-                sep_end_by(Arg::parse(), spaces()),
-                optional(token('|').with(Self::parse())),
-                optional(spaces().with(token('&')).skip(spaces())),
-            )
-                .map(|(name, args, pipe, bg)| Self {
-                    name,
-                    args,
-                    pipe: pipe.map(Box::new),
-                    bg: bg.is_some(),
-                })),
+            eof()
+                .map(|_| Self::empty())
+                .or(Cond::parse().skip(spaces()).map(|cond| Self {
+                    cond: Some(cond),
+                    ..Self::empty()
+                }))
+                .or(attempt(
+                    sep_end_by1(assign(), spaces())
+                        .skip(eof().or(look_ahead(one_of("\n;&}".chars())).map(|_| ()))),
+                )
+                .map(|assigns| Self {
+                    assigns,
+                    ..Self::empty()
+                }))
+                // Only the words before the name can be assignments, and the
+                // arguments like `--color=auto` are taken as they are.
+                .or((
+                    many(assign().skip(spaces())),
+                    SpecialStr::parse().skip(spaces()),
+                    sep_end_by(Arg::parse(), spaces()),
+                    // A compound command after `|` is left to `Block`.
+                    optional(attempt(
+                        token('|')
+                            .skip(not_followed_by(token('|')))
+                            .with(Self::parse())
+                            .skip(not_followed_by(token('{'))),
+                    )),
+                )
+                    .map(|(assigns, name, args, pipe)| Self {
+                        assigns,
+                        name,
+                        args,
+                        pipe: pipe.map(Box::new),
+                        bg: false,
+                        cond: None,
+                    })),
         )
     }
 }
 
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(cond) = &self.cond {
+            return write!(f, "{}", cond);
+        }
+        let mut words = self
+            .assigns
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>();
+        if self.name != SpecialStr::new() {
+            words.push(self.name.to_string());
+        }
+        words.extend(self.args.iter().map(|arg| arg.to_string()));
+        write!(f, "{}", words.join(" "))?;
+        if let Some(pipe) = &self.pipe {
+            write!(f, " | {}", pipe)?;
+        }
+        if self.bg {
+            write!(f, " &")?;
+        }
+        Ok(())
+    }
+}
+
 combine::parser! {
     fn command[I]()(I) -> Command
     where [I: Stream<Token = char>]
@@ -50,6 +105,29 @@ combine::parser! {
     }
 }
 
+fn assign<I: Stream<Token = char>>() -> impl Parser<I, Output = (String, SpecialStr)> {
+    attempt(
+        (
+            satisfy(|c: char| c.is_ascii_alphabetic() || c == '_'),
+            many(satisfy(|c: char| c.is_ascii_alphanumeric() || c == '_')),
+            token('='),
+        )
+            .map(|(head, tail, _): (char, String, _)| format!("{}{}", head, tail)),
+    )
+    .and(SpecialStr::parse_value())
+}
+
+impl fmt::Display for Arg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::ExpandArg(s) => write!(f, "!{}", s),
+            Self::Arg(s) => write!(f, "{}", s),
+            Self::Redirect(r) => write!(f, "{}", r),
+        }
+    }
+}
+
+#[allow(clippy::enum_variant_names)]
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Arg {
     ExpandArg(SpecialStr),
@@ -59,8 +137,13 @@ pub enum Arg {
 
 impl Arg {
     pub fn parse<I: Stream<Token = char>>() -> impl Parser<I, Output = Self> {
-        attempt(Redirect::parse().map(Self::Redirect))
-            .or(token('!').with(SpecialStr::parse().map(Self::ExpandArg)))
+        attempt(Redirect::parse().map(Self::Redirect)).or(Self::parse_word())
+    }
+
+    // An argument which can't be a redirect, as in the list of `for`.
+    pub fn parse_word<I: Stream<Token = char>>() -> impl Parser<I, Output = Self> {
+        token('!')
+            .with(SpecialStr::parse().map(Self::ExpandArg))
             .or(SpecialStr::parse().map(Self::Arg))
     }
 }
@@ -0,0 +1,172 @@
+use super::{spaces, spaces_line, SpecialStr};
+use combine::parser::char;
+use combine::{attempt, choice, look_ahead, many, many1, one_of, optional, satisfy, token};
+use combine::{Parser, Stream};
+use std::fmt;
+
+// The expression of `[[ ... ]]`, where `&&` binds tighter than `||`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Cond {
+    Word(SpecialStr),
+    Unary(String, SpecialStr),
+    Binary(SpecialStr, String, SpecialStr),
+    Not(Box<Self>),
+    And(Box<Self>, Box<Self>),
+    Or(Box<Self>, Box<Self>),
+}
+
+impl Cond {
+    pub fn parse<I: Stream<Token = char>>() -> impl Parser<I, Output = Self> {
+        cond()
+    }
+
+    fn parse_<I: Stream<Token = char>>() -> impl Parser<I, Output = Self> {
+        (
+            attempt(char::string("[[").skip(look_ahead(char::space()))),
+            spaces_line(),
+            or_expr(),
+            spaces_line(),
+            char::string("]]"),
+        )
+            .map(|(_, _, cond, _, _)| cond)
+    }
+}
+
+combine::parser! {
+    fn cond[I]()(I) -> Cond
+    where [I: Stream<Token = char>]
+    {
+        Cond::parse_()
+    }
+}
+
+impl fmt::Display for Cond {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[[ ")?;
+        self.write_expr(f)?;
+        write!(f, " ]]")
+    }
+}
+
+impl Cond {
+    fn write_expr(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Word(word) => write!(f, "{}", word),
+            Self::Unary(op, word) => write!(f, "{} {}", op, word),
+            Self::Binary(lhs, op, rhs) => write!(f, "{} {} {}", lhs, op, rhs),
+            Self::Not(cond) => {
+                write!(f, "! ")?;
+                cond.write_expr(f)
+            }
+            Self::And(lhs, rhs) | Self::Or(lhs, rhs) => {
+                lhs.write_expr(f)?;
+                write!(
+                    f,
+                    "{}",
+                    if matches!(self, Self::And(_, _)) {
+                        " && "
+                    } else {
+                        " || "
+                    }
+                )?;
+                rhs.write_expr(f)
+            }
+        }
+    }
+}
+
+fn or_expr<I: Stream<Token = char>>() -> impl Parser<I, Output = Cond> {
+    (
+        and_expr(),
+        many(
+            attempt(spaces().with(char::string("||")))
+                .with(spaces_line())
+                .with(and_expr()),
+        ),
+    )
+        .map(|(first, rest): (_, Vec<_>)| {
+            rest.into_iter()
+                .fold(first, |lhs, rhs| Cond::Or(Box::new(lhs), Box::new(rhs)))
+        })
+}
+
+fn and_expr<I: Stream<Token = char>>() -> impl Parser<I, Output = Cond> {
+    (
+        not_expr(),
+        many(
+            attempt(spaces().with(char::string("&&")))
+                .with(spaces_line())
+                .with(not_expr()),
+        ),
+    )
+        .map(|(first, rest): (_, Vec<_>)| {
+            rest.into_iter()
+                .fold(first, |lhs, rhs| Cond::And(Box::new(lhs), Box::new(rhs)))
+        })
+}
+
+combine::parser! {
+    fn not_expr[I]()(I) -> Cond
+    where [I: Stream<Token = char>]
+    {
+        attempt(token('!').skip(look_ahead(char::space())))
+            .with(spaces())
+            .with(not_expr())
+            .map(|cond| Cond::Not(Box::new(cond)))
+            .or(primary())
+    }
+}
+
+fn primary<I: Stream<Token = char>>() -> impl Parser<I, Output = Cond> {
+    choice((
+        attempt(
+            (token('-'), one_of("efdrwxszLhn".chars()))
+                .skip(look_ahead(char::space()))
+                .skip(spaces()),
+        )
+        .and(SpecialStr::parse())
+        .map(|((_, op), word)| Cond::Unary(format!("-{}", op), word)),
+        (
+            SpecialStr::parse(),
+            optional(attempt(spaces().with(binary()))),
+        )
+            .map(|(lhs, rhs)| match rhs {
+                Some((op, rhs)) => Cond::Binary(lhs, op, rhs),
+                None => Cond::Word(lhs),
+            }),
+    ))
+}
+
+// An operator and the right side. The regex of `=~` is taken as it is
+// unless it is quoted or a variable, since it is full of operators.
+fn binary<I: Stream<Token = char>>() -> impl Parser<I, Output = (String, SpecialStr)> {
+    choice((
+        attempt(char::string("=~").skip(look_ahead(char::space())))
+            .skip(spaces())
+            .with(
+                look_ahead(one_of("\"'$".chars()))
+                    .with(SpecialStr::parse())
+                    .or(many1(satisfy(|c: char| !c.is_whitespace())).map(SpecialStr::unquoted)),
+            )
+            .map(|re| (String::from("=~"), re)),
+        attempt(
+            choice((
+                attempt(char::string("==")),
+                attempt(char::string("!=")),
+                char::string("="),
+                char::string("<"),
+                char::string(">"),
+                attempt(char::string("-eq")),
+                attempt(char::string("-ne")),
+                attempt(char::string("-lt")),
+                attempt(char::string("-le")),
+                attempt(char::string("-gt")),
+                char::string("-ge"),
+            ))
+            .skip(look_ahead(char::space())),
+        )
+        .skip(spaces())
+        .and(SpecialStr::parse())
+        .map(|(op, rhs)| (String::from(op), rhs)),
+    ))
+}
@@ -0,0 +1,85 @@
+use std::convert::TryFrom;
+
+// The longest sequence `decode` looks at, `U{0010FFFF}`.
+pub const MAX_ESCAPE: usize = 11;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Escape {
+    Char(char),
+    // `\c`, after which nothing is printed.
+    Stop,
+}
+
+// Decodes the escape sequence after a backslash, shared by string literals
+// and `printf`. Returns it with the number of the characters consumed, or
+// `None` if it is unknown.
+pub fn decode(seq: &[char]) -> Option<(Escape, usize)> {
+    let c = match seq.first()? {
+        'a' => '\x07',
+        'b' => '\x08',
+        'e' => '\x1b',
+        'f' => '\x0c',
+        'n' => '\n',
+        'r' => '\r',
+        't' => '\t',
+        'v' => '\x0b',
+        '\\' => '\\',
+        '"' => '"',
+        'c' => return Some((Escape::Stop, 1)),
+        '0' => {
+            let (code, len) = digits(&seq[1..], 8, 3).unwrap_or((0, 0));
+            return Some((Escape::Char(char::from(code as u8)), len + 1));
+        }
+        'x' => {
+            let (code, len) = digits(&seq[1..], 16, 2)?;
+            return Some((Escape::Char(char::from(code as u8)), len + 1));
+        }
+        'u' | 'U' if seq.get(1) == Some(&'{') => {
+            let (code, len) = digits(&seq[2..], 16, 8)?;
+            if seq.get(len + 2) != Some(&'}') {
+                return None;
+            }
+            return Some((Escape::Char(char::try_from(code).ok()?), len + 3));
+        }
+        _ => return None,
+    };
+    Some((Escape::Char(c), 1))
+}
+
+// Reads one to `max` digits, which fit in `u32`.
+fn digits(seq: &[char], radix: u32, max: usize) -> Option<(u32, usize)> {
+    let digits = seq
+        .iter()
+        .take(max)
+        .take_while(|c| c.is_digit(radix))
+        .collect::<String>();
+    let code = u32::from_str_radix(&digits, radix).ok()?;
+    Some((code, digits.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_str(s: &str) -> Option<(Escape, usize)> {
+        decode(&s.chars().collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn octal() {
+        assert_eq!(decode_str("0101x"), Some((Escape::Char('A'), 4)));
+        assert_eq!(decode_str("0101"), Some((Escape::Char('A'), 4)));
+        assert_eq!(decode_str("012"), Some((Escape::Char('\n'), 3)));
+        assert_eq!(decode_str("08"), Some((Escape::Char('\0'), 1)));
+        assert_eq!(decode_str("0"), Some((Escape::Char('\0'), 1)));
+    }
+
+    #[test]
+    fn stop_and_others() {
+        assert_eq!(decode_str("cabc"), Some((Escape::Stop, 1)));
+        assert_eq!(decode_str("x41"), Some((Escape::Char('A'), 3)));
+        assert_eq!(decode_str("u{3042}"), Some((Escape::Char('あ'), 7)));
+        assert_eq!(decode_str("u{3042"), None);
+        assert_eq!(decode_str("q"), None);
+    }
+}
@@ -3,29 +3,79 @@ extern crate combine;
 mod block;
 mod chars;
 mod command;
+mod cond;
+mod escape;
 mod redirect;
 mod string;
 
-pub use block::Block;
+pub use block::{Block, CaseEnd};
+pub use chars::is_word_char;
 pub use command::{Arg, Command};
+pub use cond::Cond;
+pub use escape::{decode as decode_escape, Escape};
 pub use redirect::{RedKind, RedTarget, Redirect};
 pub use string::SpecialStr;
 
 use chars::{spaces, spaces_line};
+use combine::stream::position::{SourcePosition, Stream};
 use combine::{EasyParser, ParseError};
-use combine::stream::position::Stream;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Parsed {
-    Complete(Block),
+    Complete(Vec<(usize, Block)>),
     Yet,
 }
 
-pub fn parse_line(input: &str) -> anyhow::Result<Parsed> {
-    Ok(match Block::parse().easy_parse(Stream::new(input)) {
+// CRLF line endings are read as LF like the lines of a script, while a lone
+// `\r` is kept as it is.
+pub fn parse_line(input: &str, line: usize) -> anyhow::Result<Parsed> {
+    let input = input.replace("\r\n", "\n");
+    let pos = SourcePosition {
+        line: line as i32,
+        column: 1,
+    };
+    let res = block::statements().easy_parse(Stream::with_positioner(input.as_str(), pos));
+    Ok(match res {
         Ok((res, rem)) if rem.input.is_empty() => Parsed::Complete(res),
         Ok(_) => anyhow::bail!("Unread characters are remain."),
         Err(e) if e.is_unexpected_end_of_input() => Parsed::Yet,
         Err(e) => anyhow::bail!(e.to_string()),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Vec<(usize, Block)> {
+        match parse_line(input, 1).unwrap() {
+            Parsed::Complete(stmts) => stmts,
+            Parsed::Yet => panic!("incomplete: {}", input),
+        }
+    }
+
+    #[test]
+    fn dump() {
+        let stmts = parse("if true {\n    echo a\n} else {\n    echo b\n}");
+        assert_eq!(
+            stmts[0].1.to_string(),
+            "If: true\n  then: Multi\n    line 2: Single: echo a\n  else: Multi\n    line 4: Single: echo b\n"
+        );
+    }
+
+    #[test]
+    fn empty_blocks() {
+        let stmts = parse("if true { } else {\n    # only a comment\n}\nwhile false { }\n{ }");
+        assert_eq!(stmts.len(), 3);
+        assert_eq!(
+            stmts[0].1.to_string(),
+            "If: true\n  then: Multi\n  else: Multi\n"
+        );
+        assert_eq!(stmts[1].1.to_string(), "While: false\n  Multi\n");
+        assert_eq!(stmts[2].1.to_string(), "Multi\n");
+
+        // The closing brace isn't taken as the name of a function.
+        assert_eq!(parse("if true { }; { }").len(), 2);
+        assert_eq!(parse("f { }\n{ }").len(), 2);
+    }
+}
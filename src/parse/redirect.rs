@@ -1,6 +1,7 @@
 use super::{spaces, SpecialStr};
 use combine::{choice, one_of, token, value};
 use combine::{Parser, Stream};
+use std::fmt;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Redirect {
@@ -17,6 +18,31 @@ impl Redirect {
     }
 }
 
+impl fmt::Display for Redirect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            RedKind::AppendStdout => ">>",
+            RedKind::OverwriteStdout => ">",
+            RedKind::ForceStdout => ">|",
+            RedKind::AppendStderr => "2>>",
+            RedKind::OverwriteStderr => "2>",
+            RedKind::ForceStderr => "2>|",
+            RedKind::AppendBoth => "&>>",
+            RedKind::OverwriteBoth => "&>",
+            RedKind::ForceBoth => "&>|",
+            RedKind::Stdin => "<",
+            RedKind::HereDoc => "<<",
+        };
+        match &self.target {
+            RedTarget::Stdin => write!(f, "{}&0", kind),
+            RedTarget::Stdout => write!(f, "{}&1", kind),
+            RedTarget::Stderr => write!(f, "{}&2", kind),
+            RedTarget::Null => write!(f, "{}&!", kind),
+            RedTarget::Other(s) => write!(f, "{}{}", kind, s),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum RedTarget {
     Stdin,
@@ -45,10 +71,13 @@ impl RedTarget {
 pub enum RedKind {
     AppendStdout,
     OverwriteStdout,
+    ForceStdout,
     AppendStderr,
     OverwriteStderr,
+    ForceStderr,
     AppendBoth,
     OverwriteBoth,
+    ForceBoth,
     Stdin,
     HereDoc,
 }
@@ -56,31 +85,31 @@ pub enum RedKind {
 impl RedKind {
     pub fn parse<I: Stream<Token = char>>() -> impl Parser<I, Output = Self> {
         choice((
-            one_of("1-o".chars()).and(token('>')).with(
-                token('>')
-                    .map(|_| Self::AppendStdout)
-                    .or(value(Self::OverwriteStdout)),
-            ),
-            one_of("2=e".chars()).and(token('>')).with(
-                token('>')
-                    .map(|_| Self::AppendStderr)
-                    .or(value(Self::OverwriteStderr)),
-            ),
-            token('&').and(token('>')).with(
-                token('>')
-                    .map(|_| Self::AppendBoth)
-                    .or(value(Self::OverwriteBoth)),
-            ),
+            one_of("1-o".chars()).and(token('>')).with(choice((
+                token('>').map(|_| Self::AppendStdout),
+                token('|').map(|_| Self::ForceStdout),
+                value(Self::OverwriteStdout),
+            ))),
+            one_of("2=e".chars()).and(token('>')).with(choice((
+                token('>').map(|_| Self::AppendStderr),
+                token('|').map(|_| Self::ForceStderr),
+                value(Self::OverwriteStderr),
+            ))),
+            token('&').and(token('>')).with(choice((
+                token('>').map(|_| Self::AppendBoth),
+                token('|').map(|_| Self::ForceBoth),
+                value(Self::OverwriteBoth),
+            ))),
             token('<').with(
                 one_of("<-=h".chars())
                     .map(|_| Self::HereDoc)
                     .or(value(Self::Stdin)),
             ),
-            token('>').with(
-                token('>')
-                    .map(|_| Self::AppendStdout)
-                    .or(value(Self::OverwriteStdout)),
-            ),
+            token('>').with(choice((
+                token('>').map(|_| Self::AppendStdout),
+                token('|').map(|_| Self::ForceStdout),
+                value(Self::OverwriteStdout),
+            ))),
         ))
     }
 }
@@ -1,15 +1,21 @@
 extern crate unindent;
 
-use super::Command;
+use super::escape::MAX_ESCAPE;
+use super::{decode_escape, is_word_char, Command, Escape};
 use combine::parser::char;
 use combine::{
-    any, attempt, choice, count_min_max, many, many1, one_of, parser, satisfy, token, value,
+    any, attempt, choice, many, many1, not_followed_by, one_of, optional, parser, satisfy, sep_by,
+    token,
 };
 use combine::{ParseError, Parser, Stream};
+use std::fmt;
 use unindent::unindent;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub struct SpecialStr(Vec<StrKind>);
+pub struct SpecialStr {
+    kinds: Vec<StrKind>,
+    quoted: bool,
+}
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 enum StrKind {
@@ -17,39 +23,101 @@ enum StrKind {
     Var(String),
     Cmd(Command),
     Pid(usize),
+    Tilde,
 }
 
 impl From<String> for SpecialStr {
     fn from(s: String) -> Self {
-        Self(vec![StrKind::String(s)])
+        Self::quoted(vec![StrKind::String(s)])
+    }
+}
+
+impl Default for SpecialStr {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl SpecialStr {
     pub fn new() -> Self {
-        SpecialStr(Vec::new())
+        Self::quoted(Vec::new())
+    }
+
+    // A word taken as it is, but not quoted.
+    pub fn unquoted(s: String) -> Self {
+        Self::direct(vec![StrKind::String(s)])
+    }
+
+    fn quoted(kinds: Vec<StrKind>) -> Self {
+        Self {
+            kinds,
+            quoted: true,
+        }
+    }
+
+    fn direct(kinds: Vec<StrKind>) -> Self {
+        Self {
+            kinds,
+            quoted: false,
+        }
     }
 
     pub fn parse<I: Stream<Token = char>>() -> impl Parser<I, Output = Self> {
         choice((
             attempt(raw_unindent()).map(Self::from),
-            raw_str().map(|s| Self(vec![StrKind::String(s)])),
+            raw_str().map(Self::from),
             attempt(lit_unindent()),
             lit(),
             direct(),
         ))
     }
 
-    pub fn eval(&self, jobs: &crate::job::SharedJobs) -> anyhow::Result<String> {
+    // The right-hand side of an assignment, where a tilde is also expanded
+    // after each `:` like `PATH=~/bin:~/.local/bin`.
+    pub fn parse_value<I: Stream<Token = char>>() -> impl Parser<I, Output = Self> {
+        choice((
+            attempt(raw_unindent()).map(Self::from),
+            raw_str().map(Self::from),
+            attempt(lit_unindent()),
+            lit(),
+            assign_value(),
+        ))
+    }
+
+    pub fn is_quoted(&self) -> bool {
+        self.quoted
+    }
+
+    pub fn eval(
+        &self,
+        jobs: &crate::job::SharedJobs,
+        ns: &mut crate::eval::NameSpace,
+    ) -> anyhow::Result<String> {
         Ok(self
-            .0
+            .kinds
             .iter()
             .map(|kind| -> anyhow::Result<_> {
                 match kind {
                     StrKind::String(s) => Ok(s.clone()),
-                    StrKind::Var(key) => Ok(std::env::var(key)?),
+                    StrKind::Var(key) if key == "!" => {
+                        match jobs.with(|jobs| Ok(jobs.last_pid()))? {
+                            Some(pid) => Ok(pid.to_string()),
+                            None if ns.opts().nounset => anyhow::bail!("!: unbound variable"),
+                            None => Ok(String::new()),
+                        }
+                    }
+                    // Unset variables are empty unless `nounset` is set.
+                    StrKind::Var(key) => match crate::eval::expand_param(key, ns)? {
+                        Some(value) => Ok(value),
+                        None if ns.opts().nounset => anyhow::bail!("{}: unbound variable", key),
+                        None => Ok(String::new()),
+                    },
+                    StrKind::Tilde => {
+                        Ok(ns.get_var("HOME").ok_or(std::env::VarError::NotPresent)?)
+                    }
                     StrKind::Cmd(cmd) => Ok(crate::eval::Command::from(cmd.clone())
-                        .output(jobs)?
+                        .output(jobs, ns)?
+                        .0
                         .trim()
                         .to_string()),
                     StrKind::Pid(id) => Ok(jobs.with(|jobs| jobs.get_pid(id))?.to_string()),
@@ -60,20 +128,97 @@ impl SpecialStr {
     }
 }
 
+impl fmt::Display for SpecialStr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.quoted {
+            write!(f, "\"")?;
+        }
+        for kind in self.kinds.iter() {
+            match kind {
+                StrKind::String(s) if self.quoted => {
+                    for c in s.chars() {
+                        match c {
+                            '"' | '\\' | '$' | '(' => write!(f, "\\{}", c)?,
+                            '\n' => write!(f, "\\n")?,
+                            '\t' => write!(f, "\\t")?,
+                            c => write!(f, "{}", c)?,
+                        }
+                    }
+                }
+                StrKind::String(s) => write!(f, "{}", s)?,
+                StrKind::Var(key) => write!(f, "${{{}}}", key)?,
+                StrKind::Cmd(cmd) => write!(f, "({})", cmd)?,
+                StrKind::Pid(id) => write!(f, "%{}", id)?,
+                StrKind::Tilde => write!(f, "~")?,
+            }
+        }
+        if self.quoted {
+            write!(f, "\"")?;
+        }
+        Ok(())
+    }
+}
+
 fn direct<I: Stream<Token = char>>() -> impl Parser<I, Output = SpecialStr> {
-    many1(choice((
+    choice((
+        tilde().with(many(direct_part())).map(|mut kinds: Vec<_>| {
+            kinds.insert(0, StrKind::Tilde);
+            kinds
+        }),
+        many1(direct_part()),
+    ))
+    .map(SpecialStr::direct)
+}
+
+fn assign_value<I: Stream<Token = char>>() -> impl Parser<I, Output = SpecialStr> {
+    sep_by(
+        (
+            optional(assign_tilde()),
+            many(choice((
+                command().map(StrKind::Cmd),
+                env().map(StrKind::Var),
+                pid().map(StrKind::Pid),
+                many1(satisfy(|c| c != ':' && is_word_char(c))).map(StrKind::String),
+            ))),
+        ),
+        token(':'),
+    )
+    .map(|parts: Vec<(Option<char>, Vec<StrKind>)>| {
+        let mut kinds = Vec::new();
+        for (n, (tilde, part)) in parts.into_iter().enumerate() {
+            if n > 0 {
+                kinds.push(StrKind::String(String::from(":")));
+            }
+            kinds.extend(tilde.map(|_| StrKind::Tilde));
+            kinds.extend(part);
+        }
+        SpecialStr::direct(kinds)
+    })
+}
+
+fn assign_tilde<I: Stream<Token = char>>() -> impl Parser<I, Output = char> {
+    attempt(token('~').skip(not_followed_by(satisfy(|c: char| {
+        c != '/' && c != ':' && is_word_char(c)
+    }))))
+}
+
+fn direct_part<I: Stream<Token = char>>() -> impl Parser<I, Output = StrKind> {
+    choice((
         command().map(StrKind::Cmd),
         env().map(StrKind::Var),
         pid().map(StrKind::Pid),
         direct_str().map(StrKind::String),
-    )))
-    .map(SpecialStr)
+    ))
+}
+
+fn tilde<I: Stream<Token = char>>() -> impl Parser<I, Output = char> {
+    attempt(token('~').skip(not_followed_by(satisfy(|c: char| {
+        c != '/' && is_word_char(c)
+    }))))
 }
 
 fn direct_str<I: Stream<Token = char>>() -> impl Parser<I, Output = String> {
-    many1(satisfy(|c: char| {
-        !c.is_whitespace() && "#|&;${}()".chars().all(|d| c != d)
-    }))
+    many1(satisfy(is_word_char))
 }
 
 fn lit_unindent<I: Stream<Token = char>>() -> impl Parser<I, Output = SpecialStr> {
@@ -97,6 +242,9 @@ fn lit<I: Stream<Token = char>>() -> impl Parser<I, Output = SpecialStr> {
     token('"')
         .with(parser(|input: &mut I| {
             let (s, commited) = lit_str().parse_stream(input).into_result()?;
+            if s.is_empty() {
+                return Ok((SpecialStr::new(), commited));
+            }
             let res = lit_reparse().parse_stream(&mut s.as_str()).into_result();
 
             match res {
@@ -112,7 +260,10 @@ fn lit<I: Stream<Token = char>>() -> impl Parser<I, Output = SpecialStr> {
 fn lit_str<I: Stream<Token = char>>() -> impl Parser<I, Output = String> {
     many(choice((
         token('\\').with(any()).map(|c| {
-            if c == '"' {
+            // A backslash before a newline joins the lines.
+            if c == '\n' {
+                String::new()
+            } else if c == '"' {
                 String::from(c)
             } else {
                 format!("\\{}", c)
@@ -124,48 +275,49 @@ fn lit_str<I: Stream<Token = char>>() -> impl Parser<I, Output = String> {
 }
 
 fn lit_reparse<I: Stream<Token = char>>() -> impl Parser<I, Output = SpecialStr> {
-    use std::convert::TryFrom;
-
     many1(choice((
         command().map(StrKind::Cmd),
         env().map(StrKind::Var),
-        pid().map(StrKind::Pid),
-        many1(satisfy(|c| c != '$' && c != '(').then(|c| {
-            if c == '\\' {
-                choice((
-                    one_of("abefnrtv%$(\\".chars()).map(|seq| match seq {
-                        'a' => '\x07',
-                        'b' => '\x08',
-                        'e' => '\x1b',
-                        'f' => '\x0c',
-                        'n' => '\n',
-                        'r' => '\r',
-                        't' => '\t',
-                        'v' => '\x0b',
-                        '%' => '%',
-                        '$' => '$',
-                        '(' => '(',
-                        '\\' => '\\',
-                        _ => unimplemented!(),
-                    }),
-                    token('x')
-                        .with(count_min_max(2, 2, char::hex_digit()))
-                        .map(|s: String| u8::from_str_radix(s.as_str(), 16).unwrap() as char),
-                    one_of("uU".chars())
-                        .and(token('{'))
-                        .with(many1(char::hex_digit()).map(|s: String| {
-                            char::try_from(u32::from_str_radix(s.as_str(), 16).unwrap()).unwrap()
-                        }))
-                        .skip(token('}')),
-                ))
-                .left()
-            } else {
-                value(c).right()
-            }
-        }))
+        many1(choice((
+            token('\\').with(choice((
+                one_of("%$(".chars()).map(String::from),
+                // `\c` is left for `echo -e`.
+                escape().map(|esc| match esc {
+                    Escape::Char(c) => String::from(c),
+                    Escape::Stop => String::from("\\c"),
+                }),
+            ))),
+            many1(satisfy(|c| c != '$' && c != '(' && c != '\\')),
+        )))
+        .map(|strs: Vec<String>| strs.concat())
         .map(StrKind::String),
     )))
-    .map(SpecialStr)
+    .map(SpecialStr::quoted)
+}
+
+fn escape<I: Stream<Token = char>>() -> impl Parser<I, Output = Escape> {
+    parser(|input: &mut I| {
+        let start = input.checkpoint();
+        let mut seq = Vec::new();
+        while seq.len() < MAX_ESCAPE {
+            match input.uncons() {
+                Ok(c) => seq.push(c),
+                Err(_) => break,
+            }
+        }
+        input.reset(start).ok();
+        match decode_escape(&seq) {
+            Some((esc, len)) => {
+                for _ in 0..len {
+                    input.uncons().ok();
+                }
+                Ok((esc, combine::error::Commit::Commit(())))
+            }
+            None => Err(combine::error::Commit::Peek(combine::error::Tracked::from(
+                I::Error::empty(input.position()),
+            ))),
+        }
+    })
 }
 
 fn raw_unindent<I: Stream<Token = char>>() -> impl Parser<I, Output = String> {
@@ -190,17 +342,58 @@ fn env<I: Stream<Token = char>>() -> impl Parser<I, Output = String> {
         token('{')
             .with(many1(satisfy(|c| c != '}')).skip(token('}')))
             .or(many1(satisfy(|c: char| {
-                !c.is_whitespace() && c != '"' && c != '\'' && c != ')'
-            }))),
+                c.is_ascii_alphanumeric() || c == '_'
+            })))
+            .or(one_of("$#@?!*-".chars()).map(String::from)),
     )
 }
 
+// `(cmd)`, or `$(cmd)` as other shells. A pipeline is captured as a whole.
 fn command<I: Stream<Token = char>>() -> impl Parser<I, Output = Command> {
-    token('(').with(Command::parse()).skip(token(')'))
+    choice((
+        attempt(char::string("$(")).map(|_| ()),
+        token('(').map(|_| ()),
+    ))
+    .with(Command::parse())
+    .skip(token(')'))
 }
 
 fn pid<I: Stream<Token = char>>() -> impl Parser<I, Output = usize> {
-    token('%')
-        .with(many1(char::digit()))
-        .map(|id: String| id.parse().unwrap())
+    attempt(token('%').with(many1(char::digit()))).map(|id: String| id.parse().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> SpecialStr {
+        let (parsed, rest) = SpecialStr::parse().parse(s).unwrap();
+        assert_eq!(rest, "");
+        parsed
+    }
+
+    #[test]
+    fn job_refs() {
+        assert_eq!(parse("%1").kinds, [StrKind::Pid(1)]);
+        assert_eq!(
+            parse("\"%5.2f $x\"").kinds,
+            [
+                StrKind::String(String::from("%5.2f ")),
+                StrKind::Var(String::from("x"))
+            ]
+        );
+        assert_eq!(parse("\"%1\"").to_string(), "\"%1\"");
+    }
+
+    #[test]
+    fn line_continuation() {
+        assert_eq!(
+            parse("\"a\\\nb\\nc\"").kinds,
+            [StrKind::String(String::from("ab\nc"))]
+        );
+        assert_eq!(
+            parse("'a\\\nb'").kinds,
+            [StrKind::String(String::from("a\\\nb"))]
+        );
+    }
 }
@@ -0,0 +1,38 @@
+use std::os::unix::io::RawFd;
+
+// Shell messages are colored only on a terminal, and never with `NO_COLOR`
+// set (https://no-color.org).
+pub fn enabled(fd: RawFd) -> bool {
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    !no_color && nix::unistd::isatty(fd).unwrap_or(false)
+}
+
+fn paint<T: AsRef<str>>(code: &str, s: T, fd: RawFd) -> String {
+    if enabled(fd) {
+        format!("\x1b[{}m{}\x1b[0m", code, s.as_ref())
+    } else {
+        s.as_ref().to_string()
+    }
+}
+
+pub fn error<T: AsRef<str>>(s: T) -> String {
+    paint("31", s, 2)
+}
+
+pub fn prompt<T: AsRef<str>>(s: T) -> String {
+    paint("1;32", s, 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_without_a_terminal() {
+        let (r, w) = nix::unistd::pipe().unwrap();
+        assert!(!enabled(w));
+        assert_eq!(paint("31", "x", w), "x");
+        nix::unistd::close(r).ok();
+        nix::unistd::close(w).ok();
+    }
+}
@@ -1,23 +1,55 @@
-use super::Reader;
+use super::{color, Reader};
 use std::fs::File;
-use std::io::{BufRead, BufReader, Lines};
+use std::io::{BufRead, BufReader};
 use std::path::Path;
 
-pub struct IOReader<R>(Lines<R>);
+pub struct IOReader<R> {
+    reader: R,
+    line: usize,
+}
 
 impl<R: BufRead> Reader for IOReader<R> {
+    // Invalid UTF-8 is replaced with U+FFFD, warning where it is, so that a
+    // Latin-1 file still runs instead of losing the line.
     fn next_line(&mut self) -> anyhow::Result<Option<String>> {
-        match self.0.next() {
-            Some(line) => Ok(Some(line?)),
-            None => Ok(None),
+        let mut buf = Vec::new();
+        if self.reader.read_until(b'\n', &mut buf)? == 0 {
+            return Ok(None);
+        }
+        self.line += 1;
+        if buf.last() == Some(&b'\n') {
+            buf.pop();
+            if buf.last() == Some(&b'\r') {
+                buf.pop();
+            }
         }
+
+        Ok(Some(match String::from_utf8(buf) {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!(
+                    "{}",
+                    color::error(format!(
+                        "line {}: invalid UTF-8 at byte {}, replaced with U+FFFD.",
+                        self.line,
+                        e.utf8_error().valid_up_to() + 1
+                    ))
+                );
+                String::from_utf8_lossy(e.as_bytes()).into_owned()
+            }
+        }))
+    }
+}
+
+impl<R: BufRead> IOReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader, line: 0 }
     }
 }
 
 impl IOReader<BufReader<File>> {
     pub fn new_file<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
         let file = File::open(path)?;
-        let reader = BufReader::new(file);
-        Ok(Self(reader.lines()))
+        Ok(Self::new(BufReader::new(file)))
     }
 }
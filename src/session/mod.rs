@@ -1,16 +1,25 @@
+pub mod color;
 mod io;
 mod prompt;
 
 pub use io::IOReader;
 pub use prompt::PromptReader;
 
-use crate::eval::{Block, NameSpace};
+use crate::eval::{located, Block, Completions, History, NameSpace, SubshellExit};
 use crate::job::SharedJobs;
 use crate::parse::{parse_line, Parsed};
+use std::collections::BTreeMap;
 
 pub struct Session<T> {
     reader: T,
     jobs: SharedJobs,
+    namespace: NameSpace,
+    line: usize,
+    dump_ast: bool,
+    // EOFs in a row at the prompt.
+    eofs: usize,
+    // An error has been reported for a statement.
+    failed: bool,
 }
 
 pub trait Reader: Sized {
@@ -22,65 +31,279 @@ pub trait Reader: Sized {
     fn more_line(&mut self) -> anyhow::Result<Option<String>> {
         self.next_line()
     }
+    fn interactive(&self) -> bool {
+        false
+    }
+    #[allow(unused_variables)]
+    fn add_history(&mut self, line: &str) {}
+    #[allow(unused_variables)]
+    fn set_history_size(&mut self, size: usize) {}
+    #[allow(unused_variables)]
+    fn set_completions(&mut self, completions: &Completions) {}
+    #[allow(unused_variables)]
+    fn set_vi_mode(&mut self, vi: bool) {}
 }
 
 impl<T: Reader> Session<T> {
-    pub fn new(mut reader: T) -> anyhow::Result<Self> {
+    pub fn new(reader: T) -> anyhow::Result<Self> {
+        Self::with_namespace(reader, NameSpace::new())
+    }
+
+    // A sourced file runs with the namespace of the caller, which is taken
+    // back by `into_namespace`.
+    pub fn with_namespace(mut reader: T, namespace: NameSpace) -> anyhow::Result<Self> {
         let jobs = SharedJobs::new();
         reader.init(&jobs)?;
-        Ok(Self { reader, jobs })
+        Ok(Self {
+            reader,
+            jobs,
+            namespace,
+            line: 0,
+            dump_ast: false,
+            eofs: 0,
+            failed: false,
+        })
+    }
+
+    pub fn namespace(&self) -> &NameSpace {
+        &self.namespace
+    }
+
+    pub fn namespace_mut(&mut self) -> &mut NameSpace {
+        &mut self.namespace
     }
 
-    pub fn next(&mut self, namespace: &mut NameSpace) -> anyhow::Result<bool> {
-        let mut line = match self.reader.next_line() {
+    pub fn into_namespace(self) -> NameSpace {
+        self.namespace
+    }
+
+    // Prints the parsed statements instead of running them.
+    pub fn dump_ast(&mut self, on: bool) {
+        self.dump_ast = on;
+    }
+
+    fn next(&mut self) -> anyhow::Result<bool> {
+        self.report_jobs()?;
+        self.prompt_command();
+        self.reader.set_completions(self.namespace.completions());
+        self.reader.set_vi_mode(self.namespace.opts().vi);
+        let line = match self.reader.next_line() {
             Ok(Some(s)) => s,
+            Ok(None) if self.ignore_eof() => {
+                eprintln!("Use \"exit\" to leave the shell.");
+                return Ok(true);
+            }
             Ok(None) => return Ok(false),
             Err(e) => {
-                eprintln!("Readline Error: {}", e);
+                eprintln!("{}", color::error(format!("Readline Error: {}", e)));
                 return Ok(true);
             }
         };
+        self.eofs = 0;
+        self.line += 1;
+        let start = self.line;
+        let mut line = match self.expand_history(line) {
+            Some(s) => s,
+            None => return Ok(true),
+        };
 
-        let line = loop {
-            match parse_line(line.as_str()) {
-                Ok(Parsed::Complete(cmd)) => break cmd,
+        let stmts = loop {
+            match parse_line(line.as_str(), start) {
+                Ok(Parsed::Complete(stmts)) => {
+                    self.record_history(&line);
+                    break stmts;
+                }
                 Ok(Parsed::Yet) => {
                     let additional = match self.reader.more_line() {
                         Ok(Some(s)) => s,
-                        Ok(None) => return Ok(true),
+                        Ok(None) if self.reader.interactive() => return Ok(true),
+                        Ok(None) => {
+                            return self.parse_error(anyhow::anyhow!("Unexpected end of file."))
+                        }
                         Err(e) => {
-                            eprintln!("Readline Error: {}", e);
+                            eprintln!("{}", color::error(format!("Readline Error: {}", e)));
                             return Ok(true);
                         }
                     };
+                    self.line += 1;
+                    let additional = match self.expand_history(additional) {
+                        Some(s) => s,
+                        None => return Ok(true),
+                    };
                     line.push('\n');
                     line.push_str(&additional);
                     continue;
                 }
+                Err(e) => return self.parse_error(e),
+            }
+        };
+
+        for (line, stmt) in stmts {
+            if self.dump_ast {
+                print!("{}", stmt);
+                continue;
+            }
+            self.namespace.set_line(line);
+            let block = Block::from(stmt);
+
+            match block.eval(&self.jobs, &mut self.namespace) {
+                // The rest of a sourced file is skipped by `break` and
+                // `continue` for the loop around `source`.
+                Ok(_) if self.namespace.jumping() => return Ok(false),
+                Ok(_) => (),
+                // `exit` in a sourced file in a subshell ends the subshell.
+                Err(e) if e.is::<SubshellExit>() => return Err(e),
+                // An error, like the limit of a loop, fails the statement.
+                Err(e) if !self.reader.interactive() => {
+                    eprintln!("{}", color::error(located(e, line).to_string()));
+                    self.namespace.push_var("status", "1");
+                    self.failed = true;
+                    return Ok(true);
+                }
                 Err(e) => {
-                    eprintln!("Parse Error: {}", e);
+                    eprintln!("{}", color::error(e.to_string()));
+                    self.namespace.push_var("status", "1");
+                    self.failed = true;
                     return Ok(true);
                 }
             }
+        }
+
+        Ok(true)
+    }
+
+    // Prints the held messages about background jobs before the prompt.
+    fn report_jobs(&self) -> anyhow::Result<()> {
+        use std::io::Write;
+
+        let hold = !self.namespace.opts().notify;
+        let notices = self.jobs.with(|jobs| {
+            jobs.set_hold(hold);
+            Ok(jobs.take_notices())
+        })?;
+        if !notices.is_empty() {
+            let mut out = notices.join("\n");
+            out.push('\n');
+            std::io::stderr().write_all(out.as_bytes()).ok();
+        }
+        Ok(())
+    }
+
+    // With `ignoreeof`, the prompt ends at the EOF after `$IGNOREEOF` (10 by
+    // default) ones in a row.
+    fn ignore_eof(&mut self) -> bool {
+        if !self.reader.interactive() || !self.namespace.opts().ignoreeof {
+            return false;
+        }
+        let limit = self
+            .namespace
+            .get_var("IGNOREEOF")
+            .and_then(|n| n.parse::<usize>().ok())
+            .unwrap_or(10);
+        self.eofs += 1;
+        self.eofs <= limit
+    }
+
+    // Runs `$PROMPT_COMMAND` before each prompt, keeping `$status` of the
+    // last command.
+    fn prompt_command(&mut self) {
+        let (jobs, namespace) = (&self.jobs, &mut self.namespace);
+        let command = match namespace.get_var("PROMPT_COMMAND") {
+            Some(command) if self.reader.interactive() => command,
+            _ => return,
         };
+        let status = namespace.get_var("status");
+        let res = command
+            .parse::<Block>()
+            .and_then(|block| block.eval(jobs, namespace));
+        if let Err(e) = res {
+            eprintln!("{}", color::error(e.to_string()));
+        }
+        if let Some(status) = status {
+            namespace.push_var("status", status);
+        }
+    }
+
+    // Scripts stop at a syntax error unless `parseskip` is set, while the
+    // prompt always goes on.
+    fn parse_error(&self, e: anyhow::Error) -> anyhow::Result<bool> {
+        if self.reader.interactive() || self.namespace.opts().parse_skip {
+            eprintln!("{}", color::error(format!("Parse Error: {}", e)));
+            Ok(true)
+        } else {
+            anyhow::bail!("Parse Error: {}", e)
+        }
+    }
 
-        eprintln!("{:?}", line);
-        let block = Block::from(line);
+    fn expand_history(&self, line: String) -> Option<String> {
+        if !self.reader.interactive() {
+            return Some(line);
+        }
 
-        match block.eval(&self.jobs, namespace) {
-            Ok(_) => (),
+        match self.namespace.history().expand(&line) {
+            Ok(expanded) => {
+                if expanded != line {
+                    eprintln!("{}", expanded);
+                }
+                Some(expanded)
+            }
             Err(e) => {
-                eprintln!("{}", e);
-                return Ok(true);
+                eprintln!("{}", color::error(e.to_string()));
+                None
             }
         }
+    }
 
-        Ok(true)
+    // Loads `$HISTFILE` into the history of an interactive session.
+    pub fn load_history(&mut self) -> anyhow::Result<()> {
+        let namespace = &mut self.namespace;
+        let file = match namespace.get_var("HISTFILE") {
+            Some(file) if self.reader.interactive() => file,
+            _ => return Ok(()),
+        };
+        namespace.history_mut().load(file)?;
+        let size = history_size(namespace, "HISTSIZE");
+        namespace.history_mut().truncate(size);
+        self.reader.set_history_size(size);
+        for n in 1..=namespace.history().len() {
+            self.reader
+                .add_history(namespace.history().get(n).unwrap_or_default());
+        }
+        Ok(())
+    }
+
+    fn record_history(&mut self, line: &str) {
+        let namespace = &mut self.namespace;
+        if !self.reader.interactive() || line.trim().is_empty() {
+            return;
+        }
+
+        let control = namespace.get_var("HISTCONTROL").unwrap_or_default();
+        let ignore = |opt| control.split(':').any(|c| c == opt || c == "ignoreboth");
+        if ignore("ignorespace") && line.starts_with(' ') {
+            return;
+        }
+        if ignore("ignoredups") && namespace.history().last() == Some(line) {
+            return;
+        }
+
+        let size = history_size(namespace, "HISTSIZE");
+        namespace.history_mut().push(line);
+        namespace.history_mut().truncate(size);
+        self.reader.set_history_size(size);
+        self.reader.add_history(line);
+
+        if let Some(file) = namespace.get_var("HISTFILE") {
+            let size = history_size(namespace, "HISTFILESIZE");
+            if let Err(e) = History::append_file(file, line, size) {
+                eprintln!("{}", color::error(e.to_string()));
+            }
+        }
     }
 
-    pub fn all(&mut self, namespace: &mut NameSpace) -> anyhow::Result<()> {
+    pub fn all(&mut self) -> anyhow::Result<()> {
         loop {
-            if !self.next(namespace)? {
+            if !self.next()? {
                 break;
             }
         }
@@ -88,21 +311,83 @@ impl<T: Reader> Session<T> {
         Ok(())
     }
 
-    pub fn all_with_args<N, A, AS>(
-        &mut self,
-        namespace: &mut NameSpace,
-        name: N,
-        args: AS,
-    ) -> anyhow::Result<()>
+    // Returns the status of the last command, taken before the arguments
+    // are dropped, or 1 if an error has been reported for a statement like a
+    // stray `break`.
+    pub fn all_with_args<N, A, AS>(&mut self, name: N, args: AS) -> anyhow::Result<i32>
     where
         N: AsRef<str>,
         A: AsRef<str>,
         AS: IntoIterator<Item = A>,
     {
-        namespace.mark();
-        namespace.set_args(name, args);
-        self.all(namespace)?;
-        namespace.drop();
-        Ok(())
+        self.namespace.mark();
+        self.namespace.set_args(name, args);
+        let res = self.all().map(|_| {
+            if self.failed {
+                1
+            } else {
+                self.namespace
+                    .get_var("status")
+                    .and_then(|code| code.parse().ok())
+                    .unwrap_or(0)
+            }
+        });
+        self.namespace.drop();
+        res
+    }
+}
+
+/// Variables for programs embedding the shell. Shell variables stay in the
+/// namespace, while exported ones are also passed to every command.
+///
+/// ```
+/// use tish::session::{IOReader, Session};
+///
+/// let mut session = Session::new(IOReader::new("echo hi\n".as_bytes())).unwrap();
+/// session.set_var("LOCAL", "1");
+/// session.export_var("EXPORTED", "2");
+/// assert_eq!(session.get_var("EXPORTED").as_deref(), Some("2"));
+///
+/// let env = session.env_snapshot();
+/// assert_eq!(env.get("EXPORTED").map(String::as_str), Some("2"));
+/// assert!(!env.contains_key("LOCAL"));
+/// ```
+impl<T: Reader> Session<T> {
+    pub fn get_var(&self, key: &str) -> Option<String> {
+        self.namespace.get_var(key)
+    }
+
+    pub fn set_var(&mut self, key: &str, value: &str) {
+        self.namespace.push_var(key, value);
     }
+
+    pub fn export_var(&mut self, key: &str, value: &str) {
+        self.namespace.push_gvar(key, value);
+    }
+
+    // Commands see `env` with the exports made after, instead of the
+    // environment the shell was started with.
+    pub fn set_base_env(&mut self, env: BTreeMap<String, String>) {
+        self.namespace.set_base_env(env);
+    }
+
+    // Exactly the environment commands are spawned with.
+    pub fn env_snapshot(&self) -> BTreeMap<String, String> {
+        self.namespace.env_snapshot()
+    }
+
+    // Background blocks start the shell again as `tish --child`, so a program
+    // which is not the `tish` binary itself sets the path to it.
+    pub fn set_shell_path(&mut self, path: &str) {
+        self.namespace.set_shell_path(path);
+    }
+}
+
+// `HISTFILESIZE` falls back to `HISTSIZE`, which is 500 by default.
+fn history_size(namespace: &NameSpace, var: &str) -> usize {
+    namespace
+        .get_var(var)
+        .or_else(|| namespace.get_var("HISTSIZE"))
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(500)
 }
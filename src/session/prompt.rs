@@ -1,16 +1,45 @@
 extern crate rustyline;
 extern crate signal_hook;
 
-use super::Reader;
+use super::{color, Reader};
+use crate::eval::Completions;
 use crate::job::SharedJobs;
 use anyhow::Context;
-use rustyline::{error::ReadlineError, Editor};
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{config::Configurer, error::ReadlineError, EditMode, Editor, Helper};
 use signal_hook::consts::signal;
 use signal_hook::iterator::Signals;
 use std::thread;
 
-#[derive(Debug)]
-pub struct PromptReader(Editor<()>);
+pub struct PromptReader(Editor<ShellHelper>);
+
+// Without a terminal, rustyline reads lines with the newline left in them,
+// which would go into the history.
+fn chomp(mut line: String) -> String {
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+    line
+}
+
+// The line with invalid UTF-8 has been consumed, so reading goes on.
+fn readline_error(e: ReadlineError) -> anyhow::Error {
+    match e {
+        ReadlineError::Utf8Error => {
+            anyhow::anyhow!("Invalid UTF-8 in the input, the line is skipped.")
+        }
+        ReadlineError::Io(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+            anyhow::anyhow!("Invalid UTF-8 in the input, the line is skipped.")
+        }
+        e => e.into(),
+    }
+}
 
 impl Reader for PromptReader {
     fn init(&mut self, jobs: &SharedJobs) -> anyhow::Result<()> {
@@ -18,32 +47,132 @@ impl Reader for PromptReader {
     }
 
     fn next_line(&mut self) -> anyhow::Result<Option<String>> {
-        match self.0.readline("$ ") {
-            Ok(s) => Ok(Some(s)),
+        match self.0.readline(&color::prompt("$ ")) {
+            Ok(s) => Ok(Some(chomp(s))),
             Err(ReadlineError::Interrupted) => Ok(Some(String::new())),
             Err(ReadlineError::Eof) => Ok(None),
-            Err(e) => Err(e.into()),
+            Err(e) => Err(readline_error(e)),
         }
     }
 
     fn more_line(&mut self) -> anyhow::Result<Option<String>> {
-        match self.0.readline("... ") {
-            Ok(s) => Ok(Some(s)),
+        match self.0.readline(&color::prompt("... ")) {
+            Ok(s) => Ok(Some(chomp(s))),
             Err(ReadlineError::Eof) => Ok(None),
-            Err(e) => Err(e.into()),
+            Err(e) => Err(readline_error(e)),
         }
     }
+
+    fn interactive(&self) -> bool {
+        true
+    }
+
+    fn add_history(&mut self, line: &str) {
+        self.0.add_history_entry(line);
+    }
+
+    fn set_history_size(&mut self, size: usize) {
+        self.0.set_max_history_size(size);
+    }
+
+    fn set_completions(&mut self, completions: &Completions) {
+        if let Some(helper) = self.0.helper_mut() {
+            helper.completions = completions.clone();
+        }
+    }
+
+    fn set_vi_mode(&mut self, vi: bool) {
+        self.0
+            .set_edit_mode(if vi { EditMode::Vi } else { EditMode::Emacs });
+    }
+}
+
+impl Default for PromptReader {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PromptReader {
     pub fn new() -> Self {
-        Self(Editor::new())
+        let mut editor = Editor::new();
+        editor.set_helper(Some(ShellHelper {
+            completions: Completions::default(),
+            files: FilenameCompleter::new(),
+        }));
+        Self(editor)
+    }
+}
+
+// Completes the arguments of the commands registered with `complete`.
+struct ShellHelper {
+    completions: Completions,
+    files: FilenameCompleter,
+}
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let head = &line[..pos];
+        let stmt = head
+            .rsplit([';', '|', '&', '{', '}'])
+            .next()
+            .unwrap_or(head)
+            .trim_start();
+        let name = match stmt.split_whitespace().next() {
+            Some(name) if stmt.len() > name.len() => name,
+            _ => return Ok((pos, Vec::new())),
+        };
+        let spec = match self.completions.get(name) {
+            Some(spec) => spec,
+            None => return Ok((pos, Vec::new())),
+        };
+
+        let start = head.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let mut candidates = self
+            .completions
+            .words(name, &head[start..])
+            .into_iter()
+            .map(|word| Pair {
+                display: word.to_string(),
+                replacement: word.to_string(),
+            })
+            .collect::<Vec<_>>();
+        if spec.files {
+            let (file_start, files) = self.files.complete_path(line, pos)?;
+            if file_start == start || candidates.is_empty() {
+                candidates.extend(files);
+                return Ok((file_start, candidates));
+            }
+        }
+        Ok((start, candidates))
     }
 }
 
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
 fn sighook(jobs: &SharedJobs) -> anyhow::Result<()> {
-    let mut signals = Signals::new(&[signal::SIGINT, signal::SIGTSTP, signal::SIGCHLD])
-        .context("Failed to initialize signals.")?;
+    let mut signals = Signals::new([
+        signal::SIGINT,
+        signal::SIGTSTP,
+        signal::SIGCHLD,
+        signal::SIGHUP,
+    ])
+    .context("Failed to initialize signals.")?;
 
     let jobs = jobs.clone();
     thread::spawn(move || {
@@ -52,6 +181,7 @@ fn sighook(jobs: &SharedJobs) -> anyhow::Result<()> {
                 signal::SIGINT => jobs.sigint(),
                 signal::SIGTSTP => jobs.sigtstp(),
                 signal::SIGCHLD => jobs.sigchld(),
+                signal::SIGHUP => jobs.sighup(),
                 _ => unreachable!(),
             });
             match res {
@@ -62,3 +192,18 @@ fn sighook(jobs: &SharedJobs) -> anyhow::Result<()> {
     });
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vi_mode() {
+        let mut reader = PromptReader::new();
+        assert_eq!(reader.0.config_mut().edit_mode(), EditMode::Emacs);
+        reader.set_vi_mode(true);
+        assert_eq!(reader.0.config_mut().edit_mode(), EditMode::Vi);
+        reader.set_vi_mode(false);
+        assert_eq!(reader.0.config_mut().edit_mode(), EditMode::Emacs);
+    }
+}
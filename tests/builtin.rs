@@ -0,0 +1,591 @@
+mod common;
+
+use common::{run, run_err};
+
+#[test]
+fn exit_warns_again_after_another_command() {
+    let (out, code) = run("set -o checkjobs\n\
+         sleep 1 &\n\
+         exit 3\n\
+         echo between\n\
+         exit 3\n\
+         exit 3\n\
+         echo after\n");
+    let out = out.lines().filter(|line| !line.starts_with("Job %"));
+    assert_eq!(out.collect::<Vec<_>>(), ["between"]);
+    assert_eq!(code, 3);
+}
+
+#[test]
+fn exit_forced_or_without_checkjobs() {
+    let (out, code) = run("set -o checkjobs\n\
+         sleep 0.1 &\n\
+         exit -f 4\n\
+         echo after\n");
+    assert!(!out.contains("after"));
+    assert_eq!(code, 4);
+    let (out, code) = run("sleep 0.1 &\nexit 5\necho after\n");
+    assert!(!out.contains("after"));
+    assert_eq!(code, 5);
+}
+
+#[test]
+fn misused_builtin_prints_its_usage() {
+    let (out, err, _) = run_err("cd a b c\necho $status\n");
+    assert_eq!(out, "2\n");
+    assert!(err.contains("tish: cd: usage: cd [dir]\n"));
+}
+
+#[test]
+fn hash_forgets_commands() {
+    let (out, _) = run("ls > /dev/null\n\
+         ls > /dev/null\n\
+         hash\n\
+         hash -r\n\
+         hash\n");
+    let lines = out.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[1].starts_with("   2\t") && lines[1].ends_with("/ls"));
+    assert_eq!(lines[2], "hits\tcommand");
+}
+
+#[test]
+fn printf_formats_in_quotes() {
+    let (out, _) = run("printf \"%5.2f|%*d|%q\\n\" 3.14159 3 7 \"a b\"\n");
+    assert_eq!(out, " 3.14|  7|\"a b\"\n");
+}
+
+#[test]
+fn octal_and_stop_escapes() {
+    let (out, _) = run("printf 'a\\0101\\cdef'\n\
+         printf \"%b|\\n\" 'b\\0102\\cz'\n\
+         printf \"\\n\"\n\
+         echo -e \"c\\0103\\cz\"\n\
+         echo end\n");
+    assert_eq!(out, "aAbB\ncCend\n");
+}
+
+#[test]
+fn read_prints_the_prompt() {
+    let (out, err, _) = run_err("echo hi | { read -p \"name? \" x; echo $x }\n");
+    assert_eq!(out, "hi\n");
+    assert!(err.contains("name? "));
+}
+
+#[test]
+fn read_times_out() {
+    let (out, _) = run("sleep 1 | read -t 0.1 x\necho $status\n");
+    assert_eq!(out, "1\n");
+}
+
+#[test]
+fn read_takes_some_characters() {
+    let (out, _) = run("printf abcdef | { read -n 3 x; echo $x }\n\
+         printf '\u{e9}t\u{e9}!' | { read -n 3 y; echo $y }\n");
+    assert_eq!(out, "abc\n\u{e9}t\u{e9}\n");
+}
+
+#[test]
+fn read_fails_at_the_end() {
+    let (out, _) = run("read x < /dev/null\necho \"[$x] $status\"\n");
+    assert_eq!(out, "[] 1\n");
+}
+
+#[test]
+fn read_in_a_pipeline() {
+    let (out, _) = run("echo last | read x; echo \"[$x]\"\n\
+         echo middle | read y | cat; echo \"[$y]\"\n\
+         echo inside | { read z; echo \"[$z]\" } | cat\n\
+         echo first > /dev/null | read w; echo \"[$w] $status\"\n");
+    assert_eq!(out, "[]\n[]\n[inside]\n[] 1\n");
+}
+
+#[test]
+fn jobs_as_json() {
+    let (out, _) = run("sleep 1 &\njobs --json\nkill %1\n");
+    let json = out.lines().find(|line| line.starts_with('[')).unwrap();
+    assert!(json.starts_with("[{\"id\":1,\"pid\":"), "{}", json);
+    assert!(json.ends_with(",\"suspended\":false}]"), "{}", json);
+}
+
+#[test]
+fn type_and_alias_as_json() {
+    let (out, _) = run("f { true }\n\
+         alias ll = ls -l\n\
+         type --json f cd nosuchcommand\n\
+         alias --json\n");
+    assert_eq!(
+        out,
+        "[{\"name\":\"f\",\"kind\":\"function\",\"path\":null},\
+         {\"name\":\"cd\",\"kind\":\"builtin\",\"path\":null},\
+         {\"name\":\"nosuchcommand\",\"kind\":\"none\",\"path\":null}]\n\
+         [{\"name\":\"ll\",\"value\":\"ls -l\"}]\n"
+    );
+}
+
+#[test]
+fn export_rejects_bad_names() {
+    let (out, err, _) = run_err("export x=1\necho $status\nexport y = 2\nsh -c 'echo $y'\n");
+    assert_eq!(out, "1\n2\n");
+    assert!(err.contains("line 1: x=1: invalid variable name\n"));
+}
+
+#[test]
+fn mapfile_reads_lines() {
+    let (out, _) = run(
+        "printf 'a\\nb\\nc\\n' | { mapfile arr; echo ${#arr[@]} ${arr[2]} }\n\
+         printf 'a\\nb\\nc\\n' | { mapfile -n 2 brr; echo ${#brr[@]} }\n\
+         printf 'a\\nb\\n' | { readarray -t; echo \"[${MAPFILE[0]}]\" ${#MAPFILE[@]} }\n",
+    );
+    assert_eq!(out, "3 c\n2\n[a] 2\n");
+}
+
+#[test]
+fn printf_to_a_variable() {
+    let (out, _) = run("printf -v s \"%d-%d\" 1 2; echo $s;\n");
+    assert_eq!(out, "1-2\n");
+}
+
+#[test]
+fn cd_needs_a_directory() {
+    let (out, err, _) = run_err(
+        "let f = /tmp/tish-cd-$$\n\
+         cd /\n\
+         touch $f\n\
+         cd $f\n\
+         echo $status\n\
+         rm $f\n\
+         cd $f\n\
+         echo $status $PWD\n",
+    );
+    assert_eq!(out, "1\n1 /\n");
+    assert!(err.contains(": Not a directory\n"));
+    assert!(err.contains(": No such file or directory\n"));
+}
+
+#[test]
+fn help_lists_builtins() {
+    let (out, err, _) = run_err("help cd\necho $status\nhelp nosuch\necho $status\nhelp\n");
+    let mut lines = out.lines();
+    assert_eq!(lines.next(), Some("usage: cd [dir]"));
+    assert_eq!(lines.next(), Some("    Change the current directory."));
+    assert_eq!(lines.next(), Some("0"));
+    assert_eq!(lines.next(), Some("1"));
+    let names = lines
+        .map(|line| line.split_whitespace().next().unwrap())
+        .collect::<Vec<_>>();
+    assert!(names.contains(&"cd") && names.contains(&"help") && names.contains(&"read"));
+    let mut sorted = names.clone();
+    sorted.sort_unstable();
+    assert_eq!(names, sorted);
+    assert!(err.contains("help: no builtin named `nosuch`\n"));
+}
+
+#[test]
+fn messages_are_plain_in_a_pipe() {
+    let (_, err, _) = run_err("cd a b c\ncd /nosuch\nbreak\n");
+    assert!(err.contains("tish: cd: usage: cd [dir]\n"));
+    assert!(!err.contains('\x1b'));
+}
+
+#[test]
+fn aliases_expand_once() {
+    let (out, _) = run("a { echo ran a }\n\
+         alias a = b\n\
+         alias b = a\n\
+         a\n\
+         alias c = echo via c\n\
+         alias d = c\n\
+         d hi\n");
+    assert_eq!(out, "ran a\nvia c hi\n");
+}
+
+#[test]
+fn unalias_all() {
+    let (out, _) = run("alias a = echo a\n\
+         alias b = echo b\n\
+         unalias -a\n\
+         alias\n\
+         alias --json\n");
+    assert_eq!(out, "[]\n");
+}
+
+#[test]
+fn true_false_and_colon() {
+    let (out, _) = run("true\n\
+         echo $status\n\
+         false\n\
+         echo $status\n\
+         : $(touch /tmp/tish-colon-$$) ignored\n\
+         echo $status\n\
+         [[ -f /tmp/tish-colon-$$ ]]\n\
+         echo $status\n\
+         : > /tmp/tish-colon-redir-$$\n\
+         [[ -f /tmp/tish-colon-redir-$$ ]]\n\
+         echo $status\n\
+         rm /tmp/tish-colon-$$ /tmp/tish-colon-redir-$$\n");
+    assert_eq!(out, "0\n1\n0\n0\n0\n");
+}
+
+#[test]
+fn true_and_false_need_no_path() {
+    let (out, _) = run("let old = $PATH\n\
+         PATH=/nonexistent\n\
+         false\n\
+         let f = $status\n\
+         true\n\
+         let t = $status\n\
+         PATH=$old\n\
+         echo $f $t\n");
+    assert_eq!(out, "1 0\n");
+}
+
+#[test]
+fn wait_for_the_next_job() {
+    let start = std::time::Instant::now();
+    let (out, _) = run("wait -n\n\
+         echo $status\n\
+         sleep 5 &\n\
+         { sleep 0.2; exit 4 } &\n\
+         wait -n\n\
+         echo $status\n\
+         kill %1\n");
+    assert!(start.elapsed() < std::time::Duration::from_secs(3));
+    let lines = out
+        .lines()
+        .filter(|line| !line.starts_with("Job %"))
+        .collect::<Vec<_>>();
+    assert_eq!(lines, ["127", "4"]);
+}
+
+#[test]
+fn read_splits_by_ifs() {
+    let (out, _) = run(
+        "echo \"a::b:c d\" | { IFS=: read w x y z; echo \"[$w][$x][$y][$z]\" }\n\
+         echo \"  one   two  three \" | { read p q; echo \"[$p][$q]\" }\n",
+    );
+    assert_eq!(out, "[a][][b][c d]\n[one][two  three]\n");
+}
+
+#[test]
+fn complete_lists_and_removes() {
+    let (out, err, _) = run_err(
+        "complete -W \"start stop restart\" myservice\n\
+         complete -f -W x cat\n\
+         complete\n\
+         complete -r cat\n\
+         complete -r cat\n\
+         echo $status\n\
+         complete\n",
+    );
+    assert_eq!(
+        out,
+        "complete -W \"x\" -f cat\n\
+         complete -W \"start stop restart\" myservice\n\
+         1\n\
+         complete -W \"start stop restart\" myservice\n"
+    );
+    assert!(err.contains("complete: cat: no completion specification"));
+}
+
+#[test]
+fn declare_round_trips_functions() {
+    let (out, _) = run("f {\n\
+             if [[ $1 == a ]] { echo \"is a\" } else { echo other }\n\
+             for x in 1 2 { echo $x }\n\
+         }\n\
+         g { echo g | cat }\n\
+         declare -F\n\
+         declare -f g\n\
+         declare -f f > /tmp/tish-declare-$$\n\
+         unset -f f\n\
+         source /tmp/tish-declare-$$\n\
+         f a\n\
+         rm /tmp/tish-declare-$$\n");
+    assert_eq!(
+        out,
+        "declare -f f\ndeclare -f g\ng {\n    echo g | cat\n}\nis a\n1\n2\n"
+    );
+}
+
+#[test]
+fn sigint_stops_read() {
+    use nix::sys::signal::{kill, Signal};
+    use nix::unistd::Pid;
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+    use std::time::Duration;
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tish"))
+        .env_remove("HISTFILE")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"read x\n").unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGINT).unwrap();
+    std::thread::sleep(Duration::from_millis(300));
+    stdin.write_all(b"echo \"status $status\"\n").unwrap();
+    drop(stdin);
+
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "status 1\n");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Interrupted\n"));
+    assert!(output.status.success());
+}
+
+#[test]
+fn disown_drops_jobs() {
+    let (out, _) = run("sleep 5 &\n\
+         sleep 0.1 &\n\
+         disown\n\
+         jobs\n\
+         disown -h %1\n\
+         jobs\n\
+         disown %9\n\
+         echo $status\n\
+         kill %1\n");
+    let lines = out
+        .lines()
+        .filter(|line| !line.starts_with("Job %"))
+        .collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("%1 pid: ") && lines[0].ends_with(" sleep 5"));
+    assert_eq!(lines[1], lines[0]);
+    assert_eq!(lines[2], "1");
+}
+
+#[test]
+fn job_specs_pick_jobs() {
+    let (out, _) = run("sleep 5 &\n\
+         sleep 6 &\n\
+         sh -c \"sleep 7\" &\n\
+         jobs %+\n\
+         jobs %%\n\
+         jobs %-\n\
+         jobs %2\n\
+         jobs %sh\n\
+         jobs %?6\n\
+         jobs %sleep\n\
+         echo $status\n\
+         jobs %9\n\
+         echo $status\n\
+         kill %1 %2 %3\n");
+    let lines = out
+        .lines()
+        .filter(|line| !line.starts_with("Job %"))
+        .map(|line| line.split(' ').next().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(lines, ["%3", "%3", "%2", "%2", "%3", "%2", "1", "1"]);
+}
+
+#[test]
+fn dumped_variables_parse_back() {
+    let (dump, _) = run("export Q1 = 'a b $x \"d\" \\n %1'\n\
+         let Q2 = \"x\n\
+         y\"\n\
+         export -p | grep ' Q1 '\n\
+         set | grep ^Q2=\n");
+    assert_eq!(
+        dump,
+        "export Q1 = \"a b \\$x \\\"d\\\" \\\\n %1\"\nQ2=\"x\\ny\"\n"
+    );
+    let (out, _) = run(&format!("{}printf \"[%s]\\n\" $Q1 $Q2\n", dump));
+    assert_eq!(out, "[a b $x \"d\" \\n %1]\n[x\ny]\n");
+}
+
+#[test]
+fn last_background_pid() {
+    let (out, _) = run("sleep 5 &\n\
+         echo \"bang $!\"\n\
+         jobs -p\n\
+         kill %1\n");
+    let lines = out.lines().collect::<Vec<_>>();
+    let pid = lines[0]
+        .strip_prefix("Job %1 (")
+        .and_then(|rest| rest.strip_suffix(") has started."))
+        .unwrap();
+    assert_eq!(lines[1..], [format!("bang {}", pid), pid.to_string()]);
+}
+
+#[test]
+fn disabled_builtins_run_commands() {
+    let (out, err, _) = run_err(
+        "type true\n\
+         enable -n true\n\
+         type true\n\
+         enable | grep true\n\
+         let old = $PATH\n\
+         PATH=/nonexistent\n\
+         true\n\
+         let a = $status\n\
+         enable true\n\
+         true\n\
+         let b = $status\n\
+         PATH=$old\n\
+         echo $a $b\n\
+         enable -n nosuch\n\
+         echo $status\n",
+    );
+    let lines = out.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "true is a shell builtin");
+    assert!(lines[1].starts_with("true is /") && lines[1].ends_with("/true"));
+    assert_eq!(lines[2..], ["enable -n true", "127 0", "1"]);
+    assert!(err.contains("enable: nosuch: not a shell builtin"));
+}
+
+#[test]
+fn builtin_skips_functions() {
+    let (out, err, _) = run_err(
+        "cd { echo \"into $1\"; builtin cd $1 }\n\
+         cd /tmp\n\
+         pwd\n\
+         builtin nosuch\n\
+         echo $status\n",
+    );
+    assert_eq!(out, "into /tmp\n/tmp\n1\n");
+    assert!(err.contains("builtin: nosuch: not a shell builtin"));
+}
+
+#[test]
+fn cd_searches_cdpath() {
+    let dir = std::env::temp_dir().join(format!("tish-cdpath-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    let (out, err, _) = run_err(&format!(
+        "cd /\n\
+         CDPATH=/nonexistent:{}\n\
+         cd sub\n\
+         echo \"$PWD $OLDPWD\"\n\
+         cd ./nosub\n\
+         echo $status\n",
+        dir.display()
+    ));
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let sub = dir.join("sub");
+    assert_eq!(out, format!("{0}\n{0} /\n1\n", sub.display()));
+    assert!(err.contains("cd: ./nosub: No such file or directory"));
+}
+
+#[test]
+fn autocd_enters_directories() {
+    let dir = std::env::temp_dir().join(format!("tish-autocd-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("true")).unwrap();
+    let (out, _) = run(&format!(
+        "set -o autocd\n\
+         {0}\n\
+         echo $PWD\n\
+         true\n\
+         echo $PWD\n\
+         ./true\n\
+         echo $PWD\n\
+         set +o autocd\n\
+         {0}\n\
+         echo $status $PWD\n",
+        dir.display()
+    ));
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(
+        out,
+        format!("{0}\n{0}\n{0}/true\n126 {0}/true\n", dir.display())
+    );
+}
+
+#[test]
+fn mktemp_creates_files_and_directories() {
+    let (out, err, _) = run_err(
+        "let f = $(mktemp)\n\
+         [[ -f $f ]]\n\
+         echo $status\n\
+         rm $f\n\
+         let d = $(mktemp -d)\n\
+         [[ -d $d ]]\n\
+         echo $status\n\
+         rmdir $d\n\
+         export TMPDIR = /tmp/tish-mktemp-$$\n\
+         mkdir $TMPDIR\n\
+         let g = $(mktemp)\n\
+         echo $g\n\
+         rm $g\n\
+         rmdir $TMPDIR\n\
+         mktemp /tmp/x-X\n\
+         echo $status\n",
+    );
+    let lines = out.lines().collect::<Vec<_>>();
+    assert_eq!(lines[..2], ["0", "0"]);
+    assert!(lines[2].starts_with("/tmp/tish-mktemp-") && lines[2].contains("/tmp."));
+    assert_eq!(lines[3], "1");
+    assert!(err.contains("mktemp: /tmp/x-X: too few X's in the template"));
+}
+
+#[test]
+fn readonly_keeps_the_value() {
+    let (out, err, _) = run_err(
+        "readonly R=1\n\
+         echo $status\n\
+         readonly R = 1\n\
+         R=2\n\
+         echo \"$R $status\"\n\
+         let R = 3\n\
+         echo \"$R $status\"\n\
+         unset R\n\
+         echo \"$R $status\"\n\
+         export R = 4\n\
+         echo \"$R $status\"\n\
+         echo q | read R\n\
+         echo \"$R $status\"\n\
+         readonly\n",
+    );
+    assert_eq!(out, "1\n1 1\n1 1\n1 1\n1 1\n1 1\nreadonly R = \"1\"\n");
+    assert!(err.contains("R=1: invalid variable name"));
+    assert_eq!(err.matches("R: readonly variable").count(), 5);
+}
+
+#[test]
+fn times_prints_two_lines() {
+    // Like `0m0.004s`.
+    let is_time = |time: &str| {
+        let (min, sec) = time.strip_suffix('s').unwrap().split_once('m').unwrap();
+        let (whole, frac) = sec.split_once('.').unwrap();
+        min.parse::<u64>().is_ok() && whole.parse::<u8>().unwrap() < 60 && frac.len() == 3
+    };
+    let (out, code) = run("sleep 0.1\ntimes\n");
+    assert_eq!(code, 0);
+    let lines = out.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 2);
+    for line in lines {
+        let (user, sys) = line.split_once(' ').unwrap();
+        assert!(is_time(user) && is_time(sys), "{}", line);
+    }
+}
+
+#[test]
+fn caller_reports_the_calling_line() {
+    let (out, _) = run("f {\n\
+         caller\n\
+         caller 1\n\
+         caller 2\n\
+         echo $status\n\
+         }\n\
+         g { f }\n\
+         \n\
+         g\n\
+         caller\n\
+         echo $status\n\
+         echo $0\n");
+    let lines = out.lines().collect::<Vec<_>>();
+    let script = lines[4];
+    assert_eq!(
+        lines[..4],
+        [
+            format!("7 {}", script),
+            format!("9 g {}", script),
+            String::from("1"),
+            String::from("1"),
+        ]
+    );
+}
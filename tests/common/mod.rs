@@ -0,0 +1,70 @@
+// Each test crate uses only some of the helpers.
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+// Writes `contents` to a new file in the temporary directory.
+pub fn temp_file(contents: &str) -> PathBuf {
+    let n = COUNT.fetch_add(1, Ordering::Relaxed);
+    let path = std::env::temp_dir().join(format!("tish-test-{}-{}", std::process::id(), n));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+// Runs `script` as a file with the shell, and returns its stdout and status.
+pub fn run(script: &str) -> (String, i32) {
+    let (out, _, code) = run_err(script);
+    (out, code)
+}
+
+// Like `run`, with the stderr.
+pub fn run_err(script: &str) -> (String, String, i32) {
+    run_args(&[], script)
+}
+
+// Like `run_err`, passing `args` to the shell before the script.
+pub fn run_args(args: &[&str], script: &str) -> (String, String, i32) {
+    let path = temp_file(script);
+    let output = Command::new(env!("CARGO_BIN_EXE_tish"))
+        .args(args)
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).ok();
+    (
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+        output.status.code().unwrap_or(-1),
+    )
+}
+
+// Runs the shell reading `input` from the stdin without a script, as it
+// does in a session, and returns the stdout, the stderr and the status.
+pub fn run_input(input: &str) -> (String, String, i32) {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tish"))
+        .env_remove("HISTFILE")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    (
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+        output.status.code().unwrap_or(-1),
+    )
+}
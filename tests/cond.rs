@@ -0,0 +1,29 @@
+mod common;
+
+use common::{run, run_err};
+
+#[test]
+fn bad_regex_fails_with_2() {
+    let (out, err, _) = run_err("[[ a =~ ( ]]\necho $status\n");
+    assert_eq!(out, "2\n");
+    assert!(err.contains("tish: (: invalid regex\n"));
+}
+
+#[test]
+fn bad_integer_fails_with_2() {
+    let (out, err, _) = run_err("[[ a -eq 1 ]]\necho $status\n");
+    assert_eq!(out, "2\n");
+    assert!(err.contains("tish: a: integer expression expected\n"));
+}
+
+#[test]
+fn bad_condition_is_false() {
+    let (out, _) = run("if [[ a =~ ( ]] { echo yes } else { echo no }\n");
+    assert_eq!(out, "no\n");
+}
+
+#[test]
+fn regex_sets_the_groups() {
+    let (out, _) = run("[[ ab12 =~ ([a-z]+)([0-9]+) ]]\necho $status ${BASH_REMATCH[2]}\n");
+    assert_eq!(out, "0 12\n");
+}
@@ -0,0 +1,37 @@
+use std::collections::BTreeMap;
+use std::fs;
+use tish::session::{IOReader, Session};
+
+#[test]
+fn base_env_replaces_the_inherited_one() {
+    std::env::set_var("TISH_TEST_HOST", "1");
+    let out = std::env::temp_dir().join(format!("tish-embed-{}", std::process::id()));
+    let script = format!("export EXPORTED = 2\nLOCAL=3 env > {}\n", out.display());
+
+    let mut session = Session::new(IOReader::new(script.as_bytes())).unwrap();
+    let base = vec![("BASE", "1"), ("PATH", "/usr/bin:/bin")]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect::<BTreeMap<_, _>>();
+    session.set_base_env(base);
+    session.all().unwrap();
+
+    let env = fs::read_to_string(&out).unwrap();
+    fs::remove_file(&out).ok();
+    let mut keys = env
+        .lines()
+        .filter_map(|line| line.split('=').next())
+        .collect::<Vec<_>>();
+    keys.sort_unstable();
+    assert_eq!(keys, ["BASE", "EXPORTED", "LOCAL", "PATH", "PWD", "SHLVL"]);
+    assert!(env.contains("BASE=1\n"));
+    assert!(env.contains("EXPORTED=2\n"));
+
+    // The environment of the process itself is left alone.
+    assert_eq!(std::env::var("TISH_TEST_HOST").as_deref(), Ok("1"));
+    assert!(std::env::var("EXPORTED").is_err());
+    assert_eq!(
+        session.env_snapshot().get("EXPORTED").map(String::as_str),
+        Some("2")
+    );
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use common::{run, run_err};
+
+#[test]
+fn glob_without_matches() {
+    let (out, err, _) = run_err(
+        "cd /\n\
+         echo *.nosuch x\n\
+         set -o nullglob\n\
+         echo *.nosuch x\n\
+         set +o nullglob\n\
+         set -o failglob\n\
+         echo *.nosuch x\n\
+         echo $status\n",
+    );
+    assert_eq!(out, "*.nosuch x\nx\n1\n");
+    assert!(err.contains("line 7: No match: *.nosuch\n"));
+}
+
+#[test]
+fn tildes_in_assignments() {
+    let (out, _, _) = run_err(
+        "HOME=/home/u\n\
+         X=~/a:~/b:x~\n\
+         echo $X\n\
+         let Y = ~/c:~d\n\
+         echo $Y\n\
+         echo a:~/b\n",
+    );
+    assert_eq!(out, "/home/u/a:/home/u/b:x~\n/home/u/c:~d\na:~/b\n");
+}
+
+#[test]
+fn case_folding_by_unicode_or_ascii() {
+    let (out, _) = run("let v = \"éa straße\"\n\
+         echo ${v^^} ${v^}\n\
+         set -o asciicase\n\
+         echo ${v^^} ${v^}\n\
+         set +o asciicase\n\
+         let w = \"ÉA\"\n\
+         echo ${w,,}\n");
+    assert_eq!(out, "ÉA STRASSE Éa straße\néA STRAßE éa straße\néa\n");
+}
+
+#[test]
+fn words_expand_alike_everywhere() {
+    let (out, err, _) = run_err(
+        "HOME=/home/u\n\
+         let d = /tmp/tish-words-$$\n\
+         mkdir $d\n\
+         touch $d/a.txt $d/b.txt\n\
+         cd $d\n\
+         echo *.txt ~/x\n\
+         for f in *.txt ~/x { echo \"for $f\" }\n\
+         case *.txt { \"a.txt b.txt\" => echo globbed;; * => echo literal;; }\n\
+         echo x > *.txt\n\
+         echo $status\n\
+         cd /\n\
+         rm -r $d\n",
+    );
+    assert_eq!(
+        out,
+        "a.txt b.txt /home/u/x\nfor a.txt\nfor b.txt\nfor /home/u/x\nliteral\n1\n"
+    );
+    assert!(err.contains("*.txt: ambiguous redirect"));
+}
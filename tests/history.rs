@@ -0,0 +1,132 @@
+mod common;
+
+use common::run_input;
+
+#[test]
+fn bang_bang_runs_the_last_command() {
+    let (out, err, _) = run_input("echo hi\n!!\n!ec there\n");
+    assert_eq!(out, "hi\nhi\nhi there\n");
+    assert!(err.contains("echo hi\n"));
+    assert!(err.contains("echo hi there\n"));
+}
+
+#[test]
+fn quotes_keep_bangs() {
+    let (out, _, _) = run_input("echo hi\necho '!!' \"!!\"\n");
+    assert_eq!(out, "hi\n!! !!\n");
+}
+
+#[test]
+fn unknown_event_is_skipped() {
+    let (out, err, _) = run_input("!nosuch\necho after\n");
+    assert_eq!(out, "after\n");
+    assert!(err.contains("!nosuch: event not found"));
+}
+
+#[test]
+fn ignore_dups_and_spaces() {
+    let (out, _, _) = run_input("HISTCONTROL=ignoreboth\necho a\necho a\n echo b\nfc -l\n");
+    assert_eq!(out, "a\na\nb\n1\tHISTCONTROL=ignoreboth\n2\techo a\n");
+}
+
+#[test]
+fn size_drops_the_oldest() {
+    let (out, _, _) = run_input("echo a\nHISTSIZE=2\necho b\nfc -l\n");
+    assert_eq!(out, "a\nb\n1\techo b\n");
+}
+
+#[test]
+fn fc_lists_and_reruns() {
+    let (out, _, _) =
+        run_input("echo a\necho b\necho c\nfc -l 2\nfc -ln 1 2\nfc -lr -3 -2\nfc -e true -4\n");
+    assert_eq!(
+        out,
+        "a\nb\nc\n2\techo b\n3\techo c\n\techo a\n\techo b\n4\tfc -l 2\n3\techo c\nc\n"
+    );
+}
+
+#[test]
+fn fc_uses_tmpdir() {
+    let dir = std::env::temp_dir().join(format!("tish-fc-{}", std::process::id()));
+    std::fs::create_dir(&dir).unwrap();
+    let (out, _, _) = run_input(&format!(
+        "export TMPDIR = {0}\necho a\nfc -e ls -1\nls {0} | wc -l\n",
+        dir.display()
+    ));
+    std::fs::remove_dir(&dir).unwrap();
+
+    let lines = out.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[1].starts_with(&format!("{}/tish-fc-", dir.display())));
+    assert_eq!([lines[0], lines[2], lines[3]], ["a", "a", "0"]);
+}
+
+#[test]
+fn prompt_command_runs_before_each_prompt() {
+    let (out, err, _) = run_input(
+        "let n = 0\n\
+         PROMPT_COMMAND='let n = $(expr $n + 1)'\n\
+         echo a\n\
+         false\n\
+         echo $status $n\n\
+         PROMPT_COMMAND=nosuch_prompt_command\n\
+         echo still\n",
+    );
+    assert_eq!(out, "a\n1 3\nstill\n");
+    assert!(err.contains("tish: nosuch_prompt_command: command not found"));
+}
+
+#[test]
+fn invalid_utf8_line_is_skipped() {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tish"))
+        .env_remove("HISTFILE")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"echo a\necho \xff b\necho c\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "a\nc\n");
+    let err = String::from_utf8(output.stderr).unwrap();
+    assert!(err.contains("Invalid UTF-8 in the input, the line is skipped."));
+}
+
+#[test]
+fn notify_reports_jobs_at_once() {
+    let order = |opt| {
+        let (_, err, _) = run_input(&format!(
+            "set {}\nsleep 0.2 & sleep 0.8; echo marker >&2\n",
+            opt
+        ));
+        err.lines()
+            .filter_map(|line| match line {
+                "marker" => Some("marker"),
+                _ if line.starts_with("[Background process %1 ") => Some("notice"),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(order("+o notify"), ["marker", "notice"]);
+    assert_eq!(order("-o notify"), ["notice", "marker"]);
+    assert_eq!(order("-b"), ["notice", "marker"]);
+}
+
+#[test]
+fn ignoreeof_needs_more_eofs() {
+    let eofs = |input| {
+        let (_, err, _) = run_input(input);
+        err.matches("Use \"exit\" to leave the shell.").count()
+    };
+    assert_eq!(eofs("echo hi\n"), 0);
+    assert_eq!(eofs("set -o ignoreeof\n"), 10);
+    assert_eq!(eofs("set -o ignoreeof\nIGNOREEOF=2\n"), 2);
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use common::run;
+
+#[test]
+fn function_stage_ends_with_the_reader() {
+    let (out, code) = run("gen { while true { echo y } }\ngen | head -n 1\necho $status\n");
+    assert_eq!(out, "y\n0\n");
+    assert_eq!(code, 0);
+}
+
+#[test]
+fn function_stages_stream_in_the_middle() {
+    let (out, _) = run("f { echo a; echo b; echo c }\n\
+         f | grep b\n\
+         echo x | f | wc -l\n");
+    assert_eq!(out.split_whitespace().collect::<Vec<_>>(), ["b", "3"]);
+}
+
+#[test]
+fn compound_stage_ends_with_the_reader() {
+    let (out, _) = run("while true { echo y } | head -n 1\necho done\n");
+    assert_eq!(out, "y\ndone\n");
+}
+
+#[test]
+fn compound_stage_sees_the_namespace() {
+    let (out, _) = run("let pre = got\n\
+         for i in a b { echo $pre $i } | while read l { echo [$l] }\n");
+    assert_eq!(out, "[got a]\n[got b]\n");
+}
+
+#[test]
+fn simple_stages_run_in_subshells() {
+    let (out, _) = run("cd /\n\
+         printf 'hi\\n' | read x; echo \"[$x] $status\"\n\
+         echo a | y=1; echo \"[$y]\"\n\
+         f { read line; echo \"f got $line\"; cd /tmp }\n\
+         echo z | f; pwd\n\
+         echo tmp | cd /tmp; pwd\n\
+         echo q | false; echo $status\n");
+    assert_eq!(out, "[] 0\n[]\nf got z\n/\n/\n1\n");
+}
+
+#[test]
+fn compound_stages_run_in_subshells() {
+    let (out, _) = run("cd /\n\
+         echo hi | { read z; echo \"in [$z]\"; cd /tmp }; echo \"[$z]\"; pwd\n\
+         printf 'a\\nb\\n' | while read w { let n = $w }; echo \"[$n]\"\n\
+         echo hi | if true { read v }; echo \"[$v]\"\n\
+         { echo a; echo b } | { read u; read u }; echo \"[$u]\"\n");
+    assert_eq!(out, "in [hi]\n[]\n/\n[]\n[]\n[]\n");
+}
+
+#[test]
+fn pipefail_decides_and_or() {
+    let (out, _) = run("false | true && echo x1\n\
+         set -o pipefail\n\
+         false | true && echo x2\n\
+         false | true || echo y2\n\
+         true | true && echo z2\n");
+    assert_eq!(out, "x1\ny2\nz2\n");
+}
+
+#[test]
+fn pipestatus_keeps_each_stage() {
+    let (out, _) = run("false | true\n\
+         echo ${PIPESTATUS[@]}\n\
+         true | false | true\n\
+         echo ${PIPESTATUS[@]} ${#PIPESTATUS[@]}\n\
+         sh -c \"exit 3\" | true\n\
+         echo ${PIPESTATUS[0]} ${PIPESTATUS[1]}\n\
+         false\n\
+         echo ${PIPESTATUS[@]}\n");
+    assert_eq!(out, "1 0\n0 1 0 3\n3 0\n1\n");
+}
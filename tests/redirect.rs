@@ -0,0 +1,116 @@
+mod common;
+
+use common::{run, run_err};
+
+#[test]
+fn failed_redirect_skips_the_command() {
+    let (out, err, _) = run_err("echo body > nodir/out\necho $status\n");
+    assert_eq!(out, "1\n");
+    assert!(err.contains("tish: nodir/out: No such file or directory"));
+}
+
+#[test]
+fn failed_redirect_skips_a_function() {
+    let (out, _) = run("f { echo body }\nf > nodir/out\necho $status\n");
+    assert_eq!(out, "1\n");
+}
+
+#[test]
+fn targets_are_expanded() {
+    let (out, err, _) = run_err(
+        "let d = /tmp/tish-redirect-$$\n\
+         mkdir $d\n\
+         let f = $d/out\n\
+         echo a > $f;\n\
+         cat $f\n\
+         HOME=$d\n\
+         echo b > ~/x\n\
+         cat $d/x\n\
+         echo c > $d/*\n\
+         echo $status\n\
+         rm -r $d\n",
+    );
+    assert_eq!(out, "a\nb\n1\n");
+    assert!(err.contains("/*: ambiguous redirect\n"));
+}
+
+#[test]
+fn redirects_in_one_command() {
+    let (out, _) = run("let d = /tmp/tish-redirects-$$\n\
+         mkdir $d\n\
+         cd $d\n\
+         echo input > in\n\
+         cat < in > out 2> err\n\
+         echo a > f b\n\
+         ls nosuch > o 2> e in\n\
+         cat out err f o\n\
+         [[ -s e ]]\n\
+         echo $status\n\
+         cd /\n\
+         rm -r $d\n");
+    assert_eq!(out, "input\na b\nin\n0\n");
+}
+
+#[test]
+fn redirects_apply_in_order() {
+    let (out, _) = run("let f = /tmp/tish-order-$$\n\
+         ls nosuch > $f 2>&1\n\
+         [[ -s $f ]]\n\
+         echo $status\n\
+         ls nosuch 2>&1 > $f | wc -l\n\
+         [[ -s $f ]]\n\
+         echo $status\n\
+         rm $f\n");
+    assert_eq!(out, "0\n1\n1\n");
+}
+
+#[test]
+fn noclobber() {
+    let (out, err, _) = run_err(
+        "let f = /tmp/tish-noclobber-$$\n\
+         echo old > $f\n\
+         set -C\n\
+         echo new > $f\n\
+         echo $status\n\
+         cat $f\n\
+         echo forced >| $f\n\
+         echo more >> $f\n\
+         cat $f\n\
+         set +C\n\
+         echo over > $f\n\
+         cat $f\n\
+         rm $f\n",
+    );
+    assert_eq!(out, "1\nold\nforced\nmore\nover\n");
+    assert!(err.contains(": cannot overwrite existing file\n"));
+}
+
+#[test]
+fn large_here_string() {
+    let start = std::time::Instant::now();
+    let (out, _) = run("let big = $(seq 1000000)\n\
+         head -c 10 << $big\n\
+         echo\n\
+         echo $status\n\
+         cat << $big | wc -c\n");
+    assert!(start.elapsed() < std::time::Duration::from_secs(10));
+    assert_eq!(out, "1\n2\n3\n4\n5\n\n0\n6888895\n");
+}
+
+#[test]
+fn umask_restricts_created_files() {
+    let (out, err, _) = run_err(
+        "umask 077\n\
+         umask\n\
+         echo x > /tmp/tish-umask-$$\n\
+         stat -c %a /tmp/tish-umask-$$\n\
+         rm /tmp/tish-umask-$$\n\
+         umask u=rwx,g=rx,o=\n\
+         umask\n\
+         umask -S\n\
+         umask 9\n\
+         echo $status\n",
+    );
+    assert_eq!(out, "0077\n600\n0027\nu=rwx,g=rx,o=\n1\n");
+    assert!(err.contains("9: invalid symbolic mode"));
+}
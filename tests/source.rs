@@ -0,0 +1,72 @@
+mod common;
+
+use common::{run_err, temp_file};
+
+// Writes `lib` to a file to be sourced as `$lib` by `script`.
+fn with_lib(lib: &str, script: &str) -> (String, String, i32) {
+    let path = temp_file(lib);
+    let res = run_err(&format!("let lib = {}\n{}", path.display(), script));
+    std::fs::remove_file(&path).ok();
+    res
+}
+
+#[test]
+fn error_in_a_block_reports_its_line() {
+    let (out, err, _) = with_lib(
+        "set -u\n\
+         while true {\n\
+             echo in\n\
+             echo $nosuch\n\
+             break\n\
+         }\n",
+        "source $lib\n",
+    );
+    assert_eq!(out, "in\n");
+    assert!(err.contains("line 4 (in the block from line 2): nosuch: unbound variable\n"));
+}
+
+#[test]
+fn crlf_runs_like_lf() {
+    let script = "let x = \"a\rb\"\n\
+                  if true {\n\
+                      echo one\n\
+                  }\n\
+                  for i in 1 2 {\n\
+                      echo $i\n\
+                  }\n\
+                  printf %s $x | od -c\n";
+    let (lf, _, _) = with_lib(script, "source $lib\n");
+    let (crlf, _, _) = with_lib(&script.replace('\n', "\r\n"), "source $lib\n");
+    assert!(lf.starts_with("one\n1\n2\n0000000   a  \\r   b"));
+    assert_eq!(crlf, lf);
+}
+
+#[test]
+fn stray_break_in_a_sourced_file() {
+    let (out, err, _) = with_lib(
+        "echo before\n\
+         break\n\
+         echo \"after $status\"\n",
+        "source $lib\n\
+         for i in 1 2 {\n\
+             echo \"loop $i\"\n\
+             source $lib\n\
+         }\n\
+         echo end\n\
+         source $lib\n\
+         echo \"source $status\"\n",
+    );
+    assert_eq!(
+        out,
+        "before\nafter 1\nloop 1\nbefore\nend\nbefore\nafter 1\nsource 1\n"
+    );
+    assert!(err.contains("line 2: break: only meaningful in a loop\n"));
+}
+
+#[test]
+fn source_returns_the_last_status() {
+    let (out, _, _) = with_lib("false\n", "source $lib\necho $status\n");
+    assert_eq!(out, "1\n");
+    let (out, _, _) = with_lib("false\ntrue\n", "false\nsource $lib\necho $status\n");
+    assert_eq!(out, "0\n");
+}
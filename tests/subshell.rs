@@ -0,0 +1,110 @@
+mod common;
+
+use common::{run, run_err};
+
+// The lines of the output without the notices of jobs.
+fn output(script: &str) -> Vec<String> {
+    run(script)
+        .0
+        .lines()
+        .filter(|line| !line.starts_with("Job %"))
+        .map(String::from)
+        .collect()
+}
+
+#[test]
+fn background_block_sees_the_namespace() {
+    let lines = output(
+        "let x = 1\n\
+         f { echo f $1 }\n\
+         let pid = $$\n\
+         { echo $pid $$; echo $x; f arg; echo $LINENO } &\n\
+         wait\n",
+    );
+    let (pid, own) = lines[0].split_once(' ').unwrap();
+    assert_eq!(pid, own);
+    assert_eq!(lines[1..], ["1", "f arg", "4"]);
+}
+
+#[test]
+fn coprocess_sees_the_namespace() {
+    let lines = output(
+        "let pre = got\n\
+         coproc C { while read line { echo $pre $line } }\n\
+         echo hi > /dev/fd/${C[1]}\n\
+         read reply < /dev/fd/${C[0]}\n\
+         echo $reply\n",
+    );
+    assert_eq!(lines, ["got hi"]);
+}
+
+#[test]
+fn substitution_sets_the_status() {
+    let (out, code) = run("x=$(false); echo $status;\n\
+         x=$(true); echo $status\n\
+         y=(exit 3); echo $status\n\
+         f { echo in f; exit 4 }\n\
+         z=(f); echo \"$z $status\"\n\
+         echo end\n");
+    assert_eq!(out, "1\n0\n3\nin f 4\nend\n");
+    assert_eq!(code, 0);
+}
+
+#[test]
+fn exit_ends_only_a_subshell() {
+    let (out, code) = run("echo q | { exit 8 }\n\
+         echo $status\n\
+         exit 7\n\
+         echo never\n");
+    assert_eq!(out, "8\n");
+    assert_eq!(code, 7);
+}
+
+#[test]
+fn output_limit_stops_a_substitution() {
+    let (out, err, _) = run_err(
+        "set -L 1000\n\
+         let x = $(seq 100000)\n\
+         echo $status\n\
+         let y = $(seq 3)\n\
+         echo $y\n\
+         set +L\n\
+         let z = $(seq 100000 | wc -l)\n\
+         echo $z\n",
+    );
+    assert_eq!(out, "1\n1\n2\n3\n100000\n");
+    assert!(err.contains("The output exceeded the limit (1000 bytes)."));
+}
+
+#[test]
+fn substitution_of_a_pipeline() {
+    let (out, _) = run("n=$(seq 5 | wc -l); echo $n $status\n\
+         x=$(seq 10 | grep 1 | wc -l); echo $x\n\
+         x=$(false | true); echo $status\n\
+         x=$(true | false); echo $status\n\
+         set -o pipefail\n\
+         x=$(false | true); echo $status\n");
+    assert_eq!(out, "5 0\n2\n0\n1\n1\n");
+}
+
+#[test]
+fn substitution_captures_only_stdout() {
+    let (out, err, _) = run_err(
+        "x=$(sh -c \"echo out; echo err >&2; exit 2\")\n\
+         echo \"[$x] $status\"\n",
+    );
+    assert_eq!(out, "[out] 2\n");
+    assert!(err.contains("err\n"));
+}
+
+#[test]
+fn braces_run_in_the_current_shell() {
+    let (out, code) = run("{ x=1 }; echo $x\n\
+         { { y=2; let z = 3 } }; echo $y $z\n\
+         cd /; { cd /tmp }; pwd\n\
+         (let x = 5); (cd /usr); echo $x $(pwd)\n\
+         f { w=4 }; f; echo \"[$w]\"\n\
+         { exit 3 }; echo never\n");
+    assert_eq!(out, "1\n2 3\n/tmp\n1 /tmp\n[]\n");
+    assert_eq!(code, 3);
+}
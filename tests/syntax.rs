@@ -0,0 +1,317 @@
+mod common;
+
+use common::{run, run_args, run_err};
+
+#[test]
+fn case_stops_at_the_matching_pattern() {
+    let (out, _) = run("let f = /tmp/tish-case-$$\n\
+         case a {\n\
+             a | (touch $f) => echo matched;;\n\
+         }\n\
+         [[ -e $f ]]\n\
+         echo $status\n\
+         case b {\n\
+             a | (touch $f) => echo matched;;\n\
+         }\n\
+         [[ -e $f ]]\n\
+         echo $status\n\
+         rm $f\n");
+    assert_eq!(out, "matched\n1\n0\n");
+}
+
+#[test]
+fn case_falls_through() {
+    let (out, _) = run("case a {\n\
+             a => echo one;&\n\
+             b => echo two;;\n\
+             c => echo three;;\n\
+         }\n");
+    assert_eq!(out, "one\ntwo\n");
+}
+
+#[test]
+fn case_keeps_matching() {
+    let (out, _) = run("case ab {\n\
+             a* => echo first;;&\n\
+             x* => echo no;;\n\
+             *b => echo second;;\n\
+             * => echo last;;\n\
+         }\n");
+    assert_eq!(out, "first\nsecond\n");
+}
+
+#[test]
+fn loop_limit_aborts_a_loop() {
+    let (out, err, _) = run_err(
+        "set -l 5\n\
+         while true { true }\n\
+         echo $status\n\
+         for i in 1 2 3 4 5 6 { true }\n\
+         echo $status\n\
+         set +l\n\
+         for i in 1 2 3 4 5 6 { true }\n\
+         echo $status\n",
+    );
+    assert_eq!(out, "1\n1\n0\n");
+    assert!(err.contains("line 2: Loop limit (5) exceeded.\n"));
+    assert!(err.contains("line 4: Loop limit (5) exceeded.\n"));
+}
+
+#[test]
+fn time_reports_the_real_time() {
+    let (out, err, _) = run_err("time -p sleep 0.1\necho $status\ntime false\necho $status\n");
+    assert_eq!(out, "0\n1\n");
+    let real = err
+        .lines()
+        .find_map(|line| line.strip_prefix("real "))
+        .unwrap()
+        .parse::<f64>()
+        .unwrap();
+    assert!((0.1..1.0).contains(&real), "{}", real);
+    assert!(err.contains("\nreal\t0m0.0"));
+}
+
+#[test]
+fn semicolons_after_blocks() {
+    let (out, _) = run("if true { echo a } ; echo b\n\
+         while false { echo never };echo c\n\
+         case a { a => echo d;; };\n\
+         for i in e { echo $i };\n\
+         echo f\n");
+    assert_eq!(out, "a\nb\nc\nd\ne\nf\n");
+}
+
+#[test]
+fn while_read_loops_over_lines() {
+    let (out, _) = run(
+        "printf 'one\\ntwo\\nthree\\n' | while read line { echo \"[$line]\" }\n\
+         printf 'a b\\nc d\\n' | while read x y { echo $y $x }\n",
+    );
+    assert_eq!(out, "[one]\n[two]\n[three]\nb a\nd c\n");
+}
+
+#[test]
+fn braced_conditions() {
+    let (out, _) = run("let n = x\n\
+         while { test ${#n} -lt 3 } { echo $n; let n = \"${n}x\" }\n\
+         printf 'a\\nb\\n' | while { read line } { echo \"[$line]\" }\n\
+         if { false } { echo yes } else { echo no }\n\
+         if { false; true } { echo yes } else { echo no }\n");
+    assert_eq!(out, "x\nxx\n[a]\n[b]\nno\nyes\n");
+}
+
+#[test]
+fn functions_as_conditions() {
+    let (out, _) = run("f { false }\n\
+         true; if f { echo wrong } else { echo right }\n\
+         let n = x\n\
+         short { test ${#n} -lt 3 }\n\
+         while short { echo $n; let n = \"${n}x\" }\n\
+         myreader { read $1 }\n\
+         let i = ''\n\
+         printf 'a\\nb\\n' | while myreader line { let i = \"${i}i\"; echo $i }\n");
+    assert_eq!(out, "right\nx\nxx\ni\nii\n");
+}
+
+#[test]
+fn parse_error_stops_a_script() {
+    let (out, err, code) = run_err("echo before\necho )(\necho after\n");
+    assert_eq!(out, "before\n");
+    assert!(err.contains("Parse Error: "));
+    assert_eq!(code, 1);
+}
+
+#[test]
+fn parse_error_is_skipped() {
+    let (out, err, code) = run_err("set -o parseskip\necho before\necho )(\necho after\n");
+    assert_eq!(out, "before\nafter\n");
+    assert!(err.contains("Parse Error: "));
+    assert_eq!(code, 0);
+
+    let (out, _, _) = run_args(&["--parse-skip"], "echo before\necho )(\necho after\n");
+    assert_eq!(out, "before\nafter\n");
+}
+
+#[test]
+fn for_takes_words() {
+    let (out, _) = run("for x in a b c { echo $x; }\n\
+         for x in \"a b\" c { echo \"[$x]\" }\n\
+         let l = \"1 2\"\n\
+         for x in $l { echo \"<$x>\" }\n\
+         for x in !$l { echo \"{$x}\" }\n");
+    assert_eq!(out, "a\nb\nc\n[a b]\n[c]\n<1 2>\n{1}\n{2}\n");
+}
+
+#[test]
+fn dump_ast() {
+    let (out, err, _) = run_args(&["--dump-ast"], "if true { echo a } else { echo b }\n");
+    assert_eq!(
+        out,
+        "If: true\n  then: Multi\n    line 1: Single: echo a\n  else: Multi\n    line 1: Single: echo b\n"
+    );
+    assert_eq!(err, "");
+    // The tree is printed only when it is asked for.
+    let (out, err, _) = run_err("if true { echo a } else { echo b }\n");
+    assert_eq!((out.as_str(), err.as_str()), ("a\n", ""));
+}
+
+#[test]
+fn comments_in_blocks() {
+    let (out, _) = run("# top\n\
+         \n\
+         if true {\n\
+             # inside\n\
+             echo a\n\
+         \n\
+             # again\n\
+             echo b # trailing\n\
+         }\n\
+         case x {\n\
+             # before an arm\n\
+             x => echo c;;\n\
+         \n\
+             # between arms\n\
+             y => echo d;;\n\
+         }\n\
+         while false {\n\
+             # only a comment\n\
+         }\n\
+         echo e\n");
+    assert_eq!(out, "a\nb\nc\ne\n");
+}
+
+#[test]
+fn ampersand_separates_commands() {
+    let start = std::time::Instant::now();
+    let (out, _) = run("sleep 5 & echo done\njobs\nkill %1\n");
+    assert!(start.elapsed() < std::time::Duration::from_secs(3));
+
+    let lines = out.lines().collect::<Vec<_>>();
+    assert_eq!(lines[1], "done");
+    assert!(lines[2].starts_with("%1 pid: ") && lines[2].ends_with(" sleep 5"));
+}
+
+#[test]
+fn case_expands_substitutions_lazily() {
+    let (out, _) = run("let p = web\n\
+         case $(echo webserver) {\n\
+             db* => echo db;;\n\
+             $(echo $p)* => echo web;;\n\
+             $(touch /tmp/tish-lazy-$$) => echo never;;\n\
+         }\n\
+         [[ -e /tmp/tish-lazy-$$ ]]\n\
+         echo $status\n");
+    assert_eq!(out, "web\n1\n");
+}
+
+#[test]
+fn select_reads_choices() {
+    let (out, err, _) = run_err(
+        "printf \"2\\n9\\n3\\n\" | {\n\
+             select x in apple banana cherry {\n\
+                 echo \"got $x [$REPLY]\"\n\
+                 if [[ $REPLY == 3 ]] { break }\n\
+             }\n\
+             echo done $x\n\
+             select y in a b { echo never }\n\
+             echo \"eof [$y]\"\n\
+         }\n",
+    );
+    assert_eq!(
+        out,
+        "got banana [2]\ngot  [9]\ngot cherry [3]\ndone cherry\neof []\n"
+    );
+    assert!(err.contains("1) apple\n2) banana\n3) cherry\n#? "));
+}
+
+#[test]
+fn comments_after_headers() {
+    let (out, _) = run("if true { # after if\n\
+             echo a\n\
+         } else { # after else\n\
+             echo b\n\
+         }\n\
+         if true # before the brace\n\
+         {\n\
+             echo c\n\
+         }\n\
+         while false { # while\n\
+         }\n\
+         for i in 1 2 { # for\n\
+             echo $i\n\
+         }\n\
+         case x { # case\n\
+             x => # arm\n\
+                 echo d;;\n\
+         }\n\
+         f { # function\n\
+             echo e\n\
+         }\n\
+         f\n");
+    assert_eq!(out, "a\nc\n1\n2\nd\ne\n");
+}
+
+#[test]
+fn empty_blocks() {
+    let (out, _) = run("if true { }\n\
+         if false { echo x } else { }\n\
+         while false { }\n\
+         for i in 1 2 { }\n\
+         f { }\n\
+         f; { }\n\
+         if true {\n\
+             # only a comment\n\
+         }\n\
+         echo done\n");
+    assert_eq!(out, "done\n");
+}
+
+#[test]
+fn path_skips_what_cannot_run() {
+    let (out, err, _) = run_err(
+        "let d = /tmp/tish-path-$$\n\
+         mkdir -p $d/a $d/b $d/c/mycmd\n\
+         echo \"echo wrong\" > $d/a/mycmd\n\
+         printf \"#!/bin/sh\\necho right\\n\" > $d/b/mycmd\n\
+         chmod +x $d/b/mycmd\n\
+         let old = $PATH\n\
+         PATH=$d/c:$d/a:$d/b:$old\n\
+         mycmd\n\
+         PATH=$d/a:$old\n\
+         mycmd\n\
+         echo $status\n\
+         PATH=$old\n\
+         rm -r $d\n\
+         nosuchcmd_x\n\
+         echo $status\n\
+         ./nosuchcmd_x\n\
+         echo $status\n\
+         nosuchcmd_x | echo piped\n\
+         echo ${PIPESTATUS[@]}\n",
+    );
+    assert_eq!(out, "right\n126\n127\n127\npiped\n127 0\n");
+    assert!(err.contains("tish: mycmd: Permission denied\n"));
+    assert!(err.contains("tish: nosuchcmd_x: command not found\n"));
+    assert!(err.contains("tish: ./nosuchcmd_x: No such file or directory\n"));
+}
+
+#[test]
+fn invalid_utf8_is_replaced() {
+    use std::process::Command;
+
+    let path = std::env::temp_dir().join(format!("tish-utf8-{}", std::process::id()));
+    std::fs::write(&path, b"echo a\necho \xff\xfe b\necho c\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_tish"))
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).ok();
+
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "a\n\u{fffd}\u{fffd} b\nc\n"
+    );
+    let err = String::from_utf8(output.stderr).unwrap();
+    assert!(err.contains("line 2: invalid UTF-8 at byte 6, replaced with U+FFFD.\n"));
+    assert!(output.status.success());
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use common::run_err;
+
+// The lines traced by `set -x`, leaving out the rest of the stderr.
+fn traced(script: &str) -> Vec<String> {
+    let (_, err, _) = run_err(script);
+    err.lines()
+        .filter(|line| line.starts_with('+'))
+        .map(|line| {
+            // Drop the path of the script from `+($0:$LINENO) `.
+            match (line.find('('), line.find(':')) {
+                (Some(start), Some(end)) if start < end => {
+                    format!("{}{}", &line[..start + 1], &line[end + 1..])
+                }
+                _ => line.to_string(),
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn nested_loops() {
+    let script = "set -x
+for i in 1 2 {
+    for c in a b {
+        if [[ $i == 1 && $c == b ]] {
+            true
+        }
+    }
+}
+";
+    assert_eq!(
+        traced(script),
+        [
+            "+(2) for i in 1 2",
+            "+(3) for c in a b",
+            "+(4) [[ 1 == 1 && a == b ]]",
+            "+(3) for c in a b",
+            "+(4) [[ 1 == 1 && b == b ]]",
+            "+(5) true",
+            "+(2) for i in 1 2",
+            "+(3) for c in a b",
+            "+(4) [[ 2 == 1 && ${c} == b ]]",
+            "+(3) for c in a b",
+            "+(4) [[ 2 == 1 && ${c} == b ]]",
+        ]
+    );
+}
+
+#[test]
+fn case_and_functions() {
+    let script = "set -x
+f { true }
+case a {
+    a => f;;
+}
+";
+    assert_eq!(traced(script), ["+(3) case a in", "+(3) f", "++(2) true"]);
+}
+
+#[test]
+fn custom_prompt() {
+    let (_, err, _) = run_err("PS4='> '\nset -x\nx=1\ntrue $x\n");
+    let traced = err
+        .lines()
+        .filter(|line| line.starts_with('>'))
+        .collect::<Vec<_>>();
+    assert_eq!(traced, ["> x=1", "> true 1"]);
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use common::{run, run_err, temp_file};
+use std::process::Command;
+
+#[test]
+fn pid_is_the_shell() {
+    let (out, _) = run("sh -c 'echo $PPID' > /tmp/tish-ppid-$$\n\
+         [[ $(cat /tmp/tish-ppid-$$) == $$ ]]\n\
+         echo $status\n\
+         rm /tmp/tish-ppid-$$\n");
+    assert_eq!(out, "0\n");
+}
+
+#[test]
+fn shlvl_increments() {
+    let path = temp_file("echo $SHLVL\nsh -c 'echo $SHLVL'\n");
+    let output = Command::new(env!("CARGO_BIN_EXE_tish"))
+        .arg(&path)
+        .env("SHLVL", "5")
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).ok();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "6\n6\n");
+}
+
+#[test]
+fn pwd_follows_cd() {
+    let (out, _) = run("cd /\ncd /tmp\necho $PWD $OLDPWD\nsh -c 'echo $PWD'\n");
+    assert_eq!(out, "/tmp /\n/tmp\n");
+}
+
+#[test]
+fn only_exports_reach_commands() {
+    let (out, _) = run("let L = local\n\
+         export E = exported\n\
+         printenv L\n\
+         echo $status\n\
+         printenv E\n");
+    assert_eq!(out, "1\nexported\n");
+}
+
+#[test]
+fn funcname_lineno_and_zero() {
+    let (out, _) = run("echo \"[$FUNCNAME]\"\n\
+         f { echo $FUNCNAME $LINENO; g }\n\
+         g { echo $FUNCNAME }\n\
+         f\n\
+         echo $LINENO\n\
+         echo $0\n");
+    let lines = out.lines().collect::<Vec<_>>();
+    assert_eq!(lines[..4], ["[]", "f 2", "g", "5"]);
+    let dir = std::env::temp_dir();
+    assert!(lines[4].starts_with(&*dir.join("tish-test-").to_string_lossy()));
+}
+
+#[test]
+fn unset_is_empty_unless_nounset() {
+    let (out, err, _) = run_err(
+        "echo \"[$nosuch]\"\n\
+         set -u\n\
+         echo \"[$nosuch]\"\n\
+         echo $status\n\
+         set +u\n\
+         echo \"[$nosuch]\"\n",
+    );
+    assert_eq!(out, "[]\n1\n[]\n");
+    assert!(err.contains("line 3: nosuch: unbound variable\n"));
+}
+
+#[test]
+fn random_and_seconds() {
+    let (out, _) = run("echo $RANDOM $RANDOM $RANDOM\n\
+         RANDOM=42\n\
+         let a = \"$RANDOM $RANDOM\"\n\
+         RANDOM=42\n\
+         echo $a\n\
+         echo $RANDOM $RANDOM\n\
+         echo $SECONDS\n\
+         sleep 1.1\n\
+         echo $SECONDS\n\
+         SECONDS=100\n\
+         echo $SECONDS\n");
+    let lines = out.lines().collect::<Vec<_>>();
+    let random = lines[0]
+        .split(' ')
+        .map(|n| n.parse::<u16>().unwrap())
+        .collect::<Vec<_>>();
+    assert!(random.iter().all(|n| *n <= 32767));
+    assert!(random[0] != random[1] || random[1] != random[2]);
+    assert_eq!(lines[1], lines[2]);
+    assert_eq!(lines[3..], ["0", "1", "100"]);
+}
+
+#[test]
+fn control_flow_keeps_assignments() {
+    let (out, _) = run("let n = 0\n\
+         for i in 1 2 3 {\n\
+             let n = $(expr $n + $i)\n\
+             let last = $i\n\
+         }\n\
+         echo $n $last\n\
+         if true { let a = 1 }\n\
+         if true { let b = 2; let c = 3 }\n\
+         echo $a $b $c\n\
+         case x { x => let d = 4;; }\n\
+         echo $d\n\
+         let w = 0\n\
+         while [[ $w != 2 ]] { let w = $(expr $w + 1) }\n\
+         echo $w\n");
+    assert_eq!(out, "6 3\n1 2 3\n4\n2\n");
+}
+
+#[test]
+fn function_status_outlives_its_scope() {
+    let (out, _) = run("f { false }\n\
+         f\n\
+         echo $status\n\
+         g { let local = 1; true }\n\
+         g\n\
+         echo $status\n");
+    assert_eq!(out, "1\n0\n");
+}